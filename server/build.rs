@@ -0,0 +1,4 @@
+fn main() {
+    // Migrations are embedded at compile time, so edits to them must trigger a rebuild
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,6 @@
+DROP TABLE reaction_users;
+DROP TABLE reactions;
+DROP TABLE messages;
+DROP TABLE channel_members;
+DROP TABLE channels;
+DROP TABLE users;
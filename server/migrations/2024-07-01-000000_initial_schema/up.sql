@@ -0,0 +1,50 @@
+-- Tables are created conditionally so databases set up before migrations shipped can adopt them
+CREATE TABLE IF NOT EXISTS users (
+    user_id BIGINT PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    username VARCHAR NOT NULL,
+    password VARCHAR NOT NULL,
+    email VARCHAR NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS channels (
+    channel_id BIGINT PRIMARY KEY,
+    channel_type INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS channel_members (
+    channel_id BIGINT NOT NULL REFERENCES channels (channel_id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    joined_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (channel_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS channel_members_user_id_idx ON channel_members (user_id);
+
+CREATE TABLE IF NOT EXISTS messages (
+    message_id BIGINT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    channel_id BIGINT NOT NULL REFERENCES channels (channel_id) ON DELETE CASCADE,
+    reception_status SMALLINT NOT NULL DEFAULT 0,
+    edited BOOLEAN NOT NULL DEFAULT FALSE,
+    reply_to BIGINT REFERENCES messages (message_id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS messages_channel_id_message_id_idx ON messages (channel_id, message_id DESC);
+CREATE INDEX IF NOT EXISTS messages_user_id_idx ON messages (user_id);
+
+CREATE TABLE IF NOT EXISTS reactions (
+    reaction_id SERIAL PRIMARY KEY,
+    message_id BIGINT NOT NULL REFERENCES messages (message_id) ON DELETE CASCADE,
+    emoji VARCHAR NOT NULL,
+    reaction_count INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS reaction_users (
+    id SERIAL PRIMARY KEY,
+    reaction_id INTEGER NOT NULL REFERENCES reactions (reaction_id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS reaction_users_user_id_idx ON reaction_users (user_id);
@@ -0,0 +1,2 @@
+ALTER TABLE reaction_users DROP CONSTRAINT reaction_users_reaction_id_user_id_key;
+ALTER TABLE reactions DROP CONSTRAINT reactions_message_id_emoji_key;
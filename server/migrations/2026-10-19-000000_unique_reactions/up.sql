@@ -0,0 +1,35 @@
+-- Point reaction users of duplicate (message, emoji) reactions at the oldest one
+UPDATE reaction_users ru
+SET reaction_id = keeper.reaction_id
+FROM reactions r
+JOIN (
+    SELECT message_id, emoji, MIN(reaction_id) AS reaction_id
+    FROM reactions
+    GROUP BY message_id, emoji
+) keeper ON keeper.message_id = r.message_id AND keeper.emoji = r.emoji
+WHERE ru.reaction_id = r.reaction_id
+  AND r.reaction_id <> keeper.reaction_id;
+
+DELETE FROM reactions r
+USING reactions older
+WHERE older.message_id = r.message_id
+  AND older.emoji = r.emoji
+  AND older.reaction_id < r.reaction_id;
+
+-- Collapse duplicate reaction users
+DELETE FROM reaction_users a
+USING reaction_users b
+WHERE a.reaction_id = b.reaction_id
+  AND a.user_id = b.user_id
+  AND a.id > b.id;
+
+-- Recompute counts from the surviving reaction users
+UPDATE reactions r
+SET reaction_count = (
+    SELECT COUNT(*) FROM reaction_users ru WHERE ru.reaction_id = r.reaction_id
+);
+
+ALTER TABLE reactions
+    ADD CONSTRAINT reactions_message_id_emoji_key UNIQUE (message_id, emoji);
+ALTER TABLE reaction_users
+    ADD CONSTRAINT reaction_users_reaction_id_user_id_key UNIQUE (reaction_id, user_id);
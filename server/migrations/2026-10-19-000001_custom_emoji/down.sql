@@ -0,0 +1,2 @@
+ALTER TABLE reactions DROP COLUMN custom_emoji_id;
+DROP TABLE custom_emoji;
@@ -0,0 +1,15 @@
+CREATE TABLE custom_emoji (
+    emoji_id BIGINT PRIMARY KEY,
+    name VARCHAR(32) NOT NULL,
+    owner_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    channel_id BIGINT REFERENCES channels (channel_id) ON DELETE CASCADE,
+    content_type VARCHAR NOT NULL,
+    animated BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+-- Names are unique within their scope: per owner for user emojis, per channel for channel emojis
+CREATE UNIQUE INDEX custom_emoji_owner_name_key ON custom_emoji (owner_id, name) WHERE channel_id IS NULL;
+CREATE UNIQUE INDEX custom_emoji_channel_name_key ON custom_emoji (channel_id, name) WHERE channel_id IS NOT NULL;
+
+ALTER TABLE reactions
+    ADD COLUMN custom_emoji_id BIGINT REFERENCES custom_emoji (emoji_id) ON DELETE CASCADE;
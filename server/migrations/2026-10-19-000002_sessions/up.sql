@@ -0,0 +1,10 @@
+CREATE TABLE sessions (
+    session_id BIGINT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    refresh_token_hash VARCHAR NOT NULL,
+    previous_token_hash VARCHAR,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,4 @@
+DROP TABLE login_challenges;
+DROP TABLE recovery_codes;
+DROP TABLE two_factor;
+ALTER TABLE users DROP COLUMN two_factor_enabled;
@@ -0,0 +1,23 @@
+CREATE TABLE credentials (
+    credential_id BIGINT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    -- The authenticator's credential id, base64url encoded
+    external_id VARCHAR NOT NULL UNIQUE,
+    -- COSE encoded public key
+    public_key BYTEA NOT NULL,
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    name VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMP
+);
+
+CREATE INDEX credentials_user_id_idx ON credentials (user_id);
+
+-- Challenges for ceremonies in progress, login ones have no user until a username is given
+CREATE TABLE webauthn_challenges (
+    challenge_id BIGINT PRIMARY KEY,
+    user_id BIGINT REFERENCES users (user_id) ON DELETE CASCADE,
+    ceremony VARCHAR NOT NULL,
+    challenge VARCHAR NOT NULL,
+    expires_at TIMESTAMP NOT NULL
+);
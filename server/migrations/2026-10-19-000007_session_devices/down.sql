@@ -0,0 +1,5 @@
+ALTER TABLE sessions
+    DROP COLUMN device_name,
+    DROP COLUMN user_agent,
+    DROP COLUMN ip_address,
+    DROP COLUMN last_used_at;
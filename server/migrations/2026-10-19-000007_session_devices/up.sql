@@ -0,0 +1,5 @@
+ALTER TABLE sessions
+    ADD COLUMN device_name VARCHAR,
+    ADD COLUMN user_agent VARCHAR,
+    ADD COLUMN ip_address VARCHAR,
+    ADD COLUMN last_used_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
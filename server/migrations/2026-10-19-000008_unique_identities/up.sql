@@ -0,0 +1,18 @@
+-- Accounts that only differ by case can't be merged automatically, so they have to be resolved by hand
+DO $$
+DECLARE
+    conflicts TEXT;
+BEGIN
+    SELECT string_agg(identity, ', ') INTO conflicts FROM (
+        SELECT 'username ' || lower(username) AS identity FROM users GROUP BY lower(username) HAVING COUNT(*) > 1
+        UNION ALL
+        SELECT 'email ' || lower(email) FROM users GROUP BY lower(email) HAVING COUNT(*) > 1
+    ) duplicates;
+
+    IF conflicts IS NOT NULL THEN
+        RAISE EXCEPTION 'Resolve duplicate accounts before migrating: %', conflicts;
+    END IF;
+END $$;
+
+CREATE UNIQUE INDEX users_username_lower_key ON users (lower(username));
+CREATE UNIQUE INDEX users_email_lower_key ON users (lower(email));
@@ -0,0 +1,6 @@
+ALTER TABLE users
+    DROP COLUMN bio,
+    DROP COLUMN pronouns,
+    DROP COLUMN status_text,
+    DROP COLUMN status_emoji,
+    DROP COLUMN avatar_id;
@@ -0,0 +1,6 @@
+ALTER TABLE users
+    ADD COLUMN bio VARCHAR,
+    ADD COLUMN pronouns VARCHAR,
+    ADD COLUMN status_text VARCHAR,
+    ADD COLUMN status_emoji VARCHAR,
+    ADD COLUMN avatar_id BIGINT;
@@ -0,0 +1,10 @@
+CREATE TABLE username_history (
+    change_id BIGINT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    -- The username given up by this change
+    username VARCHAR NOT NULL,
+    changed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX username_history_user_id_idx ON username_history (user_id, changed_at);
+CREATE INDEX username_history_username_lower_idx ON username_history (lower(username));
@@ -0,0 +1,9 @@
+CREATE TABLE user_blocks (
+    blocker_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    blocked_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (blocker_id, blocked_id),
+    CHECK (blocker_id <> blocked_id)
+);
+
+CREATE INDEX user_blocks_blocked_id_idx ON user_blocks (blocked_id);
@@ -0,0 +1,18 @@
+CREATE TABLE friend_requests (
+    sender_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    recipient_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (sender_id, recipient_id),
+    CHECK (sender_id <> recipient_id)
+);
+
+CREATE INDEX friend_requests_recipient_id_idx ON friend_requests (recipient_id);
+
+-- Every friendship is stored once from each side
+CREATE TABLE friendships (
+    user_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    friend_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, friend_id),
+    CHECK (user_id <> friend_id)
+);
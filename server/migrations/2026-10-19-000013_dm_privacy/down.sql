@@ -0,0 +1,2 @@
+ALTER TABLE channel_members DROP COLUMN accepted;
+DROP TABLE user_settings;
@@ -0,0 +1,8 @@
+-- 0: everyone, 1: contacts only, 2: nobody
+CREATE TABLE user_settings (
+    user_id BIGINT PRIMARY KEY REFERENCES users (user_id) ON DELETE CASCADE,
+    dm_privacy SMALLINT NOT NULL DEFAULT 0
+);
+
+-- DMs opened by someone who isn't a contact stay in the requests inbox until accepted
+ALTER TABLE channel_members ADD COLUMN accepted BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,3 @@
+DROP TABLE folder_channels;
+DROP TABLE folders;
+DROP TABLE contact_nicknames;
@@ -0,0 +1,25 @@
+-- Nicknames are private to the user who set them
+CREATE TABLE contact_nicknames (
+    user_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    contact_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    nickname VARCHAR(64) NOT NULL,
+    PRIMARY KEY (user_id, contact_id)
+);
+
+CREATE TABLE folders (
+    folder_id BIGINT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    name VARCHAR(32) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX folders_user_id_idx ON folders (user_id);
+
+-- A conversation can be filed under several folders, like labels
+CREATE TABLE folder_channels (
+    folder_id BIGINT NOT NULL REFERENCES folders (folder_id) ON DELETE CASCADE,
+    channel_id BIGINT NOT NULL REFERENCES channels (channel_id) ON DELETE CASCADE,
+    PRIMARY KEY (folder_id, channel_id)
+);
+
+CREATE INDEX folder_channels_channel_id_idx ON folder_channels (channel_id);
@@ -0,0 +1,3 @@
+ALTER TABLE channel_members DROP COLUMN hidden;
+ALTER TABLE channel_members DROP COLUMN archived;
+ALTER TABLE channel_members DROP COLUMN pinned;
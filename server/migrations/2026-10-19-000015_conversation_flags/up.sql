@@ -0,0 +1,4 @@
+-- Each member organises their side of the conversation, a new message unhides it for everyone
+ALTER TABLE channel_members ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE channel_members ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE channel_members ADD COLUMN hidden BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+ALTER TABLE channel_members DROP COLUMN last_read_message_id;
+ALTER TABLE channel_members DROP COLUMN unread_count;
+ALTER TABLE channels DROP COLUMN last_message_id;
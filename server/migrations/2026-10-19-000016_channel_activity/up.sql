@@ -0,0 +1,14 @@
+-- Kept up to date when messages are sent and deleted, so listing conversations doesn't scan messages
+ALTER TABLE channels ADD COLUMN last_message_id BIGINT;
+ALTER TABLE channel_members ADD COLUMN unread_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE channel_members ADD COLUMN last_read_message_id BIGINT;
+
+UPDATE channels c
+SET last_message_id = (SELECT MAX(m.message_id) FROM messages m WHERE m.channel_id = c.channel_id);
+
+UPDATE channel_members cm
+SET unread_count = (
+    SELECT COUNT(*)
+    FROM messages m
+    WHERE m.channel_id = cm.channel_id AND m.user_id != cm.user_id AND m.reception_status = 0
+);
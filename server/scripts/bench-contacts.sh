@@ -0,0 +1,265 @@
+#!/usr/bin/env bash
+# Compares the contact list query from before channels tracked their last message and unread counts
+# (scanning `messages` for both) with the current one, which reads them from `channels` and `channel_members`.
+#
+# Usage: scripts/bench-contacts.sh <database url> [messages] [runs]
+#
+# The database has to be a throwaway one: it is migrated if needed and then filled with users, DM channels
+# and messages. The viewer is in 200 of the 5000 channels, messages are spread evenly over all of them.
+# Checks that both return the same contacts, then prints the median `EXPLAIN ANALYZE` execution time of each.
+#
+# Output with Postgres 15, 1M messages and 20 runs:
+#
+#   Seeding 1000000 messages over 5000 channels...
+#   before (scans messages): 417.305 ms
+#   after (channel columns): 4.475 ms
+set -euo pipefail
+
+URL=${1:?Usage: $0 <database url> [messages] [runs]}
+MESSAGES=${2:-1000000}
+RUNS=${3:-20}
+USERS=10000
+CHANNELS=5000
+VIEWER_CHANNELS=200
+MIGRATIONS="$(dirname "$0")/../migrations"
+
+psql() { PGOPTIONS="-c client_min_messages=warning" command psql "$URL" -v ON_ERROR_STOP=1 -q -X "$@"; }
+
+if [ "$(psql -At -c "SELECT to_regclass('channels') IS NULL")" = "t" ]; then
+    for migration in "$MIGRATIONS"/*/up.sql; do
+        psql -f "$migration"
+    done
+fi
+
+echo "Seeding $MESSAGES messages over $CHANNELS channels..."
+psql <<SQL
+TRUNCATE users, channels CASCADE;
+
+INSERT INTO users (user_id, name, username, password, email)
+SELECT id, 'User ' || id, 'user' || id, '', 'user' || id || '@example.com'
+FROM generate_series(1, $USERS) AS id;
+
+-- Channel c is between users 2c - 1 and 2c, except that user 1 (the viewer) takes the first member's place
+-- in the first $VIEWER_CHANNELS channels
+INSERT INTO channels (channel_id, channel_type)
+SELECT c, 0 FROM generate_series(1, $CHANNELS) AS c;
+INSERT INTO channel_members (channel_id, user_id)
+SELECT c, CASE WHEN c <= $VIEWER_CHANNELS THEN 1 ELSE 2 * c - 1 END FROM generate_series(1, $CHANNELS) AS c
+UNION ALL
+SELECT c, 2 * c FROM generate_series(1, $CHANNELS) AS c;
+
+-- Every tenth message is still unread
+INSERT INTO messages (message_id, user_id, content, channel_id, reception_status)
+SELECT
+    m,
+    CASE WHEN m % 2 = 0 THEN 2 * (m % $CHANNELS + 1) ELSE 2 * (m % $CHANNELS + 1) - 1 END,
+    'Message ' || m,
+    m % $CHANNELS + 1,
+    CASE WHEN m % 10 = 0 THEN 0 ELSE 2 END
+FROM generate_series(1, $MESSAGES) AS m;
+UPDATE messages SET user_id = 1 WHERE channel_id <= $VIEWER_CHANNELS AND user_id % 2 = 1;
+
+-- Same backfill as the migration that added the columns
+UPDATE channels c
+SET last_message_id = (SELECT MAX(m.message_id) FROM messages m WHERE m.channel_id = c.channel_id);
+UPDATE channel_members cm
+SET unread_count = (
+    SELECT COUNT(*)
+    FROM messages m
+    WHERE m.channel_id = cm.channel_id AND m.user_id != cm.user_id AND m.reception_status = 0
+);
+VACUUM ANALYZE;
+SQL
+
+# Both versions of the query, the first one from before the channel columns were added
+PREPARED=$(cat <<'SQL'
+PREPARE before_contacts(BIGINT, BOOLEAN, BIGINT, BOOLEAN, BIGINT, BIGINT) AS
+WITH last_messages AS (
+    SELECT
+        m.channel_id,
+        m.message_id,
+        m.content,
+        m.reception_status
+    FROM
+        messages m
+    WHERE
+        m.message_id = (
+            SELECT MAX(m2.message_id)
+            FROM messages m2
+            WHERE m2.channel_id = m.channel_id
+        )
+),
+unread_reception_count AS (
+    SELECT
+        m.channel_id,
+        COUNT(*) AS unread_reception_count
+    FROM
+        messages m
+    WHERE
+        m.reception_status = 0 AND m.user_id != $1
+    GROUP BY
+        m.channel_id
+),
+dm_channels AS (
+    SELECT
+        cm2.user_id AS contact_id,
+        cm1.channel_id,
+        cm1.accepted,
+        cm1.pinned,
+        cm1.archived,
+        cm1.hidden
+    FROM
+        channel_members cm1
+    JOIN
+        channels c ON cm1.channel_id = c.channel_id
+    JOIN
+        channel_members cm2 ON cm1.channel_id = cm2.channel_id AND cm2.user_id != $1
+    WHERE
+        cm1.user_id = $1
+        AND c.channel_type = 0
+),
+listed AS (
+    SELECT f.friend_id AS contact_id
+    FROM friendships f
+    WHERE f.user_id = $1 AND NOT $2
+    UNION
+    SELECT dc.contact_id
+    FROM dm_channels dc
+    WHERE dc.accepted != $2
+),
+user_folders AS (
+    SELECT fc.folder_id, fc.channel_id
+    FROM folder_channels fc
+    JOIN folders f ON f.folder_id = fc.folder_id
+    WHERE f.user_id = $1
+)
+
+SELECT
+    u.user_id,
+    dc.channel_id,
+    u.name,
+    u.username,
+    cn.nickname,
+    ARRAY(
+        SELECT uf.folder_id FROM user_folders uf WHERE uf.channel_id = dc.channel_id ORDER BY uf.folder_id
+    ) AS folder_ids,
+    COALESCE(dc.pinned, FALSE) AS pinned,
+    COALESCE(dc.archived, FALSE) AS archived,
+    lm.message_id,
+    lm.content,
+    lm.reception_status,
+    COALESCE(urc.unread_reception_count, 0) AS unread_reception_count
+FROM
+    listed l
+JOIN
+    users u ON u.user_id = l.contact_id
+LEFT JOIN
+    dm_channels dc ON dc.contact_id = u.user_id
+LEFT JOIN
+    last_messages lm ON dc.channel_id = lm.channel_id
+LEFT JOIN
+    unread_reception_count urc ON dc.channel_id = urc.channel_id
+LEFT JOIN
+    contact_nicknames cn ON cn.user_id = $1 AND cn.contact_id = u.user_id
+WHERE
+    ($3 IS NULL OR dc.channel_id IN (SELECT uf.channel_id FROM user_folders uf WHERE uf.folder_id = $3))
+    AND NOT COALESCE(dc.hidden, FALSE)
+    AND COALESCE(dc.archived, FALSE) = $4
+ORDER BY
+    COALESCE(dc.pinned, FALSE) DESC,
+    COALESCE(lm.message_id, dc.channel_id, 0) DESC,
+    u.user_id
+LIMIT $5 OFFSET $6;
+PREPARE after_contacts(BIGINT, BOOLEAN, BIGINT, BOOLEAN, BIGINT, BIGINT) AS
+WITH dm_channels AS (
+    SELECT
+        cm2.user_id AS contact_id,
+        cm1.channel_id,
+        cm1.accepted,
+        cm1.pinned,
+        cm1.archived,
+        cm1.hidden,
+        cm1.unread_count,
+        c.last_message_id
+    FROM
+        channel_members cm1
+    JOIN
+        channels c ON cm1.channel_id = c.channel_id
+    JOIN
+        channel_members cm2 ON cm1.channel_id = cm2.channel_id AND cm2.user_id != $1
+    WHERE
+        cm1.user_id = $1
+        AND c.channel_type = 0
+),
+listed AS (
+    SELECT f.friend_id AS contact_id
+    FROM friendships f
+    WHERE f.user_id = $1 AND NOT $2
+    UNION
+    SELECT dc.contact_id
+    FROM dm_channels dc
+    WHERE dc.accepted != $2
+),
+user_folders AS (
+    SELECT fc.folder_id, fc.channel_id
+    FROM folder_channels fc
+    JOIN folders f ON f.folder_id = fc.folder_id
+    WHERE f.user_id = $1
+)
+
+SELECT
+    u.user_id,
+    dc.channel_id,
+    u.name,
+    u.username,
+    cn.nickname,
+    ARRAY(
+        SELECT uf.folder_id FROM user_folders uf WHERE uf.channel_id = dc.channel_id ORDER BY uf.folder_id
+    ) AS folder_ids,
+    COALESCE(dc.pinned, FALSE) AS pinned,
+    COALESCE(dc.archived, FALSE) AS archived,
+    lm.message_id,
+    lm.content,
+    lm.reception_status,
+    COALESCE(dc.unread_count, 0)::BIGINT AS unread_reception_count
+FROM
+    listed l
+JOIN
+    users u ON u.user_id = l.contact_id
+LEFT JOIN
+    dm_channels dc ON dc.contact_id = u.user_id
+LEFT JOIN
+    messages lm ON lm.message_id = dc.last_message_id
+LEFT JOIN
+    contact_nicknames cn ON cn.user_id = $1 AND cn.contact_id = u.user_id
+WHERE
+    ($3 IS NULL OR dc.channel_id IN (SELECT uf.channel_id FROM user_folders uf WHERE uf.folder_id = $3))
+    AND NOT COALESCE(dc.hidden, FALSE)
+    AND COALESCE(dc.archived, FALSE) = $4
+ORDER BY
+    COALESCE(dc.pinned, FALSE) DESC,
+    COALESCE(dc.last_message_id, dc.channel_id, 0) DESC,
+    u.user_id
+LIMIT $5 OFFSET $6;
+SQL
+)
+ARGUMENTS="(1, FALSE, NULL, FALSE, 50, 0)"
+
+# Runs a prepared query $RUNS times and prints the median execution time in milliseconds
+median() {
+    for _ in $(seq "$RUNS"); do
+        printf '%s\nEXPLAIN ANALYZE EXECUTE %s%s;\n' "$PREPARED" "$1" "$ARGUMENTS" | psql -At \
+            | sed -n 's/^Execution Time: \(.*\) ms$/\1/p'
+    done | sort -n | awk '{ times[NR] = $1 } END { print times[int((NR + 1) / 2)] }'
+}
+
+results() {
+    printf '%s\nEXECUTE %s%s;\n' "$PREPARED" "$1" "$ARGUMENTS" | psql -At
+}
+
+if [ "$(results before_contacts)" != "$(results after_contacts)" ]; then
+    echo "The queries returned different contacts" >&2
+    exit 1
+fi
+echo "before (scans messages): $(median before_contacts) ms"
+echo "after (channel columns): $(median after_contacts) ms"
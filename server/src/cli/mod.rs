@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+#[command(name = "iris", version, about = "A live messaging server")]
+pub struct Cli {
+    /// Path to the TOML configuration file, defaults to `iris.toml` when present
+    #[arg(short, long, global = true, env = "IRIS_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the HTTP and gateway server (the default)
+    Serve,
+    /// Apply pending database migrations and exit
+    Migrate,
+    /// Create a user account directly in the database
+    CreateUser(CreateUserArgs)
+}
+
+#[derive(Args, Debug)]
+pub struct CreateUserArgs {
+    #[arg(long)]
+    pub name: String,
+    #[arg(long)]
+    pub username: String,
+    #[arg(long)]
+    pub email: String,
+    /// Read from IRIS_USER_PASSWORD when omitted, keeping it out of the shell history
+    #[arg(long, env = "IRIS_USER_PASSWORD", hide_env_values = true)]
+    pub password: String,
+    /// Mark the email as verified instead of mailing a verification link
+    #[arg(long)]
+    pub verified: bool
+}
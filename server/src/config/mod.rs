@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::util::snowflake::{ISSUER_BITS, WORKER_BITS};
+
+pub const DEFAULT_CONFIG_PATH: &str = "iris.toml";
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub argon: ArgonConfig,
+    pub snowflake: SnowflakeConfig,
+    pub limits: LimitsConfig,
+    pub mail: MailConfig,
+    pub passkeys: PasskeyConfig,
+    pub rate_limits: RateLimitConfig,
+    pub usernames: UsernameConfig
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub bind_address: SocketAddr,
+    pub cors_origins: Vec<String>,
+    pub uploads_dir: PathBuf,
+    pub log_level: String,
+    pub auto_migrate: bool,
+    // Where the web client lives, used to build links sent by mail
+    pub frontend_url: String
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub url: String
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub jwt_secret: String,
+    // The salt every password used to share, hashes still using it get upgraded on login
+    pub legacy_argon_salt: Option<String>,
+    // Lifetimes in seconds
+    pub access_token_ttl: i64,
+    pub refresh_token_ttl: i64,
+    pub verification_token_ttl: i64,
+    pub password_reset_ttl: i64,
+    pub two_factor_challenge_ttl: i64,
+    // Blocks users from sending messages until they verify their email
+    pub require_verified_email: bool,
+    // Users without 2FA can only reach the enrolment routes until they set it up
+    pub require_two_factor: bool
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ArgonConfig {
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u32
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SnowflakeConfig {
+    pub issuer_id: u8,
+    pub worker_id: u8
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MailConfig {
+    pub transport: MailTransport,
+    pub from: String,
+    // Only used by the `file` transport
+    pub directory: PathBuf,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub smtp_starttls: bool
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    Stdout,
+    File,
+    Smtp
+}
+
+impl FromStr for MailTransport {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "stdout" => Ok(MailTransport::Stdout),
+            "file" => Ok(MailTransport::File),
+            "smtp" => Ok(MailTransport::Smtp),
+            _ => Err(())
+        }
+    }
+}
+
+// Both in seconds, zero turns them off
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct UsernameConfig {
+    // How long users wait between two username changes
+    pub change_cooldown: i64,
+    // How long a released username stays unavailable to everyone but its previous owner
+    pub reservation_period: i64
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PasskeyConfig {
+    // The domain passkeys are bound to, the frontend has to be served from it or a subdomain
+    pub rp_id: String,
+    pub rp_name: String,
+    // Origins allowed to run ceremonies, the frontend URL when empty
+    pub origins: Vec<String>,
+    pub challenge_ttl: i64
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    // Read the client address from `X-Forwarded-For`, only safe behind a proxy that sets it
+    pub trust_proxy: bool,
+    // Applied per client address on every route, and per user on authenticated ones
+    pub ip: RateLimit,
+    pub user: RateLimit,
+    // Overrides by route path, as written in the router (e.g. `/api/channels/:channel_id/messages`)
+    pub routes: HashMap<String, RouteRateLimit>,
+    pub lockout: LockoutConfig
+}
+
+// Allows bursts of `capacity` requests, refilled evenly over `period` seconds
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub period: u64
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RouteRateLimit {
+    pub ip: Option<RateLimit>,
+    pub user: Option<RateLimit>,
+    // Counts 401 responses as failed attempts, locking out the address and the account tried
+    pub lockout: bool
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LockoutConfig {
+    // Failed attempts before the first lockout
+    pub threshold: u32,
+    // Lockouts start at `base_duration` seconds and double with every further failure
+    pub base_duration: u64,
+    pub max_duration: u64
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LimitsConfig {
+    pub max_body_size: usize,
+    pub max_emoji_size: usize,
+    pub max_avatar_size: usize,
+    pub gateway_queue_size: usize
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            cors_origins: vec![String::from("*")],
+            uploads_dir: PathBuf::from("uploads"),
+            log_level: String::from("iris_rs=debug,tower_http=info,diesel=debug"),
+            auto_migrate: true,
+            frontend_url: String::from("http://localhost:5173")
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            jwt_secret: String::new(),
+            legacy_argon_salt: None,
+            access_token_ttl: 15 * 60,
+            refresh_token_ttl: 30 * 24 * 60 * 60,
+            verification_token_ttl: 24 * 60 * 60,
+            password_reset_ttl: 60 * 60,
+            two_factor_challenge_ttl: 5 * 60,
+            require_verified_email: false,
+            require_two_factor: false
+        }
+    }
+}
+
+impl Default for ArgonConfig {
+    fn default() -> Self {
+        ArgonConfig {
+            memory_cost: argon2::Params::DEFAULT_M_COST,
+            time_cost: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST
+        }
+    }
+}
+
+impl Default for SnowflakeConfig {
+    fn default() -> Self {
+        SnowflakeConfig {
+            issuer_id: 1,
+            worker_id: 1
+        }
+    }
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            transport: MailTransport::Stdout,
+            from: String::from("Iris <no-reply@localhost>"),
+            directory: PathBuf::from("mail"),
+            smtp_host: String::from("localhost"),
+            smtp_port: 587,
+            smtp_username: None,
+            smtp_password: None,
+            smtp_starttls: true
+        }
+    }
+}
+
+impl Default for UsernameConfig {
+    fn default() -> Self {
+        UsernameConfig {
+            change_cooldown: 7 * 24 * 60 * 60,
+            reservation_period: 30 * 24 * 60 * 60
+        }
+    }
+}
+
+impl Default for PasskeyConfig {
+    fn default() -> Self {
+        PasskeyConfig {
+            rp_id: String::from("localhost"),
+            rp_name: String::from("Iris"),
+            origins: Vec::new(),
+            challenge_ttl: 5 * 60
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let ip = |capacity, period| RouteRateLimit {
+            ip: Some(RateLimit { capacity, period }),
+            ..RouteRateLimit::default()
+        };
+        let login = RouteRateLimit {
+            lockout: true,
+            ..ip(10, 60)
+        };
+
+        RateLimitConfig {
+            enabled: true,
+            trust_proxy: false,
+            ip: RateLimit { capacity: 300, period: 60 },
+            user: RateLimit { capacity: 300, period: 60 },
+            routes: HashMap::from([
+                (String::from("/login"), login.clone()),
+                (String::from("/login/2fa"), login.clone()),
+                (String::from("/login/passkey/finish"), login),
+                (String::from("/signup"), ip(5, 60 * 60)),
+                (String::from("/password/forgot"), ip(5, 60 * 60)),
+                (String::from("/password/reset"), ip(10, 10 * 60)),
+                (String::from("/verify-email"), ip(10, 10 * 60)),
+                (String::from("/token/refresh"), ip(30, 60))
+            ]),
+            lockout: LockoutConfig::default()
+        }
+    }
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        LockoutConfig {
+            threshold: 5,
+            base_duration: 30,
+            max_duration: 60 * 60
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_body_size: 2 * 1024 * 1024,
+            max_emoji_size: 256 * 1024,
+            max_avatar_size: 1024 * 1024,
+            gateway_queue_size: 100
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env(&'static str, String),
+    Invalid(String)
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "could not read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => write!(f, "could not parse {}: {}", path.display(), err),
+            ConfigError::Env(name, value) => write!(f, "invalid value `{}` for {}", value, name),
+            ConfigError::Invalid(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    // Reads the TOML file (if any), then lets environment variables override individual settings.
+    // An explicitly requested file must exist, while the default `iris.toml` is optional.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let mut config = match path {
+            Some(path) => Config::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::from_file(Path::new(DEFAULT_CONFIG_PATH))?,
+            None => Config::default()
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        toml::from_str(&contents)
+            .map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        env_override("IRIS_BIND_ADDRESS", &mut self.server.bind_address)?;
+        env_override("IRIS_UPLOADS_DIR", &mut self.server.uploads_dir)?;
+        env_override("IRIS_LOG", &mut self.server.log_level)?;
+        env_override("IRIS_AUTO_MIGRATE", &mut self.server.auto_migrate)?;
+        env_override("IRIS_FRONTEND_URL", &mut self.server.frontend_url)?;
+        if let Ok(origins) = std::env::var("IRIS_CORS_ORIGINS") {
+            self.server.cors_origins = origins.split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect();
+        }
+        env_override("DATABASE_URL", &mut self.database.url)?;
+        env_override("JWT_SECRET", &mut self.auth.jwt_secret)?;
+        if let Ok(salt) = std::env::var("ARGON_SALT") {
+            self.auth.legacy_argon_salt = Some(salt);
+        }
+        env_override("IRIS_ACCESS_TOKEN_TTL", &mut self.auth.access_token_ttl)?;
+        env_override("IRIS_REFRESH_TOKEN_TTL", &mut self.auth.refresh_token_ttl)?;
+        env_override("IRIS_VERIFICATION_TOKEN_TTL", &mut self.auth.verification_token_ttl)?;
+        env_override("IRIS_PASSWORD_RESET_TTL", &mut self.auth.password_reset_ttl)?;
+        env_override("IRIS_TWO_FACTOR_CHALLENGE_TTL", &mut self.auth.two_factor_challenge_ttl)?;
+        env_override("IRIS_REQUIRE_VERIFIED_EMAIL", &mut self.auth.require_verified_email)?;
+        env_override("IRIS_REQUIRE_TWO_FACTOR", &mut self.auth.require_two_factor)?;
+        env_override("IRIS_ARGON_MEMORY_COST", &mut self.argon.memory_cost)?;
+        env_override("IRIS_ARGON_TIME_COST", &mut self.argon.time_cost)?;
+        env_override("IRIS_ARGON_PARALLELISM", &mut self.argon.parallelism)?;
+        env_override("IRIS_ISSUER_ID", &mut self.snowflake.issuer_id)?;
+        env_override("IRIS_WORKER_ID", &mut self.snowflake.worker_id)?;
+        env_override("IRIS_MAX_BODY_SIZE", &mut self.limits.max_body_size)?;
+        env_override("IRIS_MAX_EMOJI_SIZE", &mut self.limits.max_emoji_size)?;
+        env_override("IRIS_MAX_AVATAR_SIZE", &mut self.limits.max_avatar_size)?;
+        env_override("IRIS_GATEWAY_QUEUE_SIZE", &mut self.limits.gateway_queue_size)?;
+        env_override("IRIS_MAIL_TRANSPORT", &mut self.mail.transport)?;
+        env_override("IRIS_MAIL_FROM", &mut self.mail.from)?;
+        env_override("IRIS_MAIL_DIRECTORY", &mut self.mail.directory)?;
+        env_override("IRIS_SMTP_HOST", &mut self.mail.smtp_host)?;
+        env_override("IRIS_SMTP_PORT", &mut self.mail.smtp_port)?;
+        env_override_option("IRIS_SMTP_USERNAME", &mut self.mail.smtp_username);
+        env_override_option("IRIS_SMTP_PASSWORD", &mut self.mail.smtp_password);
+        env_override("IRIS_SMTP_STARTTLS", &mut self.mail.smtp_starttls)?;
+        env_override("IRIS_PASSKEY_RP_ID", &mut self.passkeys.rp_id)?;
+        env_override("IRIS_PASSKEY_RP_NAME", &mut self.passkeys.rp_name)?;
+        if let Ok(origins) = std::env::var("IRIS_PASSKEY_ORIGINS") {
+            self.passkeys.origins = origins.split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect();
+        }
+        env_override("IRIS_PASSKEY_CHALLENGE_TTL", &mut self.passkeys.challenge_ttl)?;
+        env_override("IRIS_RATE_LIMITS", &mut self.rate_limits.enabled)?;
+        env_override("IRIS_TRUST_PROXY", &mut self.rate_limits.trust_proxy)?;
+        env_override("IRIS_USERNAME_CHANGE_COOLDOWN", &mut self.usernames.change_cooldown)?;
+        env_override("IRIS_USERNAME_RESERVATION_PERIOD", &mut self.usernames.reservation_period)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.database.url.is_empty() {
+            return Err(ConfigError::Invalid(String::from("database.url (or DATABASE_URL) must be set")));
+        }
+        if self.auth.jwt_secret.is_empty() {
+            return Err(ConfigError::Invalid(String::from("auth.jwt_secret (or JWT_SECRET) must be set")));
+        }
+        if self.auth.access_token_ttl <= 0 || self.auth.refresh_token_ttl <= self.auth.access_token_ttl {
+            return Err(ConfigError::Invalid(String::from("auth.refresh_token_ttl must be longer than a positive auth.access_token_ttl")));
+        }
+        if self.auth.verification_token_ttl <= 0 || self.auth.password_reset_ttl <= 0 || self.auth.two_factor_challenge_ttl <= 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "auth.verification_token_ttl, auth.password_reset_ttl and auth.two_factor_challenge_ttl must be positive"
+            )));
+        }
+        if self.snowflake.issuer_id >= 1 << ISSUER_BITS || self.snowflake.worker_id >= 1 << WORKER_BITS {
+            return Err(ConfigError::Invalid(format!(
+                "snowflake.issuer_id and snowflake.worker_id must be below {} and {}",
+                1 << ISSUER_BITS, 1 << WORKER_BITS
+            )));
+        }
+        if self.passkeys.rp_id.is_empty() || self.passkeys.challenge_ttl <= 0 {
+            return Err(ConfigError::Invalid(String::from("passkeys.rp_id must be set and passkeys.challenge_ttl must be positive")));
+        }
+        if self.usernames.change_cooldown < 0 || self.usernames.reservation_period < 0 {
+            return Err(ConfigError::Invalid(String::from(
+                "usernames.change_cooldown and usernames.reservation_period can't be negative"
+            )));
+        }
+        let limits = self.rate_limits.routes.values()
+            .flat_map(|route| [route.ip, route.user])
+            .flatten()
+            .chain([self.rate_limits.ip, self.rate_limits.user]);
+        for limit in limits {
+            if limit.capacity == 0 || limit.period == 0 {
+                return Err(ConfigError::Invalid(String::from("rate limit capacities and periods must be positive")));
+            }
+        }
+        let lockout = &self.rate_limits.lockout;
+        if lockout.threshold == 0 || lockout.base_duration == 0 || lockout.max_duration < lockout.base_duration {
+            return Err(ConfigError::Invalid(String::from(
+                "rate_limits.lockout needs a positive threshold and base_duration, and a max_duration at least as long"
+            )));
+        }
+        if self.limits.gateway_queue_size == 0 {
+            return Err(ConfigError::Invalid(String::from("limits.gateway_queue_size must be positive")));
+        }
+        argon2::Params::new(self.argon.memory_cost, self.argon.time_cost, self.argon.parallelism, None)
+            .map_err(|err| ConfigError::Invalid(format!("invalid argon parameters: {}", err)))?;
+        Ok(())
+    }
+
+    pub fn argon_params(&self) -> argon2::Params {
+        argon2::Params::new(self.argon.memory_cost, self.argon.time_cost, self.argon.parallelism, None)
+            .expect("Argon parameters are validated on load")
+    }
+
+    pub fn passkey_origins(&self) -> Vec<&str> {
+        if self.passkeys.origins.is_empty() {
+            return vec![self.server.frontend_url.trim_end_matches('/')];
+        }
+        self.passkeys.origins.iter().map(|origin| origin.trim_end_matches('/')).collect()
+    }
+}
+
+fn env_override<T: FromStr>(name: &'static str, target: &mut T) -> Result<(), ConfigError> {
+    if let Ok(value) = std::env::var(name) {
+        *target = value.parse().map_err(|_| ConfigError::Env(name, value))?;
+    }
+    Ok(())
+}
+
+fn env_override_option(name: &'static str, target: &mut Option<String>) {
+    if let Ok(value) = std::env::var(name) {
+        *target = Some(value);
+    }
+}
@@ -1,9 +1,111 @@
-use diesel::{Connection, PgConnection};
+use diesel::{Connection, ConnectionResult, PgConnection};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 
-pub fn connect() -> PgConnection {
-    let database_url = std::env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
 
-    PgConnection::establish(&database_url)
-        .expect(&format!("Error connecting to {}", database_url))
-}
\ No newline at end of file
+pub fn connect(database_url: &str) -> ConnectionResult<PgConnection> {
+    PgConnection::establish(database_url)
+}
+
+// Applies every embedded migration that hasn't run yet, returning the applied versions
+pub fn run_migrations(connection: &mut PgConnection) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let versions = connection.run_pending_migrations(MIGRATIONS)?;
+    Ok(versions.into_iter().map(|version| version.to_string()).collect())
+}
+
+// Tests that need Postgres run against `TEST_DATABASE_URL` and are skipped when it isn't set.
+// Each one creates its own rows with fresh ids, so they can share a database and run in parallel.
+#[cfg(test)]
+pub mod testing {
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::OnceLock;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use diesel::{ExpressionMethods, PgConnection, RunQueryDsl};
+
+    use crate::schema::channels::channel_members::dsl::channel_members;
+    use crate::schema::channels::channels::dsl::channels;
+    use crate::schema::messages::Message;
+    use crate::schema::messages::messages::dsl::messages;
+    use crate::schema::users::User;
+    use crate::schema::users::users::dsl::users;
+
+    pub fn database_url() -> Option<String> {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL is not set, skipping");
+            return None;
+        };
+        static MIGRATED: OnceLock<()> = OnceLock::new();
+        MIGRATED.get_or_init(|| {
+            let mut connection = super::connect(&url).expect("Failed to connect to the test database");
+            super::run_migrations(&mut connection).expect("Failed to migrate the test database");
+        });
+        Some(url)
+    }
+
+    pub fn connect(url: &str) -> PgConnection {
+        super::connect(url).expect("Failed to connect to the test database")
+    }
+
+    // Seeded from the clock so reruns against the same database don't collide
+    pub fn unique_id() -> i64 {
+        static NEXT: OnceLock<AtomicI64> = OnceLock::new();
+        NEXT.get_or_init(|| {
+            let micros = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as i64;
+            AtomicI64::new(micros * 1000)
+        }).fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn insert_user(connection: &mut PgConnection) -> i64 {
+        let id = unique_id();
+        let user = User {
+            user_id: id,
+            name: String::from("Test"),
+            username: format!("test{}", id),
+            password: String::new(),
+            email: format!("test{}@example.com", id),
+            email_verified: true,
+            two_factor_enabled: false,
+            bio: None,
+            pronouns: None,
+            status_text: None,
+            status_emoji: None,
+            avatar_id: None
+        };
+        diesel::insert_into(users).values(&user).execute(connection).expect("Failed to insert user");
+        id
+    }
+
+    pub fn insert_dm(connection: &mut PgConnection, members: [i64; 2]) -> i64 {
+        use crate::schema::channels::channel_members::{channel_id as member_channel_id, user_id as member_user_id};
+        use crate::schema::channels::channels::{channel_id, channel_type};
+
+        let id = unique_id();
+        diesel::insert_into(channels)
+            .values((channel_id.eq(id), channel_type.eq(0)))
+            .execute(connection)
+            .expect("Failed to insert channel");
+        for member in members {
+            diesel::insert_into(channel_members)
+                .values((member_channel_id.eq(id), member_user_id.eq(member)))
+                .execute(connection)
+                .expect("Failed to insert channel member");
+        }
+        id
+    }
+
+    pub fn insert_message(connection: &mut PgConnection, channel_id: i64, author: i64, content: &str) -> i64 {
+        let id = unique_id();
+        let message = Message {
+            message_id: id,
+            user_id: author,
+            content: content.to_string(),
+            channel_id,
+            reception_status: 0,
+            edited: false,
+            reply_to: None
+        };
+        diesel::insert_into(messages).values(&message).execute(connection).expect("Failed to insert message");
+        id
+    }
+}
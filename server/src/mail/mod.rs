@@ -0,0 +1,142 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+use crate::config::{MailConfig, MailTransport};
+
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String
+}
+
+#[derive(Debug)]
+pub struct MailError(pub String);
+
+impl Display for MailError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for MailError {}
+
+#[async_trait]
+pub trait Mailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailError>;
+}
+
+pub type SharedMailer = Arc<dyn Mailer + Send + Sync>;
+
+pub fn from_config(config: &MailConfig) -> Result<SharedMailer, MailError> {
+    match config.transport {
+        MailTransport::Stdout => Ok(Arc::new(FileMailer { directory: None })),
+        MailTransport::File => Ok(Arc::new(FileMailer { directory: Some(config.directory.clone()) })),
+        MailTransport::Smtp => Ok(Arc::new(SmtpMailer::new(config)?))
+    }
+}
+
+// Sends in the background so handlers never wait on (or fail because of) the mail server
+pub fn dispatch(mailer: SharedMailer, mail: Mail) {
+    tokio::spawn(async move {
+        let recipient = mail.to.clone();
+        if let Err(err) = mailer.send(mail).await {
+            tracing::error!("Failed to send mail to {}: {}", recipient, err);
+        }
+    });
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox
+}
+
+impl SmtpMailer {
+    pub fn new(config: &MailConfig) -> Result<SmtpMailer, MailError> {
+        let builder = if config.smtp_starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host)
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host)
+        }.map_err(|err| MailError(err.to_string()))?;
+
+        let mut builder = builder.port(config.smtp_port);
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(SmtpMailer {
+            transport: builder.build(),
+            from: config.from.parse().map_err(|_| MailError(format!("invalid sender address `{}`", config.from)))?
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail.to.parse().map_err(|_| MailError(format!("invalid recipient `{}`", mail.to)))?)
+            .subject(mail.subject)
+            .body(mail.body)
+            .map_err(|err| MailError(err.to_string()))?;
+        self.transport.send(message).await
+            .map(|_| ())
+            .map_err(|err| MailError(err.to_string()))
+    }
+}
+
+// Keeps the recipient readable in the file name without letting it escape `directory`
+fn mail_file_name(timestamp: i64, to: &str) -> String {
+    let recipient: String = to.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '@' | '.' | '_' | '-') { c } else { '_' })
+        .collect();
+    format!("{}-{}.eml", timestamp, recipient)
+}
+
+// Development mailer: writes each mail to its own file in `directory`, or to stdout when there is none
+pub struct FileMailer {
+    pub directory: Option<PathBuf>
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailError> {
+        let contents = format!("To: {}\nSubject: {}\n\n{}\n", mail.to, mail.subject, mail.body);
+        match &self.directory {
+            None => {
+                println!("{}", contents);
+                Ok(())
+            }
+            Some(directory) => {
+                tokio::fs::create_dir_all(directory).await
+                    .map_err(|err| MailError(err.to_string()))?;
+                let name = mail_file_name(chrono::Utc::now().timestamp_micros(), &mail.to);
+                tokio::fs::write(directory.join(name), contents).await
+                    .map_err(|err| MailError(err.to_string()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mail_file_name;
+
+    #[test]
+    fn file_name_keeps_plain_addresses() {
+        assert_eq!(mail_file_name(42, "someone.else_1-2@example.com"), "42-someone.else_1-2@example.com.eml");
+    }
+
+    #[test]
+    fn file_name_replaces_path_and_control_characters() {
+        assert_eq!(mail_file_name(42, "../../etc/passwd"), "42-.._.._etc_passwd.eml");
+        assert_eq!(mail_file_name(42, "a\\b\0c\nd e:f"), "42-a_b_c_d_e_f.eml");
+        assert_eq!(mail_file_name(42, "ünï@example.com"), "42-_n_@example.com.eml");
+    }
+}
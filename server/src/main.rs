@@ -2,114 +2,322 @@ mod server;
 mod schema;
 mod database;
 mod util;
+mod config;
+mod cli;
+mod mail;
 
 use std::net::SocketAddr;
+use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
-use argon2::Argon2;
-use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Version};
 use axum::{routing::get, Router, middleware};
-use axum::routing::{delete, post, put};
+use axum::extract::DefaultBodyLimit;
+use axum::http::{HeaderValue, Method, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{delete, patch, post, put};
+use clap::Parser;
 use dashmap::DashMap;
 use diesel::PgConnection;
 use dotenvy::dotenv;
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use tokio::net::TcpListener;
-use tokio::sync::mpsc::Sender;
 use tower::ServiceBuilder;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 use tower_http::add_extension::AddExtensionLayer;
-use tower_http::cors::CorsLayer;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use crate::cli::{Cli, Command, CreateUserArgs};
+use crate::config::Config;
+use crate::mail::SharedMailer;
 use crate::schema::users::User;
 use crate::server::gateway::Gateway;
-use crate::server::messages::Packet;
-use crate::server::rest::middlewares::{authorize};
+use crate::server::gateway::context::PacketQueue;
+use crate::server::rest::auth::RegisterRequest;
+use crate::server::rest::IrisError;
+use crate::server::rest::middlewares::{authorize, limit_by_ip, limit_by_user};
+use crate::util::passwords::hash_password;
+use crate::util::rate_limit::RateLimiter;
 use crate::util::snowflake::SnowflakeIssuer;
 
 #[tokio::main]
-async fn main() {
-    dotenv().ok().expect("Failed to read .env file");
-    let key = Hmac::<Sha256>::new_from_slice(std::env::var("JWT_SECRET").expect("JWT token could not be read").as_bytes())
-        .expect("Failed to create HMAC");
-    let salt = SaltString::from_b64(&*std::env::var("ARGON_SALT").expect("Argon salt could not be read"))
-        .expect("Failed to create salt");
-
-    let database_connection = database::connect();
-    let mut gateway = Gateway::new();
-    gateway.register_handler(Box::new(server::gateway::receipts::ReceiptGatewayHandler));
-    gateway.register_handler(Box::new(server::gateway::typing::TypingGatewayHandler));
-
-    let state = AppState {
-        gateway,
-        packet_queue: DashMap::new(),
-        database: database_connection,
-        jwt_key: key,
-        argon: Argon2::default(),
-        argon_salt: salt,
-        snowflake_issuer: SnowflakeIssuer::new(1,1)
+async fn main() -> ExitCode {
+    // The .env file is optional, any variable it would set can also come from the environment
+    dotenv().ok();
+    let cli = Cli::parse();
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Invalid configuration: {err}");
+            return ExitCode::FAILURE;
+        }
     };
 
+    let mut database_connection = match database::connect(&config.database.url) {
+        Ok(connection) => connection,
+        Err(err) => {
+            eprintln!("Error connecting to the database: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let command = cli.command.unwrap_or(Command::Serve);
+    let migrate = matches!(command, Command::Migrate) || config.server.auto_migrate;
+    if migrate {
+        match database::run_migrations(&mut database_connection) {
+            Ok(versions) => versions.iter().for_each(|version| println!("Applied migration {version}")),
+            Err(err) => {
+                eprintln!("Failed to run database migrations: {err}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let mailer = match mail::from_config(&config.mail) {
+        Ok(mailer) => mailer,
+        Err(err) => {
+            eprintln!("Invalid mail configuration: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match command {
+        Command::Serve => serve(AppState::new(config, database_connection, mailer)).await,
+        Command::Migrate => ExitCode::SUCCESS,
+        Command::CreateUser(args) => create_user(AppState::new(config, database_connection, mailer), args).await
+    }
+}
+
+async fn serve(state: AppState) -> ExitCode {
+    let config = state.config.clone();
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "example_websockets=debug,tower_http=info,diesel=debug".into()),
+                .unwrap_or_else(|_| config.server.log_level.clone().into()),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
+    let limiter = Arc::new(RateLimiter::new(config.rate_limits.clone()));
+    let pruned = limiter.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            pruned.prune(Instant::now());
+        }
+    });
+
     let app = Router::new()
         .route("/ws", get(server::subscribe_chat_handshake))
         .route("/api/search", post(server::rest::search::search))
         .route("/api/users/@me", get(server::rest::user::get_self))
+        .route("/api/users/@me", patch(server::rest::user::update_profile))
+        .route("/api/users/@me/username", put(server::rest::user::change_username))
+        .route("/api/users/@me/avatar", put(server::rest::user::upload_avatar))
+        .route("/api/users/@me/avatar", delete(server::rest::user::delete_avatar))
+        .route("/api/users/@me/verification", post(server::rest::verification::resend_verification))
+        .route("/api/users/@me/password", put(server::rest::password::change_password))
+        .route("/api/users/@me/2fa", post(server::rest::two_factor::enroll))
+        .route("/api/users/@me/2fa", delete(server::rest::two_factor::disable))
+        .route("/api/users/@me/2fa/confirm", post(server::rest::two_factor::confirm))
+        .route("/api/users/@me/2fa/recovery-codes", post(server::rest::two_factor::regenerate_recovery_codes))
+        .route("/api/users/@me/sessions", get(server::rest::sessions::get_sessions))
+        .route("/api/users/@me/sessions/:session_id", delete(server::rest::sessions::delete_session))
+        .route("/api/users/@me/passkeys", get(server::rest::passkeys::get_passkeys))
+        .route("/api/users/@me/passkeys/register", post(server::rest::passkeys::start_registration))
+        .route("/api/users/@me/passkeys/register/finish", post(server::rest::passkeys::finish_registration))
+        .route("/api/users/@me/passkeys/:passkey_id", delete(server::rest::passkeys::delete_passkey))
+        .route("/api/users/@me/settings", get(server::rest::settings::get_settings))
+        .route("/api/users/@me/settings", patch(server::rest::settings::update_settings))
+        .route("/api/users/@me/blocks", get(server::rest::blocks::get_blocks))
+        .route("/api/users/:user_id", get(server::rest::user::get_user))
+        .route("/api/users/:user_id/block", post(server::rest::blocks::block_user))
+        .route("/api/users/:user_id/block", delete(server::rest::blocks::unblock_user))
+        .route("/api/friends", get(server::rest::friends::get_friends))
+        .route("/api/friends/:user_id", delete(server::rest::friends::remove_friend))
+        .route("/api/friends/requests", get(server::rest::friends::get_friend_requests))
+        .route("/api/friends/requests", post(server::rest::friends::send_friend_request))
+        .route("/api/friends/requests/:user_id", delete(server::rest::friends::cancel_friend_request))
+        .route("/api/friends/requests/:user_id/accept", post(server::rest::friends::accept_friend_request))
+        .route("/api/friends/requests/:user_id/decline", post(server::rest::friends::decline_friend_request))
         .route("/api/contacts/@me", get(server::rest::contacts::get_contacts))
+        .route("/api/contacts/requests", get(server::rest::contacts::get_message_requests))
+        .route("/api/contacts/requests/:channel_id/accept", post(server::rest::contacts::accept_message_request))
+        .route("/api/contacts/requests/:channel_id", delete(server::rest::contacts::decline_message_request))
         .route("/api/contacts/:contact_id", get(server::rest::contacts::get_contact))
         .route("/api/contacts/:contact_id/chat", post(server::rest::contacts::chat))
+        .route("/api/contacts/:contact_id/nickname", put(server::rest::contacts::set_nickname))
+        .route("/api/folders", get(server::rest::folders::get_folders))
+        .route("/api/folders", post(server::rest::folders::create_folder))
+        .route("/api/folders/:folder_id", patch(server::rest::folders::rename_folder))
+        .route("/api/folders/:folder_id", delete(server::rest::folders::delete_folder))
+        .route("/api/folders/:folder_id/channels/:channel_id", put(server::rest::folders::add_folder_channel))
+        .route("/api/folders/:folder_id/channels/:channel_id", delete(server::rest::folders::remove_folder_channel))
+        .route("/api/channels/:channel_id/conversation", patch(server::rest::contacts::update_conversation))
         .route("/api/channels/:channel_id/messages", post(server::rest::messages::create_message))
         .route("/api/channels/:channel_id/messages", get(server::rest::messages::get_messages))
         .route("/api/channels/:channel_id/messages/:message_id", put(server::rest::messages::edit_message))
         .route("/api/channels/:channel_id/messages/:message_id", delete(server::rest::messages::delete_message))
         .route("/api/channels/:channel_id/messages/:message_id/reactions", post(server::rest::reactions::add_reaction))
         .route("/api/channels/:channel_id/messages/:message_id/reactions/:reaction_id", delete(server::rest::reactions::remove_reaction))
+        .route("/api/channels/:channel_id/emojis", get(server::rest::emojis::get_channel_emojis))
+        .route("/api/channels/:channel_id/emojis", post(server::rest::emojis::upload_channel_emoji))
+        .route("/api/emojis", post(server::rest::emojis::upload_emoji))
+        .route("/api/emojis/@me", get(server::rest::emojis::get_emojis))
+        .route("/api/emojis/:emoji_id", delete(server::rest::emojis::delete_emoji))
+        .route_layer(
+            middleware::from_fn(limit_by_user)
+        )
         .route_layer(
             middleware::from_fn(authorize)
         )
         .route("/login", post(server::rest::auth::login))
+        .route("/login/2fa", post(server::rest::two_factor::verify_login))
+        .route("/login/passkey", post(server::rest::passkeys::start_login))
+        .route("/login/passkey/finish", post(server::rest::passkeys::finish_login))
         .route("/signup", post(server::rest::auth::register))
-        .layer(CorsLayer::permissive())
+        .route("/token/refresh", post(server::rest::auth::refresh))
+        .route("/verify-email", post(server::rest::verification::verify_email))
+        .route("/password/forgot", post(server::rest::password::forgot_password))
+        .route("/password/reset", post(server::rest::password::reset_password))
+        .route("/emojis/:emoji_id", get(server::rest::emojis::get_emoji_image))
+        .route("/avatars/:user_id/:avatar_id", get(server::rest::user::get_avatar_image))
+        .fallback(|| async { IrisError::from_status(StatusCode::NOT_FOUND, "Not found") })
+        .layer(middleware::from_fn(limit_by_ip))
+        .layer(cors_layer(&config.server.cors_origins))
+        .layer(DefaultBodyLimit::max(config.limits.max_body_size))
         .layer(
             ServiceBuilder::new()
                 .layer(TraceLayer::new_for_http()
                     .make_span_with(DefaultMakeSpan::default().include_headers(true))
                 )
+                // A handler that panics still answers with the usual error body
+                .layer(CatchPanicLayer::custom(|_| {
+                    tracing::error!("A request handler panicked");
+                    IrisError::internal("Something went wrong").into_response()
+                }))
                 .layer(AddExtensionLayer::new(Arc::new(RwLock::new(state))))
+                .layer(AddExtensionLayer::new(limiter.clone()))
                 .into_inner()
         );
 
-    let listener = TcpListener::bind("127.0.0.1:3000")
-        .await
-        .expect("failed to bind to address");
+    let listener = match TcpListener::bind(config.server.bind_address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Failed to bind to {}: {err}", config.server.bind_address);
+            return ExitCode::FAILURE;
+        }
+    };
     tracing::info!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(
+    let served = axum::serve(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await
-    .expect("failed to run server");
+    ).await;
+
+    match served {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Failed to run server: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn create_user(mut state: AppState, args: CreateUserArgs) -> ExitCode {
+    let request = RegisterRequest {
+        name: args.name,
+        username: args.username,
+        password: args.password,
+        email: args.email
+    };
+
+    let created = server::rest::auth::create_user(&mut state, &request).and_then(|user| {
+        let mail = if args.verified {
+            server::rest::verification::mark_verified(&mut state, user.user_id)?;
+            None
+        } else {
+            Some(server::rest::verification::create_verification(&mut state, &user)?)
+        };
+        Ok((user, mail))
+    });
+
+    match created {
+        Ok((user, mail)) => {
+            println!("Created user {} ({})", user.username, user.user_id);
+            // The process exits right after, so the mail is sent inline rather than dispatched
+            if let Some(mail) = mail {
+                if let Err(err) = state.mailer.send(mail).await {
+                    eprintln!("Failed to send the verification mail: {err}");
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to create user: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// A `*` entry keeps the permissive behaviour, otherwise only the listed origins are allowed
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    if origins.iter().any(|origin| origin == "*") {
+        return CorsLayer::permissive();
+    }
+
+    let origins: Vec<HeaderValue> = origins.iter()
+        .filter_map(|origin| HeaderValue::from_str(origin).ok())
+        .collect();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+        .allow_headers(Any)
 }
 
 pub struct AppState {
+    pub config: Config,
     pub gateway: Gateway,
-    pub packet_queue: DashMap<i64, Sender<Box<dyn Packet + Send>>>,
+    pub packet_queue: PacketQueue,
     pub database: PgConnection,
     pub jwt_key: Hmac<Sha256>,
     pub argon: Argon2<'static>,
-    pub argon_salt: SaltString,
-    pub snowflake_issuer: SnowflakeIssuer
+    // Verified against when a login names no account, so that takes as long as a wrong password
+    pub dummy_password_hash: String,
+    pub snowflake_issuer: SnowflakeIssuer,
+    pub mailer: SharedMailer
+}
+
+impl AppState {
+    pub fn new(config: Config, database: PgConnection, mailer: SharedMailer) -> AppState {
+        let jwt_key = Hmac::<Sha256>::new_from_slice(config.auth.jwt_secret.as_bytes())
+            .expect("Failed to create HMAC");
+        let argon = Argon2::new(Algorithm::Argon2id, Version::V0x13, config.argon_params());
+        let dummy_password_hash = hash_password(&argon, "not a real password").expect("Failed to hash dummy password");
+
+        let mut gateway = Gateway::new();
+        gateway.register_handler(Box::new(server::gateway::receipts::ReceiptGatewayHandler));
+        gateway.register_handler(Box::new(server::gateway::typing::TypingGatewayHandler));
+
+        AppState {
+            gateway,
+            packet_queue: DashMap::new(),
+            database,
+            jwt_key,
+            argon,
+            dummy_password_hash,
+            snowflake_issuer: SnowflakeIssuer::new(config.snowflake.issuer_id, config.snowflake.worker_id),
+            mailer,
+            config
+        }
+    }
 }
 
 unsafe impl Sync for AppState {}
 unsafe impl Send for AppState {}
 
-pub type SharedState = Arc<RwLock<AppState>>;
\ No newline at end of file
+pub type SharedState = Arc<RwLock<AppState>>;
@@ -0,0 +1,19 @@
+use chrono::NaiveDateTime;
+use diesel::{Identifiable, Insertable, Queryable, Selectable};
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = user_blocks)]
+#[diesel(primary_key(blocker_id, blocked_id))]
+pub struct UserBlock {
+    pub blocker_id: i64,
+    pub blocked_id: i64,
+    pub created_at: NaiveDateTime
+}
+
+diesel::table! {
+    user_blocks (blocker_id, blocked_id) {
+        blocker_id -> BigInt,
+        blocked_id -> BigInt,
+        created_at -> Timestamp
+    }
+}
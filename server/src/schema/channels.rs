@@ -1,5 +1,5 @@
 use diesel::sql_types::BigInt;
-use diesel::{Identifiable, Queryable, QueryableByName, Selectable, table};
+use diesel::{AsChangeset, Identifiable, Queryable, QueryableByName, Selectable, table};
 use crate::schema::users::users;
 
 #[derive(Queryable, Identifiable)]
@@ -8,6 +8,7 @@ use crate::schema::users::users;
 pub struct Channel {
     pub channel_id: i64,
     pub channel_type: i32,
+    pub last_message_id: Option<i64>
 }
 
 #[derive(Queryable, Identifiable, Selectable)]
@@ -22,6 +23,7 @@ table! {
     channels (channel_id) {
         channel_id -> BigInt,
         channel_type -> Integer,
+        last_message_id -> Nullable<BigInt>,
     }
 }
 
@@ -30,12 +32,26 @@ table! {
         channel_id -> BigInt,
         user_id -> BigInt,
         joined_at -> Timestamp,
+        accepted -> Bool,
+        pinned -> Bool,
+        archived -> Bool,
+        hidden -> Bool,
+        unread_count -> Integer,
+        last_read_message_id -> Nullable<BigInt>,
     }
 }
 
 diesel::joinable!(channel_members -> channels (channel_id));
 diesel::joinable!(channel_members -> users (user_id));
 
+#[derive(AsChangeset)]
+#[diesel(table_name = channel_members)]
+pub struct ConversationChangeset {
+    pub pinned: Option<bool>,
+    pub archived: Option<bool>,
+    pub hidden: Option<bool>
+}
+
 #[derive(Queryable, QueryableByName)]
 pub struct PrivateChannelQuery {
     #[diesel(sql_type = BigInt)]
@@ -0,0 +1,55 @@
+use chrono::NaiveDateTime;
+use diesel::{Identifiable, Insertable, Queryable, Selectable};
+use crate::schema::users::users;
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = credentials)]
+#[diesel(primary_key(credential_id))]
+pub struct Credential {
+    pub credential_id: i64,
+    pub user_id: i64,
+    pub external_id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>
+}
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = webauthn_challenges)]
+#[diesel(primary_key(challenge_id))]
+pub struct WebauthnChallenge {
+    pub challenge_id: i64,
+    pub user_id: Option<i64>,
+    // `registration` or `authentication`
+    pub ceremony: String,
+    pub challenge: String,
+    pub expires_at: NaiveDateTime
+}
+
+diesel::table! {
+    credentials (credential_id) {
+        credential_id -> BigInt,
+        user_id -> BigInt,
+        external_id -> Varchar,
+        public_key -> Bytea,
+        sign_count -> BigInt,
+        name -> Varchar,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>
+    }
+}
+
+diesel::table! {
+    webauthn_challenges (challenge_id) {
+        challenge_id -> BigInt,
+        user_id -> Nullable<BigInt>,
+        ceremony -> Varchar,
+        challenge -> Varchar,
+        expires_at -> Timestamp
+    }
+}
+
+diesel::joinable!(credentials -> users (user_id));
+diesel::joinable!(webauthn_challenges -> users (user_id));
@@ -0,0 +1,57 @@
+use diesel::{Identifiable, Insertable, Queryable, Selectable};
+use serde::{Deserialize, Serialize};
+use crate::schema::channels::channels;
+use crate::schema::users::users;
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = custom_emoji)]
+#[diesel(primary_key(emoji_id))]
+pub struct CustomEmoji {
+    pub emoji_id: i64,
+    pub name: String,
+    pub owner_id: i64,
+    pub channel_id: Option<i64>,
+    pub content_type: String,
+    pub animated: bool
+}
+
+impl CustomEmoji {
+    // User-scoped emojis follow their owner, channel-scoped ones stay in their channel
+    pub fn usable_by(&self, user_id: i64, channel_id: i64) -> bool {
+        match self.channel_id {
+            Some(emoji_channel_id) => emoji_channel_id == channel_id,
+            None => self.owner_id == user_id
+        }
+    }
+}
+
+diesel::table! {
+    custom_emoji (emoji_id) {
+        emoji_id -> BigInt,
+        name -> Varchar,
+        owner_id -> BigInt,
+        channel_id -> Nullable<BigInt>,
+        content_type -> Varchar,
+        animated -> Bool
+    }
+}
+
+diesel::joinable!(custom_emoji -> users (owner_id));
+diesel::joinable!(custom_emoji -> channels (channel_id));
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CustomEmojiSummary {
+    pub id: i64,
+    pub name: String,
+    pub animated: bool
+}
+
+impl From<CustomEmoji> for CustomEmojiSummary {
+    fn from(emoji: CustomEmoji) -> Self {
+        CustomEmojiSummary {
+            id: emoji.emoji_id,
+            name: emoji.name,
+            animated: emoji.animated
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use chrono::NaiveDateTime;
+use diesel::{Identifiable, Insertable, Queryable, Selectable};
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = contact_nicknames)]
+#[diesel(primary_key(user_id, contact_id))]
+pub struct ContactNickname {
+    pub user_id: i64,
+    pub contact_id: i64,
+    pub nickname: String
+}
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = folders)]
+#[diesel(primary_key(folder_id))]
+pub struct Folder {
+    pub folder_id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub created_at: NaiveDateTime
+}
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = folder_channels)]
+#[diesel(primary_key(folder_id, channel_id))]
+pub struct FolderChannel {
+    pub folder_id: i64,
+    pub channel_id: i64
+}
+
+diesel::table! {
+    contact_nicknames (user_id, contact_id) {
+        user_id -> BigInt,
+        contact_id -> BigInt,
+        nickname -> Varchar
+    }
+}
+
+diesel::table! {
+    folders (folder_id) {
+        folder_id -> BigInt,
+        user_id -> BigInt,
+        name -> Varchar,
+        created_at -> Timestamp
+    }
+}
+
+diesel::table! {
+    folder_channels (folder_id, channel_id) {
+        folder_id -> BigInt,
+        channel_id -> BigInt
+    }
+}
+
+diesel::joinable!(folder_channels -> folders (folder_id));
@@ -0,0 +1,36 @@
+use chrono::NaiveDateTime;
+use diesel::{Identifiable, Insertable, Queryable, Selectable};
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = friend_requests)]
+#[diesel(primary_key(sender_id, recipient_id))]
+pub struct FriendRequest {
+    pub sender_id: i64,
+    pub recipient_id: i64,
+    pub created_at: NaiveDateTime
+}
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = friendships)]
+#[diesel(primary_key(user_id, friend_id))]
+pub struct Friendship {
+    pub user_id: i64,
+    pub friend_id: i64,
+    pub created_at: NaiveDateTime
+}
+
+diesel::table! {
+    friend_requests (sender_id, recipient_id) {
+        sender_id -> BigInt,
+        recipient_id -> BigInt,
+        created_at -> Timestamp
+    }
+}
+
+diesel::table! {
+    friendships (user_id, friend_id) {
+        user_id -> BigInt,
+        friend_id -> BigInt,
+        created_at -> Timestamp
+    }
+}
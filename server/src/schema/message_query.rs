@@ -0,0 +1,351 @@
+use diesel::pg::Pg;
+use diesel::sql_types::{Array, BigInt};
+use diesel::{sql_query, PgConnection, QueryResult, RunQueryDsl};
+
+use crate::schema::messages::CompleteMessage;
+
+// Reactions are aggregated per returned message, through the (message_id, emoji) and (reaction_id, user_id)
+// unique indexes, so the cost follows the size of the page rather than of the reaction tables
+const REACTIONS: &str = r#"
+    COALESCE(
+        (
+            SELECT
+                json_agg(
+                    json_build_object(
+                        'reaction_id', r.reaction_id,
+                        'count', r.reaction_count,
+                        'me', EXISTS (
+                            SELECT 1
+                            FROM reaction_users ru
+                            WHERE ru.reaction_id = r.reaction_id AND ru.user_id = $1
+                        ),
+                        'emoji', r.emoji,
+                        'custom_emoji', CASE WHEN r.custom_emoji_id IS NULL THEN NULL ELSE
+                            json_build_object(
+                                'id', r.custom_emoji_id,
+                                'name', ce.name,
+                                'animated', ce.animated
+                            )
+                        END
+                    )
+                    ORDER BY r.reaction_id
+                )
+            FROM reactions r
+            LEFT JOIN custom_emoji ce ON r.custom_emoji_id = ce.emoji_id
+            WHERE r.message_id = qm.message_id AND r.reaction_count > 0
+        ),
+        '[]'::json
+    )::TEXT AS reactions"#;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageFilter {
+    Channel(i64),
+    Ids(Vec<i64>),
+    Before(i64),
+    After(i64),
+    Author(i64)
+}
+
+impl MessageFilter {
+    fn condition(&self, parameter: usize) -> String {
+        match self {
+            MessageFilter::Channel(_) => format!("m.channel_id = ${}", parameter),
+            MessageFilter::Ids(_) => format!("m.message_id = ANY(${})", parameter),
+            MessageFilter::Before(_) => format!("m.message_id < ${}", parameter),
+            MessageFilter::After(_) => format!("m.message_id > ${}", parameter),
+            MessageFilter::Author(_) => format!("m.user_id = ${}", parameter)
+        }
+    }
+}
+
+// Messages as seen by a user: `$1` is always the viewer, every filter binds its own parameter after it.
+// Results are newest first, and what gets joined in depends on the projections asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageQuery {
+    viewer: i64,
+    filters: Vec<MessageFilter>,
+    limit: Option<i64>,
+    author: bool,
+    reactions: bool,
+    reply_preview: bool
+}
+
+impl MessageQuery {
+    pub fn viewed_by(viewer: i64) -> MessageQuery {
+        MessageQuery {
+            viewer,
+            filters: Vec::new(),
+            limit: None,
+            author: false,
+            reactions: false,
+            reply_preview: false
+        }
+    }
+
+    pub fn in_channel(self, channel_id: i64) -> MessageQuery {
+        self.filter(MessageFilter::Channel(channel_id))
+    }
+
+    pub fn id(self, message_id: i64) -> MessageQuery {
+        self.ids(&[message_id])
+    }
+
+    pub fn ids(self, message_ids: &[i64]) -> MessageQuery {
+        self.filter(MessageFilter::Ids(message_ids.to_vec()))
+    }
+
+    pub fn before(self, message_id: i64) -> MessageQuery {
+        self.filter(MessageFilter::Before(message_id))
+    }
+
+    pub fn after(self, message_id: i64) -> MessageQuery {
+        self.filter(MessageFilter::After(message_id))
+    }
+
+    pub fn by_author(self, user_id: i64) -> MessageQuery {
+        self.filter(MessageFilter::Author(user_id))
+    }
+
+    pub fn filter(mut self, filter: MessageFilter) -> MessageQuery {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> MessageQuery {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn with_author(mut self) -> MessageQuery {
+        self.author = true;
+        self
+    }
+
+    pub fn with_reactions(mut self) -> MessageQuery {
+        self.reactions = true;
+        self
+    }
+
+    pub fn with_reply_preview(mut self) -> MessageQuery {
+        self.reply_preview = true;
+        self
+    }
+
+    // Everything a message object is built from
+    pub fn complete(self) -> MessageQuery {
+        self.with_author().with_reactions().with_reply_preview()
+    }
+
+    pub fn to_sql(&self) -> String {
+        let conditions = self.filters.iter().enumerate()
+            .map(|(index, filter)| filter.condition(index + 2))
+            .collect::<Vec<_>>();
+        let conditions = match conditions.is_empty() {
+            true => String::from("TRUE"),
+            false => conditions.join(" AND ")
+        };
+        // Pages after a message start right after it, they're only flipped back to newest first once limited
+        let ascending = self.filters.iter().any(|filter| matches!(filter, MessageFilter::After(_)))
+            && !self.filters.iter().any(|filter| matches!(filter, MessageFilter::Before(_)));
+        let limit = match self.limit {
+            Some(_) => format!("\n    LIMIT ${}", self.filters.len() + 2),
+            None => String::new()
+        };
+
+        let mut columns = vec![
+            "qm.message_id", "qm.user_id", "qm.content", "qm.channel_id", "qm.reception_status", "qm.edited", "qm.reply_to"
+        ];
+        let mut joins = Vec::new();
+        match self.author {
+            true => {
+                columns.push("u.name::TEXT AS author_name");
+                columns.push("u.username::TEXT AS author_username");
+                joins.push("LEFT JOIN users u ON qm.user_id = u.user_id");
+            }
+            false => {
+                columns.push("NULL::TEXT AS author_name");
+                columns.push("NULL::TEXT AS author_username");
+            }
+        }
+        match self.reactions {
+            true => columns.push(REACTIONS.trim_start()),
+            false => columns.push("NULL::TEXT AS reactions")
+        }
+        match self.reply_preview {
+            true => {
+                columns.push("rm.user_id AS reply_user_id");
+                columns.push("rm.content AS reply_content");
+                joins.push("LEFT JOIN messages rm ON qm.reply_to = rm.message_id");
+            }
+            false => {
+                columns.push("NULL::BIGINT AS reply_user_id");
+                columns.push("NULL::TEXT AS reply_content");
+            }
+        }
+
+        format!(r#"
+WITH querying_messages AS (
+    SELECT m.*
+    FROM messages m
+    WHERE {}
+    ORDER BY m.message_id {}{}
+)
+SELECT
+    {}
+FROM querying_messages qm
+{}
+ORDER BY
+    qm.message_id DESC
+"#,
+            conditions,
+            if ascending { "ASC" } else { "DESC" },
+            limit,
+            columns.join(",\n    "),
+            joins.join("\n")
+        )
+    }
+
+    pub fn load(&self, connection: &mut PgConnection) -> QueryResult<Vec<CompleteMessage>> {
+        let mut query = sql_query(self.to_sql()).into_boxed::<Pg>()
+            .bind::<BigInt, _>(self.viewer);
+        for filter in &self.filters {
+            query = match filter {
+                MessageFilter::Ids(ids) => query.bind::<Array<BigInt>, _>(ids.clone()),
+                MessageFilter::Channel(value)
+                | MessageFilter::Before(value)
+                | MessageFilter::After(value)
+                | MessageFilter::Author(value) => query.bind::<BigInt, _>(*value)
+            };
+        }
+        if let Some(limit) = self.limit {
+            query = query.bind::<BigInt, _>(limit);
+        }
+        query.load::<CompleteMessage>(connection)
+    }
+
+    pub fn first(&self, connection: &mut PgConnection) -> QueryResult<CompleteMessage> {
+        self.load(connection)?.into_iter().next().ok_or(diesel::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line_after<'a>(sql: &'a str, prefix: &str) -> &'a str {
+        sql.lines()
+            .map(str::trim)
+            .find_map(|line| line.strip_prefix(prefix))
+            .unwrap_or_else(|| panic!("No `{}` in {}", prefix, sql))
+    }
+
+    fn conditions(query: &MessageQuery) -> String {
+        line_after(&query.to_sql(), "WHERE ").to_string()
+    }
+
+    fn inner_order(query: &MessageQuery) -> String {
+        line_after(&query.to_sql(), "ORDER BY m.message_id ").to_string()
+    }
+
+    #[test]
+    fn unfiltered_query_matches_everything_newest_first() {
+        let sql = MessageQuery::viewed_by(1).to_sql();
+        assert_eq!(line_after(&sql, "WHERE "), "TRUE");
+        assert_eq!(line_after(&sql, "ORDER BY m.message_id "), "DESC");
+        assert!(!sql.contains("LIMIT"));
+        assert!(!sql.contains('$'), "nothing should be bound besides the viewer, which isn't used: {}", sql);
+        assert!(sql.trim_end().ends_with("ORDER BY\n    qm.message_id DESC"));
+    }
+
+    #[test]
+    fn each_filter_binds_the_parameter_after_the_viewer() {
+        let cases = [
+            (MessageFilter::Channel(7), "m.channel_id = $2"),
+            (MessageFilter::Ids(vec![7, 8]), "m.message_id = ANY($2)"),
+            (MessageFilter::Before(7), "m.message_id < $2"),
+            (MessageFilter::After(7), "m.message_id > $2"),
+            (MessageFilter::Author(7), "m.user_id = $2")
+        ];
+        for (filter, expected) in cases {
+            let query = MessageQuery::viewed_by(1).filter(filter);
+            assert_eq!(conditions(&query), expected);
+            assert!(query.limit(10).to_sql().contains("\n    LIMIT $3\n"));
+        }
+    }
+
+    #[test]
+    fn filters_are_numbered_in_order_with_the_limit_last() {
+        let query = MessageQuery::viewed_by(1)
+            .in_channel(2)
+            .before(3)
+            .after(4)
+            .by_author(5)
+            .ids(&[6])
+            .limit(50);
+        assert_eq!(
+            conditions(&query),
+            "m.channel_id = $2 AND m.message_id < $3 AND m.message_id > $4 AND m.user_id = $5 AND m.message_id = ANY($6)"
+        );
+        assert!(query.to_sql().contains("\n    LIMIT $7\n"));
+
+        let query = MessageQuery::viewed_by(1).id(6).in_channel(2);
+        assert_eq!(conditions(&query), "m.message_id = ANY($2) AND m.channel_id = $3");
+        assert!(!query.to_sql().contains("LIMIT"));
+    }
+
+    #[test]
+    fn only_pages_after_a_message_are_taken_oldest_first() {
+        assert_eq!(inner_order(&MessageQuery::viewed_by(1).after(4)), "ASC");
+        assert_eq!(inner_order(&MessageQuery::viewed_by(1).in_channel(2).after(4).limit(50)), "ASC");
+        assert_eq!(inner_order(&MessageQuery::viewed_by(1).before(3)), "DESC");
+        assert_eq!(inner_order(&MessageQuery::viewed_by(1).before(3).after(4)), "DESC");
+        assert_eq!(inner_order(&MessageQuery::viewed_by(1).after(4).before(3)), "DESC");
+        assert_eq!(inner_order(&MessageQuery::viewed_by(1).in_channel(2)), "DESC");
+
+        // Whatever order the page was taken in, it's returned newest first
+        let sql = MessageQuery::viewed_by(1).after(4).to_sql();
+        assert!(sql.trim_end().ends_with("ORDER BY\n    qm.message_id DESC"));
+    }
+
+    #[test]
+    fn projections_left_out_are_null() {
+        let sql = MessageQuery::viewed_by(1).in_channel(2).to_sql();
+        for column in [
+            "NULL::TEXT AS author_name",
+            "NULL::TEXT AS author_username",
+            "NULL::TEXT AS reactions",
+            "NULL::BIGINT AS reply_user_id",
+            "NULL::TEXT AS reply_content"
+        ] {
+            assert!(sql.contains(column), "missing `{}` in {}", column, sql);
+        }
+        assert!(!sql.contains("JOIN"));
+        assert!(!sql.contains("$1"));
+    }
+
+    #[test]
+    fn each_projection_adds_its_columns_and_join() {
+        let sql = MessageQuery::viewed_by(1).with_author().to_sql();
+        assert!(sql.contains("u.name::TEXT AS author_name") && sql.contains("u.username::TEXT AS author_username"));
+        assert!(sql.contains("LEFT JOIN users u ON qm.user_id = u.user_id"));
+        assert!(sql.contains("NULL::TEXT AS reactions") && sql.contains("NULL::BIGINT AS reply_user_id"));
+
+        let sql = MessageQuery::viewed_by(1).with_reactions().to_sql();
+        assert!(sql.contains("json_agg(") && sql.contains("::TEXT AS reactions"));
+        assert!(sql.contains("ru.user_id = $1"), "reactions should be checked against the viewer");
+        assert!(sql.contains("NULL::TEXT AS author_name") && !sql.contains("LEFT JOIN users"));
+
+        let sql = MessageQuery::viewed_by(1).with_reply_preview().to_sql();
+        assert!(sql.contains("rm.user_id AS reply_user_id") && sql.contains("rm.content AS reply_content"));
+        assert!(sql.contains("LEFT JOIN messages rm ON qm.reply_to = rm.message_id"));
+        assert!(sql.contains("NULL::TEXT AS author_name") && sql.contains("NULL::TEXT AS reactions"));
+    }
+
+    #[test]
+    fn complete_query_projects_everything() {
+        let sql = MessageQuery::viewed_by(1).id(2).complete().to_sql();
+        assert!(!sql.contains("NULL::"), "{}", sql);
+        assert!(sql.contains("LEFT JOIN users u") && sql.contains("LEFT JOIN messages rm") && sql.contains("json_agg("));
+        assert_eq!(conditions(&MessageQuery::viewed_by(1).id(2).complete()), "m.message_id = ANY($2)");
+    }
+}
@@ -1,4 +1,4 @@
-use diesel::sql_types::{Nullable, Text, BigInt, SmallInt, VarChar};
+use diesel::sql_types::{Array, Nullable, Text, BigInt, SmallInt};
 use diesel::{Associations, Identifiable, Insertable, Queryable, QueryableByName, Selectable};
 use crate::schema::users::users;
 use crate::User;
@@ -38,12 +38,20 @@ diesel::joinable!(messages -> users (user_id));
 pub struct ContactWithChannel {
     #[diesel(sql_type = BigInt)]
     pub user_id: i64,
-    #[diesel(sql_type = BigInt)]
-    pub channel_id: i64,
+    #[diesel(sql_type = Nullable<BigInt>)]
+    pub channel_id: Option<i64>,
     #[diesel(sql_type = Text)]
     pub name: String,
     #[diesel(sql_type = Text)]
     pub username: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub nickname: Option<String>,
+    #[diesel(sql_type = Array<BigInt>)]
+    pub folder_ids: Vec<i64>,
+    #[diesel(sql_type = Bool)]
+    pub pinned: bool,
+    #[diesel(sql_type = Bool)]
+    pub archived: bool,
     #[diesel(sql_type = Nullable<BigInt>)]
     pub message_id: Option<i64>,
     #[diesel(sql_type = Nullable<Text>)]
@@ -70,10 +78,15 @@ pub struct CompleteMessage {
     pub edited: bool,
     #[diesel(sql_type = Nullable<BigInt>)]
     pub reply_to: Option<i64>,
-    #[diesel(sql_type = VarChar)]
-    pub author_name: String,
-    #[diesel(sql_type = VarChar)]
-    pub author_username: String,
-    #[diesel(sql_type = Text)]
-    pub reactions: String
+    // Left empty unless the query projects them
+    #[diesel(sql_type = Nullable<Text>)]
+    pub author_name: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub author_username: Option<String>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub reactions: Option<String>,
+    #[diesel(sql_type = Nullable<BigInt>)]
+    pub reply_user_id: Option<i64>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub reply_content: Option<String>
 }
\ No newline at end of file
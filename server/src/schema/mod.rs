@@ -5,20 +5,64 @@ pub mod users;
 pub mod messages;
 pub mod reactions;
 pub mod channels;
-pub mod ctes;
+pub mod message_query;
+pub mod emojis;
+pub mod sessions;
+pub mod verifications;
+pub mod resets;
+pub mod two_factor;
+pub mod credentials;
+pub mod blocks;
+pub mod friends;
+pub mod settings;
+pub mod folders;
 
 use crate::schema::users::users as users_table;
+use crate::schema::users::username_history as username_history_table;
 use crate::schema::channels::channels as channels_table;
 use crate::schema::channels::channel_members as channel_members_table;
 use crate::schema::messages::messages as messages_table;
 use crate::schema::reactions::reactions as reactions_table;
 use crate::schema::reactions::reaction_users as reaction_users_table;
+use crate::schema::emojis::custom_emoji as custom_emoji_table;
+use crate::schema::sessions::sessions as sessions_table;
+use crate::schema::verifications::email_verifications as email_verifications_table;
+use crate::schema::resets::password_resets as password_resets_table;
+use crate::schema::two_factor::two_factor as two_factor_table;
+use crate::schema::two_factor::recovery_codes as recovery_codes_table;
+use crate::schema::two_factor::login_challenges as login_challenges_table;
+use crate::schema::credentials::credentials as credentials_table;
+use crate::schema::credentials::webauthn_challenges as webauthn_challenges_table;
+use crate::schema::blocks::user_blocks as user_blocks_table;
+use crate::schema::friends::friend_requests as friend_requests_table;
+use crate::schema::friends::friendships as friendships_table;
+use crate::schema::settings::user_settings as user_settings_table;
+use crate::schema::folders::contact_nicknames as contact_nicknames_table;
+use crate::schema::folders::folders as folders_table;
+use crate::schema::folders::folder_channels as folder_channels_table;
 
 allow_tables_to_appear_in_same_query!(
     users_table,
+    username_history_table,
     channels_table,
     channel_members_table,
     messages_table,
     reactions_table,
-    reaction_users_table
+    reaction_users_table,
+    custom_emoji_table,
+    sessions_table,
+    email_verifications_table,
+    password_resets_table,
+    two_factor_table,
+    recovery_codes_table,
+    login_challenges_table,
+    credentials_table,
+    webauthn_challenges_table,
+    user_blocks_table,
+    friend_requests_table,
+    friendships_table,
+    user_settings_table,
+    contact_nicknames_table,
+    folders_table,
+    folder_channels_table
 );
\ No newline at end of file
@@ -1,9 +1,8 @@
 use diesel::{Identifiable, Insertable, Queryable, Selectable};
 use crate::schema::messages::messages;
 use crate::schema::users::users;
-use diesel::sql_types::{Text, Integer, Bool, Serial};
-use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use crate::schema::emojis::{custom_emoji, CustomEmojiSummary};
 
 #[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
 #[diesel(belongs_to(Message))]
@@ -13,7 +12,8 @@ pub struct Reaction {
     pub reaction_id: i32,
     pub message_id: i64,
     pub emoji: String,
-    pub reaction_count: i32
+    pub reaction_count: i32,
+    pub custom_emoji_id: Option<i64>
 }
 
 diesel::table! {
@@ -21,7 +21,8 @@ diesel::table! {
         reaction_id -> Serial,
         message_id -> BigInt,
         emoji -> Varchar,
-        reaction_count -> Integer
+        reaction_count -> Integer,
+        custom_emoji_id -> Nullable<BigInt>
     }
 }
 
@@ -46,6 +47,7 @@ diesel::table! {
 }
 
 diesel::joinable!(reactions -> messages (message_id));
+diesel::joinable!(reactions -> custom_emoji (custom_emoji_id));
 diesel::joinable!(reaction_users -> reactions (reaction_id));
 diesel::joinable!(reaction_users -> users (user_id));
 
@@ -54,6 +56,8 @@ diesel::joinable!(reaction_users -> users (user_id));
 pub struct ReactionInsert {
     pub message_id: i64,
     pub emoji: String,
+    pub reaction_count: i32,
+    pub custom_emoji_id: Option<i64>
 }
 
 #[derive(Insertable)]
@@ -63,16 +67,14 @@ pub struct ReactionUserInsert {
     pub user_id: i64
 }
 
-#[derive(Debug, Serialize, Deserialize, QueryableByName, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ReactionSummary {
-    #[diesel(sql_type = Text)]
     pub emoji: String,
-    #[diesel(sql_type = Integer)]
     pub count: i32,
-    #[diesel(sql_type = Bool)]
     pub me: bool,
-    #[diesel(sql_type = Serial)]
-    pub reaction_id: i32
+    pub reaction_id: i32,
+    #[serde(default)]
+    pub custom_emoji: Option<CustomEmojiSummary>
 }
 
 
@@ -0,0 +1,24 @@
+use chrono::NaiveDateTime;
+use diesel::{Identifiable, Insertable, Queryable, Selectable};
+use crate::schema::users::users;
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = password_resets)]
+#[diesel(primary_key(reset_id))]
+pub struct PasswordReset {
+    pub reset_id: i64,
+    pub user_id: i64,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime
+}
+
+diesel::table! {
+    password_resets (reset_id) {
+        reset_id -> BigInt,
+        user_id -> BigInt,
+        token_hash -> Varchar,
+        expires_at -> Timestamp
+    }
+}
+
+diesel::joinable!(password_resets -> users (user_id));
@@ -0,0 +1,36 @@
+use chrono::NaiveDateTime;
+use diesel::{Identifiable, Insertable, Queryable, Selectable};
+use crate::schema::users::users;
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = sessions)]
+#[diesel(primary_key(session_id))]
+pub struct Session {
+    pub session_id: i64,
+    pub user_id: i64,
+    pub refresh_token_hash: String,
+    pub previous_token_hash: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub last_used_at: NaiveDateTime
+}
+
+diesel::table! {
+    sessions (session_id) {
+        session_id -> BigInt,
+        user_id -> BigInt,
+        refresh_token_hash -> Varchar,
+        previous_token_hash -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        device_name -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        ip_address -> Nullable<Varchar>,
+        last_used_at -> Timestamp
+    }
+}
+
+diesel::joinable!(sessions -> users (user_id));
@@ -0,0 +1,45 @@
+use diesel::{AsChangeset, Identifiable, Insertable, Queryable, Selectable};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, AsChangeset, Clone)]
+#[diesel(table_name = user_settings)]
+#[diesel(primary_key(user_id))]
+pub struct UserSettings {
+    pub user_id: i64,
+    pub dm_privacy: i16
+}
+
+// Who can start a conversation with the user and message them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DmPrivacy {
+    #[default]
+    Everyone,
+    Contacts,
+    Nobody
+}
+
+impl DmPrivacy {
+    pub fn from_i16(value: i16) -> DmPrivacy {
+        match value {
+            1 => DmPrivacy::Contacts,
+            2 => DmPrivacy::Nobody,
+            _ => DmPrivacy::Everyone
+        }
+    }
+
+    pub fn as_i16(&self) -> i16 {
+        match self {
+            DmPrivacy::Everyone => 0,
+            DmPrivacy::Contacts => 1,
+            DmPrivacy::Nobody => 2
+        }
+    }
+}
+
+diesel::table! {
+    user_settings (user_id) {
+        user_id -> BigInt,
+        dm_privacy -> SmallInt
+    }
+}
@@ -0,0 +1,66 @@
+use chrono::NaiveDateTime;
+use diesel::{Identifiable, Insertable, Queryable, Selectable};
+use crate::schema::users::users;
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = two_factor)]
+#[diesel(primary_key(user_id))]
+pub struct TwoFactor {
+    pub user_id: i64,
+    // Base32, as shown to authenticator apps
+    pub secret: String,
+    pub confirmed: bool,
+    // Time step of the last accepted code, so a code can't be replayed
+    pub last_used_step: i64
+}
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = recovery_codes)]
+#[diesel(primary_key(code_id))]
+pub struct RecoveryCode {
+    pub code_id: i64,
+    pub user_id: i64,
+    pub code_hash: String
+}
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = login_challenges)]
+#[diesel(primary_key(challenge_id))]
+pub struct LoginChallenge {
+    pub challenge_id: i64,
+    pub user_id: i64,
+    pub token_hash: String,
+    pub attempts: i32,
+    pub expires_at: NaiveDateTime
+}
+
+diesel::table! {
+    two_factor (user_id) {
+        user_id -> BigInt,
+        secret -> Varchar,
+        confirmed -> Bool,
+        last_used_step -> BigInt
+    }
+}
+
+diesel::table! {
+    recovery_codes (code_id) {
+        code_id -> BigInt,
+        user_id -> BigInt,
+        code_hash -> Varchar
+    }
+}
+
+diesel::table! {
+    login_challenges (challenge_id) {
+        challenge_id -> BigInt,
+        user_id -> BigInt,
+        token_hash -> Varchar,
+        attempts -> Integer,
+        expires_at -> Timestamp
+    }
+}
+
+diesel::joinable!(two_factor -> users (user_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(login_challenges -> users (user_id));
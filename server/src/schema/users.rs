@@ -1,4 +1,5 @@
-use diesel::{Identifiable, Insertable, Queryable, Selectable};
+use chrono::NaiveDateTime;
+use diesel::{AsChangeset, Identifiable, Insertable, Queryable, Selectable};
 
 #[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
 #[diesel(table_name = users)]
@@ -9,6 +10,31 @@ pub struct User {
     pub username: String,
     pub password: String,
     pub email: String,
+    pub email_verified: bool,
+    pub two_factor_enabled: bool,
+    pub bio: Option<String>,
+    pub pronouns: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    // Changes with every upload, so avatar URLs can be cached forever
+    pub avatar_id: Option<i64>
+}
+
+impl User {
+    pub fn avatar_url(&self) -> Option<String> {
+        self.avatar_id.map(|avatar_id| format!("/avatars/{}/{}", self.user_id, avatar_id))
+    }
+}
+
+// `None` leaves a field as is, `Some(None)` clears it
+#[derive(Debug, AsChangeset, Default)]
+#[diesel(table_name = users)]
+pub struct ProfileChangeset {
+    pub name: Option<String>,
+    pub bio: Option<Option<String>>,
+    pub pronouns: Option<Option<String>>,
+    pub status_text: Option<Option<String>>,
+    pub status_emoji: Option<Option<String>>
 }
 
 diesel::table! {
@@ -16,7 +42,38 @@ diesel::table! {
         user_id -> BigInt,
         name -> Varchar,
         username -> Varchar,
+        password -> Varchar,
         email -> Varchar,
-        password -> Varchar
+        email_verified -> Bool,
+        two_factor_enabled -> Bool,
+        bio -> Nullable<Varchar>,
+        pronouns -> Nullable<Varchar>,
+        status_text -> Nullable<Varchar>,
+        status_emoji -> Nullable<Varchar>,
+        avatar_id -> Nullable<BigInt>
     }
-}
\ No newline at end of file
+}
+// Kept for moderation, and to hold released usernames for a while
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = username_history)]
+#[diesel(primary_key(change_id))]
+pub struct UsernameChange {
+    pub change_id: i64,
+    pub user_id: i64,
+    pub username: String,
+    pub changed_at: NaiveDateTime
+}
+
+diesel::table! {
+    username_history (change_id) {
+        change_id -> BigInt,
+        user_id -> BigInt,
+        username -> Varchar,
+        changed_at -> Timestamp
+    }
+}
+
+diesel::joinable!(username_history -> users (user_id));
+
+// Usernames and emails are unique and looked up regardless of case
+diesel::define_sql_function!(fn lower(value: diesel::sql_types::Text) -> diesel::sql_types::Text);
@@ -0,0 +1,27 @@
+use chrono::NaiveDateTime;
+use diesel::{Identifiable, Insertable, Queryable, Selectable};
+use crate::schema::users::users;
+
+#[derive(Debug, Identifiable, Queryable, Selectable, Insertable, Clone)]
+#[diesel(table_name = email_verifications)]
+#[diesel(primary_key(verification_id))]
+pub struct EmailVerification {
+    pub verification_id: i64,
+    pub user_id: i64,
+    // The address being verified, so changing it later invalidates outstanding tokens
+    pub email: String,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime
+}
+
+diesel::table! {
+    email_verifications (verification_id) {
+        verification_id -> BigInt,
+        user_id -> BigInt,
+        email -> Varchar,
+        token_hash -> Varchar,
+        expires_at -> Timestamp
+    }
+}
+
+diesel::joinable!(email_verifications -> users (user_id));
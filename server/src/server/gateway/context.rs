@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use dashmap::DashMap;
 use futures_util::FutureExt;
 use tokio::sync::mpsc::Sender;
@@ -8,36 +10,104 @@ use diesel::ExpressionMethods;
 use crate::schema::channels::channel_members::dsl::channel_members;
 use crate::schema::channels::channel_members::{channel_id as table_channel_id, user_id};
 use crate::server::messages::Packet;
+use crate::server::rest::blocks::blocked_relations;
+
+// Open gateway connections, by user and then by the session they were opened with
+pub type PacketQueue = DashMap<i64, HashMap<i64, Sender<Box<dyn Packet + Send>>>>;
 
+// Members on either side of a block with `sender` are skipped
 pub async fn send_packet_to_channel<F>(
     lock: &mut RwLockWriteGuard<'_, AppState>,
     channel_id: i64,
+    sender: i64,
     packet_fn: F
 ) where F: Fn() -> Box<dyn Packet + Send> {
-    let members: Vec<i64> = {
-        let _members = channel_members
-            .filter(table_channel_id.eq(channel_id))
-            .select(user_id)
-            .load::<i64>(&mut lock.database);
-        if _members.is_err() {
-            return;
-        }
-        _members.unwrap()
+    let Ok(hidden) = blocked_relations(&mut lock.database, sender) else {
+        return;
+    };
+    let Ok(members) = channel_members
+        .filter(table_channel_id.eq(channel_id))
+        .select(user_id)
+        .load::<i64>(&mut lock.database) else {
+        return;
+    };
+    for member in members.into_iter().filter(|member| !hidden.contains(member)) {
+        send_packet_to_user(&mut lock.packet_queue, member, &packet_fn).await;
+    }
+}
+
+// Everyone sharing a channel with the user, and the user's own connections, minus blocks
+pub async fn send_packet_to_related_users<F>(
+    lock: &mut RwLockWriteGuard<'_, AppState>,
+    user: i64,
+    packet_fn: F
+) where F: Fn() -> Box<dyn Packet + Send> {
+    let Ok(hidden) = blocked_relations(&mut lock.database, user) else {
+        return;
     };
-    for member in members {
-        send_packet_to_user(&mut lock.packet_queue, member, packet_fn()).await;
+    let shared_channels: Vec<i64> = channel_members
+        .filter(user_id.eq(user))
+        .select(table_channel_id)
+        .load::<i64>(&mut lock.database)
+        .unwrap_or_default();
+    let mut related: Vec<i64> = channel_members
+        .filter(table_channel_id.eq_any(shared_channels))
+        .select(user_id)
+        .distinct()
+        .load::<i64>(&mut lock.database)
+        .unwrap_or_default();
+    if !related.contains(&user) {
+        related.push(user);
+    }
+    for member in related.into_iter().filter(|member| !hidden.contains(member)) {
+        send_packet_to_user(&mut lock.packet_queue, member, &packet_fn).await;
     }
 }
 
-pub async fn send_packet_to_user(packet_queue: &mut DashMap<i64, Sender<Box<dyn Packet + Send>>>, user: i64, packet: Box<dyn Packet + Send>) {
-    println!("Sending packet to user: {}", user);
-    if let Some(tx) = packet_queue.get(&user) {
-        println!("Context was found, now sending packet");
-        tx.send(packet).then(|result| {
+// Each of the user's connections gets its own copy of the packet
+pub async fn send_packet_to_user<F>(
+    packet_queue: &mut PacketQueue,
+    user: i64,
+    packet_fn: F
+) where F: Fn() -> Box<dyn Packet + Send> {
+    // The senders are cloned out so the map isn't locked while waiting on full queues
+    let senders: Vec<Sender<Box<dyn Packet + Send>>> = match packet_queue.get(&user) {
+        Some(connections) => connections.values().cloned().collect(),
+        None => return
+    };
+    for tx in senders {
+        tx.send(packet_fn()).then(|result| {
             if let Err(e) = result {
-                eprintln!("Failed to send message: {:?}", e);
+                tracing::warn!("Failed to send message: {:?}", e);
             }
             futures_util::future::ready(())
         }).await;
     }
-}
\ No newline at end of file
+}
+
+// A session only keeps its latest connection, replacing the sender closes the previous one
+pub fn connect_session(packet_queue: &PacketQueue, user: i64, session: i64, tx: Sender<Box<dyn Packet + Send>>) {
+    packet_queue.entry(user).or_default().insert(session, tx);
+}
+
+// Called once a socket is gone, unless a newer connection of the same session took its place
+pub fn release_session(packet_queue: &PacketQueue, user: i64, session: i64, tx: &Sender<Box<dyn Packet + Send>>) {
+    if let Some(mut connections) = packet_queue.get_mut(&user) {
+        if connections.get(&session).is_some_and(|current| current.same_channel(tx)) {
+            connections.remove(&session);
+        }
+    }
+    packet_queue.remove_if(&user, |_, connections| connections.is_empty());
+}
+
+// Dropping a sender ends that socket's send loop, which closes the connection
+pub fn disconnect_session(packet_queue: &PacketQueue, user: i64, session: i64) {
+    if let Some(mut connections) = packet_queue.get_mut(&user) {
+        connections.remove(&session);
+    }
+    packet_queue.remove_if(&user, |_, connections| connections.is_empty());
+}
+
+pub fn disconnect_user(packet_queue: &PacketQueue, user: i64) {
+    packet_queue.remove(&user);
+}
@@ -1,5 +1,5 @@
 use iris_macros::packet;
-use crate::server::rest::{MessageObject, StandardUser};
+use crate::server::rest::{FolderResponse, MessageObject, StandardUser, UserProfileResponse};
 // SERVERBOUND
 
 #[packet(id = 1)]
@@ -19,6 +19,7 @@ pub struct MessageCreated {
     pub message: MessageObject
 }
 
+#[allow(dead_code)]
 #[packet(id = 3)]
 pub struct MessagesRead {
     pub reader_id: i64,
@@ -63,4 +64,56 @@ pub struct ReactionRemoved {
     pub reaction_count: i32,
     pub reaction_id: i32,
     pub channel_id: i64
-}
\ No newline at end of file
+}
+
+#[packet(id = 9)]
+pub struct UserUpdated {
+    pub user: UserProfileResponse
+}
+
+#[packet(id = 10)]
+pub struct FriendRequestReceived {
+    pub user: StandardUser,
+    pub created_at: i64
+}
+
+#[packet(id = 11)]
+pub struct FriendAdded {
+    pub user: StandardUser
+}
+
+// The other side declined or cancelled a pending request
+#[packet(id = 12)]
+pub struct FriendRequestRemoved {
+    pub user_id: i64
+}
+
+#[packet(id = 13)]
+pub struct FriendRemoved {
+    pub user_id: i64
+}
+
+// Nicknames and folders are private, these only go to the user's own sessions
+#[packet(id = 14)]
+pub struct ContactNicknameUpdated {
+    pub user_id: i64,
+    pub nickname: Option<String>
+}
+
+#[packet(id = 15)]
+pub struct FolderUpdated {
+    pub folder: FolderResponse
+}
+
+#[packet(id = 16)]
+pub struct FolderDeleted {
+    pub folder_id: i64
+}
+
+#[packet(id = 17)]
+pub struct ConversationUpdated {
+    pub channel_id: i64,
+    pub pinned: bool,
+    pub archived: bool,
+    pub hidden: bool
+}
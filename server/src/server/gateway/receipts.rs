@@ -1,8 +1,12 @@
 use async_trait::async_trait;
-use diesel::{ExpressionMethods, RunQueryDsl};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
 use tokio::sync::RwLockWriteGuard;
 
 use crate::AppState;
+use crate::schema::channels::channel_members::dsl::channel_members;
+use crate::schema::channels::channel_members::{channel_id as member_channel_id, last_read_message_id, unread_count, user_id as member_user_id};
+use crate::schema::channels::channels::dsl::channels;
+use crate::schema::channels::channels::{channel_id as channels_channel_id, last_message_id};
 use crate::schema::messages::messages::{channel_id, message_id as messageId, reception_status};
 use crate::schema::messages::messages::dsl::messages as messagesTable;
 use crate::schema::users::User;
@@ -11,6 +15,9 @@ use crate::server::gateway::messages::{ChannelRead};
 use crate::server::messages::{Packet, PacketMessage};
 use crate::server::messages::PacketStaticId;
 
+// TODO: Flip this when the receipts system is enabled.
+const RECEIPTS_ENABLED: bool = false;
+
 pub struct ReceiptGatewayHandler;
 
 #[async_trait]
@@ -20,15 +27,41 @@ impl GatewayHandler for ReceiptGatewayHandler {
     }
 
     async fn handle(&self, user: &User, state: &mut RwLockWriteGuard<AppState>, message: &PacketMessage) {
-        return; // TODO: Remove this line when the receipts system is enabled.
-        let request = ChannelRead::decode_data(&message.data).expect("Failed to decode context read packet");
+        let Ok(request) = ChannelRead::decode_data(&message.data) else {
+            return;
+        };
+        // The reader's own unread count is cleared whether or not receipts are shared
+        let latest = channels
+            .filter(channels_channel_id.eq(request.channel_id))
+            .select(last_message_id)
+            .single_value();
+        let cleared = diesel::update(
+            channel_members
+                .filter(member_channel_id.eq(request.channel_id))
+                .filter(member_user_id.eq(user.user_id))
+        )
+            .set((unread_count.eq(0), last_read_message_id.eq(latest)))
+            .execute(&mut state.database);
+        if let Err(e) = cleared {
+            tracing::error!("Failed to clear unread count: {:?}", e);
+        }
+
+        if !RECEIPTS_ENABLED {
+            return;
+        }
         let query = diesel::update(messagesTable)
             .filter(channel_id.eq(request.channel_id))
             .set(reception_status.eq(2))
             .returning(messageId);
-        let returns = { query.load::<i64>(&mut state.database).expect("Failed to update reception status") };
+        let _returns = match query.load::<i64>(&mut state.database) {
+            Ok(returns) => returns,
+            Err(e) => {
+                tracing::error!("Failed to update reception status: {:?}", e);
+                return;
+            }
+        };
 
-        println!("The receipts system is currently disabled.");
+        tracing::debug!("The receipts system is currently disabled.");
         // let target_tx = state.packet_queue.get(&request.channel_id);
         // if let Some(tx) = target_tx {
         //     println!("Sending messages read...");
@@ -7,6 +7,7 @@ use crate::server::gateway::context::send_packet_to_channel;
 use crate::server::gateway::GatewayHandler;
 use crate::server::gateway::messages::{ChannelTyping, TypingRequest};
 use crate::server::messages::{Packet, PacketMessage, PacketStaticId};
+use crate::server::rest::messages::is_channel_member;
 use crate::server::rest::StandardUser;
 
 pub struct TypingGatewayHandler;
@@ -18,11 +19,17 @@ impl GatewayHandler for TypingGatewayHandler {
     }
 
     async fn handle(&self, user: &User, state: &mut RwLockWriteGuard<AppState>, message: &PacketMessage) {
-        let request: TypingRequest = TypingRequest::decode_data(&message.data).expect("Failed to decode context read packet");
+        let Ok(request) = TypingRequest::decode_data(&message.data) else {
+            return;
+        };
+        if !matches!(is_channel_member(&mut state.database, request.channel_id, user.user_id), Ok(true)) {
+            return;
+        }
 
         send_packet_to_channel(
             state,
             request.channel_id,
+            user.user_id,
             || Box::new(ChannelTyping {
                 user: StandardUser {
                     id: user.user_id,
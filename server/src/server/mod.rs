@@ -11,9 +11,12 @@ use futures::{sink::SinkExt, stream::StreamExt};
 use prost::Message;
 use tokio::sync::mpsc::Receiver;
 
+use crate::schema::sessions::Session;
 use crate::schema::users::User;
 use crate::server::gateway::Gateway;
+use crate::server::gateway::context::{connect_session, release_session};
 use crate::server::messages::{encode_packet_message, Packet, PacketMessage};
+use crate::server::rest::{current_user, IrisError};
 use crate::SharedState;
 
 pub mod messages;
@@ -27,24 +30,33 @@ pub async fn subscribe_chat_handshake(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Extension(state): Extension<SharedState>,
     request: axum::http::Request<axum::body::Body>
-) -> impl IntoResponse {
-    let _ = request.headers().get("Sec-WebSocket-Protocol").and_then(|hv| {
-        Some(hv.to_str().ok().unwrap_or_default())
-    });
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
+) -> Result<impl IntoResponse, IrisError> {
+    let user = current_user(&request)?;
+    let session = request.extensions().get::<Session>().map(|session| session.session_id)
+        .ok_or_else(|| IrisError::from_status(StatusCode::UNAUTHORIZED, "Not authenticated"))?;
     let user_agent = if let Some(TypedHeader(user_agent)) = user_agent {
         user_agent.to_string()
     } else {
         String::from("Unknown browser")
     };
-    let (tx, rx) = tokio::sync::mpsc::channel(100);
-    {
-        state.write().await.packet_queue.insert(user.user_id, tx);
-    }
-    println!("`{user_agent}` at {addr} connected.");
-    let response = ws.on_upgrade(move |socket| subscribe_chat(user, state, rx, socket, addr));
+    let (weak_tx, rx) = {
+        let state = state.write().await;
+        let (tx, rx) = tokio::sync::mpsc::channel(state.config.limits.gateway_queue_size);
+        // Only the queue holds a strong sender, so removing it from there closes the socket
+        let weak = tx.downgrade();
+        connect_session(&state.packet_queue, user.user_id, session, tx);
+        (weak, rx)
+    };
+    tracing::debug!("`{user_agent}` at {addr} connected.");
+    let response = ws.on_upgrade(move |socket| async move {
+        let user_id = user.user_id;
+        subscribe_chat(user, state.clone(), rx, socket, addr).await;
+        if let Some(tx) = weak_tx.upgrade() {
+            release_session(&state.read().await.packet_queue, user_id, session, &tx);
+        }
+    });
     // The following is necessary for Chromium-based browsers
-    return (StatusCode::SWITCHING_PROTOCOLS, [("Sec-WebSocket-Protocol", "Token")], response);
+    Ok((StatusCode::SWITCHING_PROTOCOLS, [("Sec-WebSocket-Protocol", "Token")], response))
 }
 
 pub async fn subscribe_chat(connected_user: User, application: SharedState, mut rx: Receiver<Box<dyn Packet + Send>>, ws: WebSocket, addr: SocketAddr) {
@@ -52,13 +64,19 @@ pub async fn subscribe_chat(connected_user: User, application: SharedState, mut
 
     let mut send_task = tokio::spawn(async move {
         while let Some(bytes) = rx.recv().await {
-            sender.send(Binary(encode_packet_message(bytes))).await.unwrap();
+            // The socket is gone, ending this task closes the connection
+            if sender.send(Binary(encode_packet_message(bytes))).await.is_err() {
+                break;
+            }
         }
     });
 
     let mut receive_task = tokio::spawn(async move {
         while let Some(Ok(Binary(binary))) = receiver.next().await {
-            let packet = PacketMessage::decode(binary.as_slice()).unwrap();
+            // Packets that don't decode are skipped rather than taking the connection down
+            let Ok(packet) = PacketMessage::decode(binary.as_slice()) else {
+                continue;
+            };
             let mut state = application.write().await;
             let temp_gateway = std::mem::replace(&mut state.gateway, Gateway::new());
             temp_gateway.handle_packet(&connected_user, &mut state, &packet).await;
@@ -69,19 +87,19 @@ pub async fn subscribe_chat(connected_user: User, application: SharedState, mut
     tokio::select! {
         rv_a = (&mut send_task) => {
             match rv_a {
-                Ok(()) => println!("Finished message stream to {addr}"),
-                Err(a) => println!("Error sending messages {a:?}")
+                Ok(()) => tracing::debug!("Finished message stream to {addr}"),
+                Err(a) => tracing::error!("Error sending messages to {addr}: {a:?}")
             }
             receive_task.abort();
         },
         rv_b = (&mut receive_task) => {
             match rv_b {
-                Ok(()) => println!("Received in total messages from {addr}"),
-                Err(b) => println!("Error receiving messages {b:?}")
+                Ok(()) => tracing::debug!("Finished receiving messages from {addr}"),
+                Err(b) => tracing::error!("Error receiving messages from {addr}: {b:?}")
             }
             send_task.abort();
         }
     }
 
-    println!("Disconnected!");
+    tracing::debug!("{addr} disconnected");
 }
\ No newline at end of file
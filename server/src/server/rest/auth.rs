@@ -1,76 +1,353 @@
 use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
 
-use argon2::{PasswordHash, PasswordHasher, PasswordVerifier};
-use axum::{Extension, Json};
+use axum::Extension;
 use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use diesel::dsl::exists;
 use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error::DatabaseError;
 use diesel::RunQueryDsl;
 use jwt::SignWithKey;
 use serde::Deserialize;
-use crate::schema::users::User;
+use crate::schema::sessions::Session;
+use crate::schema::sessions::sessions::dsl::sessions;
+use crate::schema::sessions::sessions::{expires_at, previous_token_hash, refresh_token_hash, session_id};
+use crate::schema::users::{lower, User};
 use crate::schema::users::users::dsl::users;
 use crate::schema::users::users::username;
+use crate::schema::users::users::email as table_email;
+use crate::schema::users::username_history::dsl::username_history;
+use crate::schema::users::username_history::{changed_at, user_id as history_user_id, username as history_username};
+use crate::schema::users::users::user_id as table_user_id;
+use crate::schema::users::users::password as table_password;
 use crate::server::rest;
-use crate::server::rest::{IrisResponse, ok, UserSelfResponse, UserAuthResponse};
-use crate::SharedState;
+use crate::server::gateway::context::disconnect_user;
+use crate::server::rest::sessions::DeviceInfo;
+use crate::server::rest::two_factor::create_challenge;
+use crate::server::rest::verification::send_verification;
+use crate::server::rest::extract::JsonBody;
+use crate::server::rest::validation::{check, Validate};
+use crate::server::rest::{error_with_code, field_errors, FieldError, IrisError, IrisResponse, LoginResponse, ok, TokenResponse, UserAuthResponse, UserSelfResponse};
+use crate::util::identity::{is_email, validate_email, validate_name, validate_password, validate_username};
+use crate::util::passwords::{hash_password, verify_password, PasswordCheck};
+use crate::util::tokens::{constant_time_eq, format_token, generate_secret, hash_secret, parse_token};
+use crate::{AppState, SharedState};
 
+// Errors: invalid_body, unauthorized, internal_error
 pub async fn login(
     Extension(state): Extension<SharedState>,
-    Json(request): Json<LoginRequest>,
-) -> IrisResponse<UserAuthResponse> {
+    device: DeviceInfo,
+    JsonBody(request): JsonBody<LoginRequest>,
+) -> IrisResponse<LoginResponse> {
     let state = &mut state.write().await;
-    let a = users
-        .filter(username.eq(&request.identifier))
-        .select(User::as_select())
-        .first::<User>(&mut state.database);
-
-    if let Ok(user) = a {
-        let password_hash = PasswordHash::new(&*user.password).expect("Failed to create password hash");
-        if state.argon.verify_password(request.password.as_bytes(), &password_hash).is_ok() {
-            let mut claims = BTreeMap::new();
-            claims.insert("id", user.user_id);
-            let signed = claims.sign_with_key(&state.jwt_key).expect("Failed to sign JWT");
-
-            return ok(UserAuthResponse {
-                user: UserSelfResponse::from(user),
-                token: signed
-            })
+    let found = find_by_identifier(&mut state.database, &request.identifier);
+    let Ok(user) = found else {
+        // Unknown identifiers still pay for a hash, so timing doesn't tell which accounts exist
+        verify_password(&state.argon, None, &state.dummy_password_hash, &request.password);
+        return rest::error(StatusCode::UNAUTHORIZED, "Invalid credentials");
+    };
+
+    let check = verify_password(
+        &state.argon,
+        state.config.auth.legacy_argon_salt.as_deref(),
+        &user.password,
+        &request.password
+    );
+    if check == PasswordCheck::Outdated {
+        // The plain password is only available here, so this is where old hashes get upgraded
+        if let Ok(rehashed) = hash_password(&state.argon, &request.password) {
+            let _ = diesel::update(users.filter(table_user_id.eq(user.user_id)))
+                .set(table_password.eq(rehashed))
+                .execute(&mut state.database);
+        }
+    }
+    if check != PasswordCheck::Invalid {
+        if user.two_factor_enabled {
+            return match create_challenge(state, user.user_id) {
+                Ok(challenge) => ok(LoginResponse::TwoFactorRequired(challenge)),
+                Err(_) => rest::error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create two-factor challenge")
+            };
         }
+        return match start_session(state, user.user_id, &device) {
+            Ok(tokens) => ok(LoginResponse::Authenticated(Box::new(UserAuthResponse {
+                user: UserSelfResponse::from(user),
+                tokens
+            }))),
+            Err(_) => rest::error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session")
+        };
     }
     rest::error(StatusCode::UNAUTHORIZED, "Invalid credentials")
 }
 
+// Logins take either a username or an email, both matched regardless of case
+pub fn find_by_identifier(connection: &mut PgConnection, identifier: &str) -> QueryResult<User> {
+    let identifier = identifier.trim();
+    let query = users.select(User::as_select()).into_boxed();
+    let query = if is_email(identifier) {
+        query.filter(lower(table_email).eq(lower(identifier)))
+    } else {
+        query.filter(lower(username).eq(lower(identifier)))
+    };
+    query.first::<User>(connection)
+}
+
+// Errors: invalid_body, invalid_fields, fields_taken, internal_error
 pub async fn register(
     Extension(state): Extension<SharedState>,
-    Json(request): Json<RegisterRequest>,
+    device: DeviceInfo,
+    JsonBody(request): JsonBody<RegisterRequest>,
 ) -> IrisResponse<UserAuthResponse> {
     let state = &mut state.write().await;
+    let user = match create_user(state, &request) {
+        Ok(user) => user,
+        Err(RegistrationError::Invalid(errors)) => {
+            return Err(IrisError::invalid_fields(errors));
+        }
+        Err(RegistrationError::Taken(errors)) => {
+            return field_errors(StatusCode::CONFLICT, "fields_taken", "Some fields are already in use", errors);
+        }
+        Err(RegistrationError::Database(_) | RegistrationError::Password(_)) => {
+            return rest::error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create user");
+        }
+    };
+    if send_verification(state, &user).is_err() {
+        tracing::error!("Failed to create a verification token for {}", user.user_id);
+    }
+
+    match start_session(state, user.user_id, &device) {
+        Ok(tokens) => ok(UserAuthResponse {
+            user: UserSelfResponse::from(user),
+            tokens
+        }),
+        Err(_) => rest::error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session")
+    }
+}
+
+// Exchanges a refresh token for a new access token, rotating the refresh token in the process.
+// Presenting an already rotated token means it leaked, so the whole session is revoked.
+// Errors: invalid_body, invalid_refresh_token, refresh_token_expired, refresh_token_reused, internal_error
+pub async fn refresh(
+    Extension(state): Extension<SharedState>,
+    JsonBody(request): JsonBody<RefreshRequest>,
+) -> IrisResponse<TokenResponse> {
+    let state = &mut state.write().await;
+    let Some((id, secret)) = parse_token(&request.refresh_token) else {
+        return error_with_code(StatusCode::UNAUTHORIZED, "invalid_refresh_token", "Invalid refresh token");
+    };
+
+    let session = sessions
+        .filter(session_id.eq(id))
+        .select(Session::as_select())
+        .first::<Session>(&mut state.database);
+    let Ok(session) = session else {
+        return error_with_code(StatusCode::UNAUTHORIZED, "invalid_refresh_token", "Invalid refresh token");
+    };
+
+    let now = Utc::now().naive_utc();
+    let presented_hash = hash_secret(secret);
+    if session.expires_at <= now {
+        let _ = diesel::delete(sessions.filter(session_id.eq(id))).execute(&mut state.database);
+        return error_with_code(StatusCode::UNAUTHORIZED, "refresh_token_expired", "Refresh token has expired");
+    }
+    if session.previous_token_hash.as_deref().is_some_and(|previous| constant_time_eq(previous, &presented_hash)) {
+        let _ = diesel::delete(sessions.filter(session_id.eq(id))).execute(&mut state.database);
+        return error_with_code(StatusCode::UNAUTHORIZED, "refresh_token_reused", "Refresh token was already used");
+    }
+    if !constant_time_eq(&session.refresh_token_hash, &presented_hash) {
+        return error_with_code(StatusCode::UNAUTHORIZED, "invalid_refresh_token", "Invalid refresh token");
+    }
+
+    let new_secret = generate_secret();
+    let rotated = diesel::update(
+        sessions
+            .filter(session_id.eq(id))
+            .filter(refresh_token_hash.eq(&presented_hash))
+    )
+        .set((
+            refresh_token_hash.eq(hash_secret(&new_secret)),
+            previous_token_hash.eq(Some(&presented_hash)),
+            expires_at.eq(now + Duration::seconds(state.config.auth.refresh_token_ttl))
+        ))
+        .execute(&mut state.database);
+    match rotated {
+        Ok(1) => ok(issue_tokens(state, session.user_id, id, &new_secret)?),
+        Ok(_) => error_with_code(StatusCode::UNAUTHORIZED, "invalid_refresh_token", "Invalid refresh token"),
+        Err(_) => rest::error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to refresh session")
+    }
+}
+
+// Shared by the signup route and the `create-user` command
+pub fn create_user(state: &mut AppState, request: &RegisterRequest) -> Result<User, RegistrationError> {
+    let (name, new_username, new_email) = (request.name.trim(), request.username.trim(), request.email.trim());
+    let invalid = request.validate();
+    if !invalid.is_empty() {
+        return Err(RegistrationError::Invalid(invalid));
+    }
+    let taken = identity_conflicts(state, Some(new_username), Some(new_email), None)?;
+    if !taken.is_empty() {
+        return Err(RegistrationError::Taken(taken));
+    }
+
     let id = { state.snowflake_issuer.generate().value() as i64 };
-    let hashed_password = state.argon.hash_password(request.password.as_bytes(), &state.argon_salt).expect("Failed to hash password");
+    let hashed_password = hash_password(&state.argon, &request.password).map_err(RegistrationError::Password)?;
 
     let new_user = User {
         user_id: id,
-        name: request.name.clone(),
-        username: request.username.clone(),
-        password: hashed_password.to_string(),
-        email: request.email.clone()
+        name: name.to_string(),
+        username: new_username.to_string(),
+        password: hashed_password,
+        email: new_email.to_string(),
+        email_verified: false,
+        two_factor_enabled: false,
+        bio: None,
+        pronouns: None,
+        status_text: None,
+        status_emoji: None,
+        avatar_id: None
     };
 
-    let user = diesel::insert_into(users)
+    // The unique indexes still catch a signup racing this one
+    diesel::insert_into(users)
         .values(&new_user)
         .get_result::<User>(&mut state.database)
-        .expect("Failed to insert user");
+        .map_err(|err| match &err {
+            DatabaseError(DatabaseErrorKind::UniqueViolation, info) => match info.constraint_name() {
+                Some("users_username_lower_key") => RegistrationError::Taken(vec![username_taken()]),
+                Some("users_email_lower_key") => RegistrationError::Taken(vec![email_taken()]),
+                _ => RegistrationError::Database(err)
+            },
+            _ => RegistrationError::Database(err)
+        })
+}
+
+// Which of the given identities another account uses or recently gave up, regardless of case
+pub fn identity_conflicts(
+    state: &mut AppState,
+    new_username: Option<&str>,
+    new_email: Option<&str>,
+    exclude: Option<i64>
+) -> QueryResult<Vec<FieldError>> {
+    let mut taken = Vec::new();
+    let others = || users.filter(table_user_id.ne(exclude.unwrap_or(0))).into_boxed();
+    if let Some(new_username) = new_username {
+        let exists = diesel::select(exists(others().filter(lower(username).eq(lower(new_username)))))
+            .get_result::<bool>(&mut state.database)?;
+        if exists {
+            taken.push(username_taken());
+        } else if is_username_reserved(state, new_username, exclude)? {
+            taken.push(FieldError::new("username", "recently_used", "This username was recently used by someone else"));
+        }
+    }
+    if let Some(new_email) = new_email {
+        let exists = diesel::select(exists(others().filter(lower(table_email).eq(lower(new_email)))))
+            .get_result::<bool>(&mut state.database)?;
+        if exists {
+            taken.push(email_taken());
+        }
+    }
+    Ok(taken)
+}
+
+// Released usernames stay with their previous owner for a while, so nobody else can pose as them
+fn is_username_reserved(state: &mut AppState, new_username: &str, owner: Option<i64>) -> QueryResult<bool> {
+    let reserved_since = Utc::now().naive_utc() - Duration::seconds(state.config.usernames.reservation_period);
+    diesel::select(exists(
+        username_history
+            .filter(history_user_id.ne(owner.unwrap_or(0)))
+            .filter(lower(history_username).eq(lower(new_username)))
+            .filter(changed_at.gt(reserved_since))
+    )).get_result::<bool>(&mut state.database)
+}
+
+pub fn username_taken() -> FieldError {
+    FieldError::new("username", "taken", "This username is already taken")
+}
+
+fn email_taken() -> FieldError {
+    FieldError::new("email", "taken", "This email is already in use")
+}
+
+pub fn start_session(state: &mut AppState, user_id: i64, device: &DeviceInfo) -> Result<TokenResponse, IrisError> {
+    let id = { state.snowflake_issuer.generate().value() as i64 };
+    let secret = generate_secret();
+    let now = Utc::now().naive_utc();
+
+    let session = Session {
+        session_id: id,
+        user_id,
+        refresh_token_hash: hash_secret(&secret),
+        previous_token_hash: None,
+        created_at: now,
+        expires_at: now + Duration::seconds(state.config.auth.refresh_token_ttl),
+        device_name: device.device_name.clone(),
+        user_agent: device.user_agent.clone(),
+        ip_address: device.ip_address.clone(),
+        last_used_at: now
+    };
+    diesel::insert_into(sessions)
+        .values(&session)
+        .execute(&mut state.database)?;
+
+    issue_tokens(state, user_id, id, &secret)
+}
+
+// Logs the user out everywhere: refresh tokens stop working, access tokens are rejected by
+// the authorization middleware and open gateway connections are closed.
+pub fn end_all_sessions(state: &mut AppState, user_identifier: i64) -> QueryResult<usize> {
+    let ended = diesel::delete(sessions.filter(crate::schema::sessions::sessions::user_id.eq(user_identifier)))
+        .execute(&mut state.database)?;
+    disconnect_user(&state.packet_queue, user_identifier);
+    Ok(ended)
+}
+
+fn issue_tokens(state: &AppState, user_id: i64, session: i64, refresh_secret: &str) -> Result<TokenResponse, IrisError> {
+    let issued_at = Utc::now().timestamp();
+    let expires_in = state.config.auth.access_token_ttl;
 
     let mut claims = BTreeMap::new();
-    claims.insert("id", user.user_id);
-    let signed = claims.sign_with_key(&state.jwt_key).expect("Failed to sign JWT");
+    claims.insert("id", user_id);
+    claims.insert("sid", session);
+    claims.insert("iat", issued_at);
+    claims.insert("exp", issued_at + expires_in);
+    let signed = claims.sign_with_key(&state.jwt_key)
+        .map_err(|_| IrisError::internal("Failed to sign the access token"))?;
 
-    ok(UserAuthResponse {
-        user: UserSelfResponse::from(user),
-        token: signed
+    Ok(TokenResponse {
+        token: signed,
+        refresh_token: format_token(session, refresh_secret),
+        expires_in
     })
 }
 
+#[derive(Debug)]
+pub enum RegistrationError {
+    Invalid(Vec<FieldError>),
+    Taken(Vec<FieldError>),
+    Database(diesel::result::Error),
+    Password(argon2::password_hash::Error)
+}
+
+impl From<diesel::result::Error> for RegistrationError {
+    fn from(err: diesel::result::Error) -> Self {
+        RegistrationError::Database(err)
+    }
+}
+
+impl Display for RegistrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistrationError::Invalid(errors) | RegistrationError::Taken(errors) => {
+                let fields: Vec<String> = errors.iter().map(|err| format!("{}: {}", err.field, err.message)).collect();
+                write!(f, "{}", fields.join(", "))
+            }
+            RegistrationError::Database(err) => write!(f, "{}", err),
+            RegistrationError::Password(err) => write!(f, "Failed to hash password: {}", err)
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct LoginRequest {
     pub identifier: String,
@@ -83,4 +360,20 @@ pub struct RegisterRequest {
     pub username: String,
     pub password: String,
     pub email: String
-}
\ No newline at end of file
+}
+
+impl Validate for RegisterRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        [
+            check("name", validate_name(self.name.trim())),
+            check("username", validate_username(self.username.trim())),
+            check("email", validate_email(self.email.trim())),
+            check("password", validate_password(&self.password))
+        ].into_iter().flatten().collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RefreshRequest {
+    pub refresh_token: String
+}
@@ -0,0 +1,149 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Extension;
+use chrono::Utc;
+use diesel::dsl::exists;
+use diesel::{BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
+
+use crate::schema::blocks::UserBlock;
+use crate::schema::blocks::user_blocks::dsl::user_blocks;
+use crate::schema::blocks::user_blocks::{blocked_id, blocker_id};
+use crate::schema::channels::channel_members::dsl::channel_members;
+use crate::schema::channels::channel_members::{channel_id as member_channel_id, user_id as member_user_id};
+use crate::schema::channels::channels::channel_type;
+use crate::schema::channels::channels::dsl::channels;
+use crate::schema::users::User;
+use crate::schema::users::users::dsl::users;
+use crate::schema::users::users::user_id as table_user_id;
+use crate::server::gateway::context::send_packet_to_user;
+use crate::server::gateway::messages::FriendRemoved;
+use crate::server::rest::friends::end_relationship;
+use crate::server::rest::extract::Path;
+use crate::server::rest::{current_user, error, IrisResponse, no_content, ok, StandardUser};
+use crate::SharedState;
+
+// Errors: invalid_path, bad_request, not_found, internal_error
+pub async fn block_user(
+    Path(target): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    if target == user.user_id {
+        return error(StatusCode::BAD_REQUEST, "You can't block yourself");
+    }
+    let state = &mut state.write().await;
+
+    let target_exists = diesel::select(exists(users.filter(table_user_id.eq(target))))
+        .get_result::<bool>(&mut state.database);
+    match target_exists {
+        Ok(true) => {}
+        Ok(false) => return error(StatusCode::NOT_FOUND, "User not found"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to block user")
+    }
+
+    let block = UserBlock {
+        blocker_id: user.user_id,
+        blocked_id: target,
+        created_at: Utc::now().naive_utc()
+    };
+    let inserted = diesel::insert_into(user_blocks)
+        .values(&block)
+        .on_conflict_do_nothing()
+        .execute(&mut state.database);
+    if inserted.is_err() {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to block user");
+    }
+
+    // A block ends the friendship and any pending request
+    match end_relationship(&mut state.database, user.user_id, target) {
+        Ok(true) => {
+            send_packet_to_user(&mut state.packet_queue, user.user_id, || Box::new(FriendRemoved {
+                user_id: target
+            })).await;
+            send_packet_to_user(&mut state.packet_queue, target, || Box::new(FriendRemoved {
+                user_id: user.user_id
+            })).await;
+            no_content()
+        }
+        Ok(false) => no_content(),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to block user")
+    }
+}
+
+// Errors: invalid_path, not_found, internal_error
+pub async fn unblock_user(
+    Path(target): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let deleted = diesel::delete(
+        user_blocks
+            .filter(blocker_id.eq(user.user_id))
+            .filter(blocked_id.eq(target))
+    ).execute(&mut state.database);
+    match deleted {
+        Ok(0) => error(StatusCode::NOT_FOUND, "User isn't blocked"),
+        Ok(_) => no_content(),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to unblock user")
+    }
+}
+
+// Errors: internal_error
+pub async fn get_blocks(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<Vec<StandardUser>> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let blocked = users
+        .filter(table_user_id.eq_any(user_blocks.filter(blocker_id.eq(user.user_id)).select(blocked_id)))
+        .select(User::as_select())
+        .load::<User>(&mut state.database);
+    match blocked {
+        Ok(blocked) => ok(blocked.into_iter().map(StandardUser::from).collect()),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get blocked users")
+    }
+}
+
+// Blocks work both ways: neither side can reach the other
+pub fn is_blocked_between(connection: &mut PgConnection, user: i64, other: i64) -> QueryResult<bool> {
+    diesel::select(exists(
+        user_blocks.filter(
+            blocker_id.eq(user).and(blocked_id.eq(other))
+                .or(blocker_id.eq(other).and(blocked_id.eq(user)))
+        )
+    )).get_result::<bool>(connection)
+}
+
+// Everyone the user blocked or was blocked by
+pub fn blocked_relations(connection: &mut PgConnection, user: i64) -> QueryResult<Vec<i64>> {
+    let blocked: Vec<i64> = user_blocks
+        .filter(blocker_id.eq(user))
+        .select(blocked_id)
+        .load::<i64>(connection)?;
+    let blockers: Vec<i64> = user_blocks
+        .filter(blocked_id.eq(user))
+        .select(blocker_id)
+        .load::<i64>(connection)?;
+    Ok(blocked.into_iter().chain(blockers).collect())
+}
+
+// Whether the channel is a DM between the user and someone on the other side of a block
+pub fn is_blocked_dm(connection: &mut PgConnection, channel: i64, user: i64) -> QueryResult<bool> {
+    let relations = blocked_relations(connection, user)?;
+    if relations.is_empty() {
+        return Ok(false);
+    }
+    diesel::select(exists(
+        channel_members
+            .inner_join(channels)
+            .filter(member_channel_id.eq(channel))
+            .filter(channel_type.eq(0))
+            .filter(member_user_id.eq_any(relations))
+    )).get_result::<bool>(connection)
+}
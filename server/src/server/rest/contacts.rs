@@ -1,166 +1,383 @@
 use axum::body::Body;
 use axum::Extension;
-use axum::extract::Path;
 use axum::http::{Request, StatusCode};
-use diesel::{RunQueryDsl, sql_query};
-use diesel::sql_types::BigInt;
-use crate::schema::channels::{PrivateChannelQuery};
+use diesel::dsl::exists;
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl, sql_query};
+use diesel::sql_types::{BigInt, Bool, Nullable};
+use serde::{Deserialize, Serialize};
+use crate::schema::channels::{ConversationChangeset, PrivateChannelQuery};
+use crate::schema::channels::channel_members::dsl::channel_members;
+use crate::schema::channels::channel_members::{accepted as member_accepted, archived as member_archived, channel_id as member_channel_id, hidden as member_hidden, pinned as member_pinned, user_id as member_user_id};
+use crate::schema::channels::channels::channel_type;
+use crate::schema::channels::channels::dsl::channels;
+use crate::schema::folders::ContactNickname;
+use crate::schema::folders::contact_nicknames::dsl::contact_nicknames;
+use crate::schema::folders::contact_nicknames::{contact_id as nickname_contact_id, nickname as nickname_value, user_id as nickname_user_id};
 use crate::schema::messages::ContactWithChannel;
-use crate::schema::users::User;
-use crate::server::rest::{ContactResponse, error, IrisResponse, ok, PrimordialMessage, PrivateChannel};
+use crate::schema::users::users::dsl::users;
+use crate::schema::users::users::user_id as table_user_id;
+use crate::server::gateway::context::send_packet_to_user;
+use crate::server::gateway::messages::{ContactNicknameUpdated, ConversationUpdated};
+use crate::server::rest::blocks::is_blocked_between;
+use crate::server::rest::friends::are_friends;
+use crate::server::rest::settings::can_message;
+use crate::server::rest::extract::{Path, Query};
+use crate::server::rest::validation::{check, Validate};
+use crate::server::rest::{ContactResponse, current_user, error, error_with_code, FieldError, IrisResponse, no_content, ok, PrimordialMessage, PrivateChannel, read_json, read_valid_json};
+use crate::util::identity::MAX_NICKNAME_LENGTH;
+use crate::util::validation::validate_length;
 use crate::SharedState;
 
-// For the time being, we will return all registered users as contacts
+const DEFAULT_CONTACTS_LIMIT: i64 = 50;
+const MAX_CONTACTS_LIMIT: i64 = 100;
+
+// Contacts are the user's friends and whoever they accepted a DM from, along with the DM if one was opened
+// Errors: invalid_query
 pub async fn get_contacts(
     Extension(state): Extension<SharedState>,
+    Query(params): Query<ContactsParams>,
     request: Request<Body>
 ) -> IrisResponse<Vec<ContactResponse>> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
+    let user = current_user(&request)?;
     let conn = &mut state.write().await.database;
 
+    ok(load_contacts(conn, user.user_id, false, &params)?)
+}
+
+// The requests inbox: DMs opened by people who aren't contacts, until they're accepted
+// Errors: invalid_query
+pub async fn get_message_requests(
+    Extension(state): Extension<SharedState>,
+    Query(params): Query<ContactsParams>,
+    request: Request<Body>
+) -> IrisResponse<Vec<ContactResponse>> {
+    let user = current_user(&request)?;
+    let conn = &mut state.write().await.database;
+
+    ok(load_contacts(conn, user.user_id, true, &params)?)
+}
+
+// Errors: invalid_path, not_found, internal_error
+pub async fn accept_message_request(
+    Path(channel_id): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    match accept_dm(&mut state.database, channel_id, user.user_id) {
+        Ok(0) => error(StatusCode::NOT_FOUND, "Message request not found"),
+        Ok(_) => no_content(),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to accept message request")
+    }
+}
+
+// Declining leaves the conversation, the sender keeps their side of it
+// Errors: invalid_path, not_found, internal_error
+pub async fn decline_message_request(
+    Path(channel_id): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let declined = diesel::delete(
+        channel_members
+            .filter(member_channel_id.eq(channel_id))
+            .filter(member_user_id.eq(user.user_id))
+            .filter(member_accepted.eq(false))
+    ).execute(&mut state.database);
+    match declined {
+        Ok(0) => error(StatusCode::NOT_FOUND, "Message request not found"),
+        Ok(_) => no_content(),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to decline message request")
+    }
+}
+
+// Pinning, archiving and hiding only change the user's own side of the conversation
+// Errors: invalid_path, invalid_body, not_found, internal_error
+pub async fn update_conversation(
+    Path(channel_id): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<ConversationResponse> {
+    let user = current_user(&request)?;
+    let request = read_json::<UpdateConversationRequest>(request).await?;
+    let changeset = ConversationChangeset {
+        pinned: request.pinned,
+        archived: request.archived,
+        hidden: request.hidden
+    };
+
+    let state = &mut state.write().await;
+    let membership = channel_members
+        .filter(member_channel_id.eq(channel_id))
+        .filter(member_user_id.eq(user.user_id));
+    let updated = match diesel::update(membership).set(&changeset)
+        .returning((member_pinned, member_archived, member_hidden))
+        .get_result::<(bool, bool, bool)>(&mut state.database) {
+        // Nothing to change
+        Err(diesel::result::Error::QueryBuilderError(_)) => membership
+            .select((member_pinned, member_archived, member_hidden))
+            .first::<(bool, bool, bool)>(&mut state.database),
+        updated => updated
+    };
+    let (pinned, archived, hidden) = match updated {
+        Ok(flags) => flags,
+        Err(diesel::NotFound) => return error(StatusCode::NOT_FOUND, "Conversation not found"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update conversation")
+    };
+
+    send_packet_to_user(&mut state.packet_queue, user.user_id, || Box::new(ConversationUpdated {
+        channel_id,
+        pinned,
+        archived,
+        hidden
+    })).await;
+    ok(ConversationResponse {
+        channel_id,
+        pinned,
+        archived,
+        hidden
+    })
+}
+
+// Pinned conversations come first, then the most recently active ones.
+// Only contacts with a DM can be filed, so filtering by folder leaves out the others.
+fn load_contacts(conn: &mut PgConnection, user_id: i64, requests: bool, params: &ContactsParams) -> QueryResult<Vec<ContactResponse>> {
+    find_contacts(conn, user_id, &ContactFilter {
+        requests: Some(requests),
+        archived: Some(params.archived),
+        folder: params.folder,
+        contact: None,
+        limit: params.limit.unwrap_or(DEFAULT_CONTACTS_LIMIT).clamp(1, MAX_CONTACTS_LIMIT),
+        offset: params.offset.unwrap_or(0).max(0)
+    })
+}
+
+// A single contact is found whether it's a request, archived or hidden
+fn find_contacts(conn: &mut PgConnection, user_id: i64, filter: &ContactFilter) -> QueryResult<Vec<ContactResponse>> {
     let query = sql_query("
-WITH last_messages AS (
+WITH dm_channels AS (
     SELECT
-        m.channel_id,
-        m.message_id,
-        m.content,
-        m.reception_status
+        cm2.user_id AS contact_id,
+        cm1.channel_id,
+        cm1.accepted,
+        cm1.pinned,
+        cm1.archived,
+        cm1.hidden,
+        cm1.unread_count,
+        c.last_message_id
     FROM
-        messages m
+        channel_members cm1
+    JOIN
+        channels c ON cm1.channel_id = c.channel_id
+    JOIN
+        channel_members cm2 ON cm1.channel_id = cm2.channel_id AND cm2.user_id != $1
     WHERE
-        m.message_id = (
-            SELECT MAX(m2.message_id)
-            FROM messages m2
-            WHERE m2.channel_id = m.channel_id
-        )
+        cm1.user_id = $1
+        AND c.channel_type = 0
 ),
-unread_reception_count AS (
-    SELECT
-        m.channel_id,
-        COUNT(*) AS unread_reception_count
-    FROM
-        messages m
-    WHERE
-        m.reception_status = 0 AND m.user_id != $1
-    GROUP BY
-        m.channel_id
+listed AS (
+    SELECT f.friend_id AS contact_id
+    FROM friendships f
+    WHERE f.user_id = $1 AND NOT COALESCE($2, FALSE)
+    UNION
+    SELECT dc.contact_id
+    FROM dm_channels dc
+    WHERE $2 IS NULL OR dc.accepted != $2
+),
+user_folders AS (
+    SELECT fc.folder_id, fc.channel_id
+    FROM folder_channels fc
+    JOIN folders f ON f.folder_id = fc.folder_id
+    WHERE f.user_id = $1
 )
 
-SELECT DISTINCT
+SELECT
     u.user_id,
-    cm1.channel_id,
+    dc.channel_id,
     u.name,
     u.username,
+    cn.nickname,
+    ARRAY(
+        SELECT uf.folder_id FROM user_folders uf WHERE uf.channel_id = dc.channel_id ORDER BY uf.folder_id
+    ) AS folder_ids,
+    COALESCE(dc.pinned, FALSE) AS pinned,
+    COALESCE(dc.archived, FALSE) AS archived,
     lm.message_id,
     lm.content,
     lm.reception_status,
-    COALESCE(urc.unread_reception_count, 0) AS unread_reception_count
+    COALESCE(dc.unread_count, 0)::BIGINT AS unread_reception_count
 FROM
-    users u
-JOIN
-    channel_members cm1 ON u.user_id = cm1.user_id
-JOIN
-    channels c ON cm1.channel_id = c.channel_id
+    listed l
 JOIN
-    channel_members cm2 ON cm1.channel_id = cm2.channel_id
+    users u ON u.user_id = l.contact_id
 LEFT JOIN
-    last_messages lm ON cm1.channel_id = lm.channel_id
+    dm_channels dc ON dc.contact_id = u.user_id
 LEFT JOIN
-    unread_reception_count urc ON cm1.channel_id = urc.channel_id
+    messages lm ON lm.message_id = dc.last_message_id
+LEFT JOIN
+    contact_nicknames cn ON cn.user_id = $1 AND cn.contact_id = u.user_id
 WHERE
-    cm2.user_id = $1
-    AND c.channel_type = 0
-    AND u.user_id != $1;
-    ").bind::<BigInt, _>(user.user_id);
-    let results = query
-        .load::<ContactWithChannel>(conn)
-        .expect("Failed to load contacts");
+    ($3 IS NULL OR dc.channel_id IN (SELECT uf.channel_id FROM user_folders uf WHERE uf.folder_id = $3))
+    AND NOT EXISTS (
+        SELECT 1
+        FROM user_blocks b
+        WHERE (b.blocker_id = $1 AND b.blocked_id = u.user_id) OR (b.blocker_id = u.user_id AND b.blocked_id = $1)
+    )
+    AND ($7 IS NULL OR u.user_id = $7)
+    AND ($7 IS NOT NULL OR NOT COALESCE(dc.hidden, FALSE))
+    AND ($4 IS NULL OR COALESCE(dc.archived, FALSE) = $4)
+ORDER BY
+    COALESCE(dc.pinned, FALSE) DESC,
+    COALESCE(dc.last_message_id, dc.channel_id, 0) DESC,
+    u.user_id
+LIMIT $5 OFFSET $6
+    ")
+        .bind::<BigInt, _>(user_id)
+        .bind::<Nullable<Bool>, _>(filter.requests)
+        .bind::<Nullable<BigInt>, _>(filter.folder)
+        .bind::<Nullable<Bool>, _>(filter.archived)
+        .bind::<BigInt, _>(filter.limit)
+        .bind::<BigInt, _>(filter.offset)
+        .bind::<Nullable<BigInt>, _>(filter.contact);
+    let results = query.load::<ContactWithChannel>(conn)?;
 
-    let result = results.into_iter().map(|contact| {
+    Ok(results.into_iter().map(|contact| {
         ContactResponse {
             user_id: contact.user_id,
             channel_id: contact.channel_id,
             name: contact.name,
             username: contact.username.clone(),
-            last_message: match contact.message_id {
-                Some(_) => Some(PrimordialMessage {
-                    id: contact.message_id.unwrap(),
-                    content: contact.content.unwrap(),
-                    receipt: contact.reception_status.unwrap(),
-                }),
-                None => None
-            },
-            unread_count: 0 // TODO: use the actual value
+            nickname: contact.nickname,
+            folders: contact.folder_ids,
+            pinned: contact.pinned,
+            archived: contact.archived,
+            last_message: last_message(contact.message_id, contact.content, contact.reception_status),
+            unread_count: contact.unread_reception_count
         }
-    }).collect();
+    }).collect())
+}
 
-    ok(result)
+fn last_message(message_id: Option<i64>, content: Option<String>, receipt: Option<i16>) -> Option<PrimordialMessage> {
+    Some(PrimordialMessage {
+        id: message_id?,
+        content: content?,
+        receipt: receipt?
+    })
 }
 
+// Errors: invalid_path, not_found
 pub async fn get_contact(
     Path(contact_id): Path<i64>,
     Extension(state): Extension<SharedState>,
     request: Request<Body>
 ) -> IrisResponse<ContactResponse> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
+    let user = current_user(&request)?;
     let conn = &mut state.write().await.database;
 
-    let query = sql_query("
-        WITH recent_messages AS (
-            SELECT u.*, m.id AS message_id, m.content, m.reception_status,
-                   (SELECT COUNT(*)
-                    FROM messages
-                    WHERE (user_id = $2 AND context = $1)
-                      AND reception_status = 0) AS reception_status_count
-            FROM users u
-            LEFT JOIN LATERAL (
-                SELECT id, content, reception_status
-                FROM messages
-                WHERE (user_id = $2 AND context = $1)
-                   OR (context = $2 AND user_id = $1)
-                ORDER BY id DESC
-                LIMIT 1
-            ) m ON true
-            where u.id = $1
-        )
-        SELECT *
-        FROM recent_messages
-        ORDER BY COALESCE(message_id, -1) DESC;
-    ").bind::<BigInt, _>(contact_id).bind::<BigInt, _>(user.user_id);
-    let results = query
-        .load::<ContactWithChannel>(conn)
-        .expect("Failed to load contact");
-
-    let contact = results.into_iter().next().expect("Contact not found");
-
-    let result = ContactResponse {
-        user_id: contact.user_id,
-        channel_id: contact.channel_id,
-        name: contact.name,
-        username: contact.username.clone(),
-        last_message: match contact.message_id {
-            Some(_) => Some(PrimordialMessage {
-                id: contact.message_id.unwrap(),
-                content: contact.content.unwrap(),
-                receipt: contact.reception_status.unwrap(),
-            }),
-            None => None
-        },
-        unread_count: contact.unread_reception_count
+    let found = find_contacts(conn, user.user_id, &ContactFilter {
+        requests: None,
+        archived: None,
+        folder: None,
+        contact: Some(contact_id),
+        limit: 1,
+        offset: 0
+    })?;
+    match found.into_iter().next() {
+        Some(contact) => ok(contact),
+        None => error(StatusCode::NOT_FOUND, "Contact not found")
+    }
+}
+
+// An empty nickname clears it
+// Errors: invalid_path, invalid_body, invalid_fields, bad_request, not_found, internal_error
+pub async fn set_nickname(
+    Path(contact_id): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<NicknameResponse> {
+    let user = current_user(&request)?;
+    let nickname = read_valid_json::<NicknameRequest>(request).await?.nickname
+        .map(|nickname| nickname.trim().to_string())
+        .filter(|nickname| !nickname.is_empty());
+    if contact_id == user.user_id {
+        return error(StatusCode::BAD_REQUEST, "You can't nickname yourself");
+    }
+
+    let state = &mut state.write().await;
+    let contact_exists = diesel::select(exists(users.filter(table_user_id.eq(contact_id))))
+        .get_result::<bool>(&mut state.database);
+    match contact_exists {
+        Ok(true) => {}
+        Ok(false) => return error(StatusCode::NOT_FOUND, "User not found"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to set nickname")
+    }
+
+    let saved = match &nickname {
+        Some(nickname) => diesel::insert_into(contact_nicknames)
+            .values(&ContactNickname {
+                user_id: user.user_id,
+                contact_id,
+                nickname: nickname.clone()
+            })
+            .on_conflict((nickname_user_id, nickname_contact_id))
+            .do_update()
+            .set(nickname_value.eq(nickname))
+            .execute(&mut state.database),
+        None => diesel::delete(
+            contact_nicknames
+                .filter(nickname_user_id.eq(user.user_id))
+                .filter(nickname_contact_id.eq(contact_id))
+        ).execute(&mut state.database)
     };
+    if saved.is_err() {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to set nickname");
+    }
 
-    ok(result)
+    send_packet_to_user(&mut state.packet_queue, user.user_id, || Box::new(ContactNicknameUpdated {
+        user_id: contact_id,
+        nickname: nickname.clone()
+    })).await;
+    ok(NicknameResponse {
+        user_id: contact_id,
+        nickname
+    })
 }
 
 // This will simply return the channel between the two users if it exists, or create it if it doesn't
+// Errors: invalid_path, blocked, dm_not_allowed, internal_error
 pub async fn chat(
     Path(contact_id): Path<i64>,
     Extension(state): Extension<SharedState>,
     request: Request<Body>
 ) -> IrisResponse<PrivateChannel> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
+    let user = current_user(&request)?;
     let state = &mut state.write().await;
 
+    match is_blocked_between(&mut state.database, user.user_id, contact_id) {
+        Ok(false) => {}
+        Ok(true) => return error_with_code(StatusCode::FORBIDDEN, "blocked", "You can't message this user"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get channel")
+    }
+    // DMs that already exist stay reachable, new ones depend on the contact's privacy settings.
+    // Unless they're friends, the contact gets it as a request.
+    let existing = find_dm(&mut state.database, user.user_id, contact_id);
+    let accepted = match existing {
+        Ok(Some(channel_id)) => return ok(PrivateChannel { channel_id }),
+        Ok(None) => can_message(&mut state.database, user.user_id, contact_id)
+            .and_then(|allowed| Ok(allowed.then_some(are_friends(&mut state.database, user.user_id, contact_id)?))),
+        Err(err) => Err(err)
+    };
+    let accepted = match accepted {
+        Ok(Some(accepted)) => accepted,
+        Ok(None) => return error_with_code(StatusCode::FORBIDDEN, "dm_not_allowed", "This user doesn't accept messages from you"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get channel")
+    };
+
     let snowflake_id = {
         &state.snowflake_issuer.generate()
     };
@@ -185,8 +402,8 @@ pub async fn chat(
         FROM inserted_channel
         RETURNING channel_id
     ), new_channel_member2 AS (
-        INSERT INTO channel_members (channel_id, user_id)
-        SELECT channel_id, $2
+        INSERT INTO channel_members (channel_id, user_id, accepted)
+        SELECT channel_id, $2, $4
         FROM inserted_channel
         RETURNING channel_id
     )
@@ -198,16 +415,119 @@ pub async fn chat(
     UNION ALL
     SELECT channel_id FROM new_channel_member2
     LIMIT 1;
-    "#).bind::<BigInt, _>(user.user_id).bind::<BigInt, _>(contact_id).bind::<BigInt, _>(snowflake_id.value() as i64);
-    let channel_id_result = query.load::<PrivateChannelQuery>(&mut state.database);
-
-    if channel_id_result.is_err() {
-        channel_id_result.unwrap();
-        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get channel");
-    }
-
-    let channel = channel_id_result.unwrap().into_iter().next().expect("Channel not found");
+    "#).bind::<BigInt, _>(user.user_id).bind::<BigInt, _>(contact_id).bind::<BigInt, _>(snowflake_id.value() as i64).bind::<Bool, _>(accepted);
+    let channel = match query.get_result::<PrivateChannelQuery>(&mut state.database) {
+        Ok(channel) => channel,
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get channel")
+    };
     ok(PrivateChannel {
         channel_id: channel.channel_id
     })
-}
\ No newline at end of file
+}
+
+// The DM channel between two users, if they have one
+pub fn find_dm(connection: &mut PgConnection, user: i64, other: i64) -> QueryResult<Option<i64>> {
+    let query = sql_query(r#"
+    SELECT cm1.channel_id
+    FROM channel_members cm1
+    JOIN channel_members cm2 ON cm1.channel_id = cm2.channel_id
+    JOIN channels c ON cm1.channel_id = c.channel_id
+    WHERE cm1.user_id = $1
+    AND cm2.user_id = $2
+    AND c.channel_type = 0
+    LIMIT 1;
+    "#).bind::<BigInt, _>(user).bind::<BigInt, _>(other);
+    query.get_result::<PrivateChannelQuery>(connection)
+        .optional()
+        .map(|channel| channel.map(|channel| channel.channel_id))
+}
+
+// The other side of a DM, and whether they accepted it
+pub fn dm_recipient(connection: &mut PgConnection, channel_id: i64, user: i64) -> QueryResult<Option<(i64, bool)>> {
+    channel_members
+        .inner_join(channels)
+        .filter(member_channel_id.eq(channel_id))
+        .filter(member_user_id.ne(user))
+        .filter(channel_type.eq(0))
+        .select((member_user_id, member_accepted))
+        .first::<(i64, bool)>(connection)
+        .optional()
+}
+
+// A new message brings the conversation back for whoever hid it
+pub fn unhide_conversation(connection: &mut PgConnection, channel_id: i64) -> QueryResult<usize> {
+    diesel::update(
+        channel_members
+            .filter(member_channel_id.eq(channel_id))
+            .filter(member_hidden.eq(true))
+    )
+        .set(member_hidden.eq(false))
+        .execute(connection)
+}
+
+// Replying to a request, or becoming friends, accepts the DM
+pub fn accept_dm(connection: &mut PgConnection, channel_id: i64, user: i64) -> QueryResult<usize> {
+    diesel::update(
+        channel_members
+            .filter(member_channel_id.eq(channel_id))
+            .filter(member_user_id.eq(user))
+            .filter(member_accepted.eq(false))
+    )
+        .set(member_accepted.eq(true))
+        .execute(connection)
+}
+
+#[derive(Deserialize)]
+pub struct NicknameRequest {
+    pub nickname: Option<String>
+}
+
+impl Validate for NicknameRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        self.nickname.as_deref()
+            .and_then(|nickname| check("nickname", validate_length(nickname.trim(), MAX_NICKNAME_LENGTH)))
+            .into_iter()
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct NicknameResponse {
+    pub user_id: i64,
+    pub nickname: Option<String>
+}
+
+// Archived conversations are listed apart from the others, hidden ones not at all
+#[derive(Deserialize)]
+pub struct ContactsParams {
+    pub folder: Option<i64>,
+    #[serde(default)]
+    pub archived: bool,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
+}
+
+// What a contact listing is narrowed to, `None` leaves that filter out
+struct ContactFilter {
+    requests: Option<bool>,
+    archived: Option<bool>,
+    folder: Option<i64>,
+    contact: Option<i64>,
+    limit: i64,
+    offset: i64
+}
+
+#[derive(Deserialize)]
+pub struct UpdateConversationRequest {
+    pub pinned: Option<bool>,
+    pub archived: Option<bool>,
+    pub hidden: Option<bool>
+}
+
+#[derive(Serialize)]
+pub struct ConversationResponse {
+    pub channel_id: i64,
+    pub pinned: bool,
+    pub archived: bool,
+    pub hidden: bool
+}
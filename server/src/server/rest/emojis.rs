@@ -0,0 +1,266 @@
+use std::path::PathBuf;
+
+use axum::body::Body;
+use axum::extract::{FromRequest, Multipart};
+use axum::http::{header, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use diesel::dsl::exists;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper};
+
+use crate::schema::channels::channel_members::dsl::channel_members;
+use crate::schema::emojis::{CustomEmoji, CustomEmojiSummary};
+use crate::schema::emojis::custom_emoji::dsl::custom_emoji as customEmojiTable;
+use crate::schema::emojis::custom_emoji::{channel_id as emojiChannelId, emoji_id, owner_id};
+use crate::server::rest::extract::Path;
+use crate::server::rest::{current_user, error, IrisError, IrisResponse, no_content, ok};
+use crate::util::emoji::{find_custom_emojis, is_valid_emoji_name};
+use crate::SharedState;
+
+// Errors: bad_request, payload_too_large, unsupported_media_type, conflict, internal_error
+pub async fn upload_emoji(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<CustomEmojiSummary> {
+    store_emoji(state, None, request).await
+}
+
+// Errors: invalid_path, bad_request, payload_too_large, unsupported_media_type, forbidden, conflict, internal_error
+pub async fn upload_channel_emoji(
+    Path(channel_id): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<CustomEmojiSummary> {
+    store_emoji(state, Some(channel_id), request).await
+}
+
+// Errors: internal_error
+pub async fn get_emojis(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<Vec<CustomEmojiSummary>> {
+    let user = current_user(&request)?;
+    let mut state = state.write().await;
+
+    let emojis = customEmojiTable
+        .filter(owner_id.eq(user.user_id))
+        .filter(emojiChannelId.is_null())
+        .select(CustomEmoji::as_select())
+        .load::<CustomEmoji>(&mut state.database);
+
+    match emojis {
+        Ok(emojis) => ok(emojis.into_iter().map(CustomEmojiSummary::from).collect()),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load emojis")
+    }
+}
+
+// Errors: invalid_path, forbidden, internal_error
+pub async fn get_channel_emojis(
+    Path(channel_id): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<Vec<CustomEmojiSummary>> {
+    let user = current_user(&request)?;
+    let mut state = state.write().await;
+
+    if !is_channel_member(&mut state.database, channel_id, user.user_id) {
+        return error(StatusCode::FORBIDDEN, "You are not a member of this channel");
+    }
+
+    let emojis = customEmojiTable
+        .filter(emojiChannelId.eq(channel_id))
+        .select(CustomEmoji::as_select())
+        .load::<CustomEmoji>(&mut state.database);
+
+    match emojis {
+        Ok(emojis) => ok(emojis.into_iter().map(CustomEmojiSummary::from).collect()),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to load emojis")
+    }
+}
+
+// Errors: invalid_path, not_found, internal_error
+pub async fn delete_emoji(
+    Path(emoji_identifier): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    let mut state = state.write().await;
+
+    let deleted = diesel::delete(
+        customEmojiTable
+            .filter(emoji_id.eq(emoji_identifier))
+            .filter(owner_id.eq(user.user_id))
+    ).execute(&mut state.database);
+
+    match deleted {
+        Ok(0) => error(StatusCode::NOT_FOUND, "Emoji not found"),
+        Ok(_) => {
+            let _ = tokio::fs::remove_file(emoji_path(&state.config.server.uploads_dir, emoji_identifier)).await;
+            no_content()
+        }
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete emoji")
+    }
+}
+
+// Emoji images are public, just like the `<:name:id>` references pointing at them
+// Errors: invalid_path, not_found
+pub async fn get_emoji_image(
+    Path(emoji_identifier): Path<i64>,
+    Extension(state): Extension<SharedState>
+) -> Response {
+    let (content_type, path) = {
+        let mut state = state.write().await;
+        let content_type = customEmojiTable
+            .filter(emoji_id.eq(emoji_identifier))
+            .select(crate::schema::emojis::custom_emoji::content_type)
+            .first::<String>(&mut state.database);
+        match content_type {
+            Ok(content_type) => (content_type, emoji_path(&state.config.server.uploads_dir, emoji_identifier)),
+            Err(_) => return IrisError::from_status(StatusCode::NOT_FOUND, "Emoji not found").into_response()
+        }
+    };
+
+    match tokio::fs::read(path).await {
+        Ok(bytes) => ([(header::CONTENT_TYPE, content_type)], bytes).into_response(),
+        Err(_) => IrisError::from_status(StatusCode::NOT_FOUND, "Emoji not found").into_response()
+    }
+}
+
+// Resolves the custom emojis referenced by a message, dropping the ones its author can't use there
+pub fn resolve_message_emojis(
+    connection: &mut PgConnection,
+    messages: &[(i64, i64, &str)]
+) -> Vec<Vec<CustomEmojiSummary>> {
+    let references: Vec<Vec<(String, i64)>> = messages.iter()
+        .map(|(_, _, content)| find_custom_emojis(content))
+        .collect();
+    let ids: Vec<i64> = references.iter().flatten().map(|(_, id)| *id).collect();
+    if ids.is_empty() {
+        return vec![vec![]; messages.len()];
+    }
+
+    let emojis = customEmojiTable
+        .filter(emoji_id.eq_any(ids))
+        .select(CustomEmoji::as_select())
+        .load::<CustomEmoji>(connection)
+        .unwrap_or_default();
+
+    messages.iter().zip(references).map(|((author_id, channel_id, _), references)| {
+        let mut resolved: Vec<CustomEmojiSummary> = Vec::new();
+        for (name, id) in references {
+            let emoji = emojis.iter()
+                .find(|emoji| emoji.emoji_id == id && emoji.name == name && emoji.usable_by(*author_id, *channel_id));
+            if let Some(emoji) = emoji {
+                if !resolved.iter().any(|summary| summary.id == id) {
+                    resolved.push(CustomEmojiSummary::from(emoji.clone()));
+                }
+            }
+        }
+        resolved
+    }).collect()
+}
+
+async fn store_emoji(
+    state: SharedState,
+    channel_id: Option<i64>,
+    request: Request<Body>
+) -> IrisResponse<CustomEmojiSummary> {
+    let user = current_user(&request)?;
+    let max_emoji_size = { state.read().await.config.limits.max_emoji_size };
+    let Ok(mut multipart) = Multipart::from_request(request, &()).await else {
+        return error(StatusCode::BAD_REQUEST, "Expected a multipart body");
+    };
+
+    let mut name: Option<String> = None;
+    let mut image: Option<Vec<u8>> = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        match field.name() {
+            Some("name") => name = field.text().await.ok(),
+            Some("image") => image = field.bytes().await.ok().map(|bytes| bytes.to_vec()),
+            _ => {}
+        }
+    }
+
+    let name = match name {
+        Some(name) if is_valid_emoji_name(name.trim()) => name.trim().to_string(),
+        _ => return error(StatusCode::BAD_REQUEST, "Emoji names must be 2-32 letters, digits or underscores")
+    };
+    let image = match image {
+        Some(image) if image.len() <= max_emoji_size => image,
+        Some(_) => return error(StatusCode::PAYLOAD_TOO_LARGE, &format!("Emoji images must be at most {} bytes", max_emoji_size)),
+        None => return error(StatusCode::BAD_REQUEST, "Missing emoji image")
+    };
+    let content_type = match sniff_image_type(&image) {
+        Some(content_type) => content_type,
+        None => return error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Emoji images must be PNG, GIF, JPEG or WebP")
+    };
+
+    let mut state = state.write().await;
+    if let Some(channel_id) = channel_id {
+        if !is_channel_member(&mut state.database, channel_id, user.user_id) {
+            return error(StatusCode::FORBIDDEN, "You are not a member of this channel");
+        }
+    }
+
+    let emoji = CustomEmoji {
+        emoji_id: state.snowflake_issuer.generate().value() as i64,
+        name,
+        owner_id: user.user_id,
+        channel_id,
+        content_type: content_type.to_string(),
+        animated: content_type == "image/gif"
+    };
+
+    let path = emoji_path(&state.config.server.uploads_dir, emoji.emoji_id);
+    if let Some(parent) = path.parent() {
+        if tokio::fs::create_dir_all(parent).await.is_err() {
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store emoji");
+        }
+    }
+    if tokio::fs::write(&path, &image).await.is_err() {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store emoji");
+    }
+
+    let inserted = diesel::insert_into(customEmojiTable)
+        .values(&emoji)
+        .execute(&mut state.database);
+    match inserted {
+        Ok(_) => ok(CustomEmojiSummary::from(emoji)),
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            match err {
+                DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) =>
+                    error(StatusCode::CONFLICT, "An emoji with this name already exists"),
+                _ => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store emoji")
+            }
+        }
+    }
+}
+
+fn is_channel_member(connection: &mut PgConnection, channel_id: i64, user_id: i64) -> bool {
+    diesel::select(exists(
+        channel_members
+            .filter(crate::schema::channels::channel_members::user_id.eq(user_id))
+            .filter(crate::schema::channels::channel_members::channel_id.eq(channel_id))
+    )).get_result::<bool>(connection).unwrap_or(false)
+}
+
+fn emoji_path(uploads_dir: &std::path::Path, emoji_identifier: i64) -> PathBuf {
+    uploads_dir.join("emojis").join(emoji_identifier.to_string())
+}
+
+fn sniff_image_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
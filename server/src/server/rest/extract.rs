@@ -0,0 +1,37 @@
+use axum::async_trait;
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use serde::de::DeserializeOwned;
+
+use crate::server::rest::validation::{validated, Validate};
+use crate::server::rest::IrisError;
+
+// Axum's extractors, rejecting with an `IrisError` instead of a plain text body
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(IrisError))]
+pub struct JsonBody<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(IrisError))]
+pub struct Path<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(IrisError))]
+pub struct Query<T>(pub T);
+
+// A JSON body that passed its own validation
+pub struct ValidJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync
+{
+    type Rejection = IrisError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let JsonBody(value) = JsonBody::<T>::from_request(request, state).await?;
+        validated(value).map(ValidJson)
+    }
+}
@@ -0,0 +1,265 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Extension;
+use chrono::Utc;
+use diesel::dsl::{count_star, exists};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
+use serde::Deserialize;
+
+use crate::schema::channels::channel_members::dsl::channel_members;
+use crate::schema::channels::channel_members::{channel_id as member_channel_id, user_id as member_user_id};
+use crate::schema::folders::{Folder, FolderChannel};
+use crate::schema::folders::folder_channels::dsl::folder_channels;
+use crate::schema::folders::folder_channels::{channel_id as filed_channel_id, folder_id as filed_folder_id};
+use crate::schema::folders::folders::dsl::folders;
+use crate::schema::folders::folders::{created_at, folder_id as table_folder_id, name as folder_name, user_id as folder_user_id};
+use crate::server::gateway::context::send_packet_to_user;
+use crate::server::gateway::messages::{FolderDeleted, FolderUpdated};
+use crate::server::rest::extract::Path;
+use crate::server::rest::validation::{check, Validate};
+use crate::server::rest::{current_user, error, error_with_code, FieldError, FolderResponse, IrisResponse, no_content, ok, read_valid_json};
+use crate::util::identity::MAX_FOLDER_NAME_LENGTH;
+use crate::util::validation::{validate_length, ValidationError};
+use crate::{AppState, SharedState};
+
+const MAX_FOLDERS: i64 = 50;
+
+// Errors: internal_error
+pub async fn get_folders(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<Vec<FolderResponse>> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let user_folders = folders
+        .filter(folder_user_id.eq(user.user_id))
+        .order(created_at.asc())
+        .select(Folder::as_select())
+        .load::<Folder>(&mut state.database);
+    let Ok(user_folders) = user_folders else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get folders");
+    };
+    let filed = folder_channels
+        .filter(filed_folder_id.eq_any(user_folders.iter().map(|folder| folder.folder_id)))
+        .select(FolderChannel::as_select())
+        .load::<FolderChannel>(&mut state.database);
+    let Ok(filed) = filed else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get folders");
+    };
+
+    ok(user_folders.into_iter().map(|folder| FolderResponse {
+        id: folder.folder_id,
+        name: folder.name,
+        channel_ids: filed.iter()
+            .filter(|entry| entry.folder_id == folder.folder_id)
+            .map(|entry| entry.channel_id)
+            .collect()
+    }).collect())
+}
+
+// Errors: invalid_body, invalid_fields, too_many_folders, internal_error
+pub async fn create_folder(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<FolderResponse> {
+    let user = current_user(&request)?;
+    let name = read_valid_json::<FolderRequest>(request).await?.name.trim().to_string();
+
+    let state = &mut state.write().await;
+    let folder_count = folders
+        .filter(folder_user_id.eq(user.user_id))
+        .select(count_star())
+        .first::<i64>(&mut state.database);
+    match folder_count {
+        Ok(folder_count) if folder_count >= MAX_FOLDERS => {
+            return error_with_code(StatusCode::BAD_REQUEST, "too_many_folders", "You can't have more folders");
+        }
+        Ok(_) => {}
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create folder")
+    }
+
+    let folder = Folder {
+        folder_id: state.snowflake_issuer.generate().value() as i64,
+        user_id: user.user_id,
+        name,
+        created_at: Utc::now().naive_utc()
+    };
+    let inserted = diesel::insert_into(folders)
+        .values(&folder)
+        .execute(&mut state.database);
+    if inserted.is_err() {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create folder");
+    }
+
+    let folder = FolderResponse {
+        id: folder.folder_id,
+        name: folder.name,
+        channel_ids: Vec::new()
+    };
+    ok(announce_folder(state, user.user_id, folder).await)
+}
+
+// Errors: invalid_path, invalid_body, invalid_fields, not_found, internal_error
+pub async fn rename_folder(
+    Path(folder_id): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<FolderResponse> {
+    let user = current_user(&request)?;
+    let name = read_valid_json::<FolderRequest>(request).await?.name.trim().to_string();
+
+    let state = &mut state.write().await;
+    let renamed = diesel::update(
+        folders
+            .filter(table_folder_id.eq(folder_id))
+            .filter(folder_user_id.eq(user.user_id))
+    )
+        .set(folder_name.eq(name))
+        .returning(Folder::as_returning())
+        .get_result::<Folder>(&mut state.database);
+    let folder = match renamed.and_then(|folder| load_folder(&mut state.database, folder)) {
+        Ok(folder) => folder,
+        Err(diesel::NotFound) => return error(StatusCode::NOT_FOUND, "Folder not found"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to rename folder")
+    };
+    ok(announce_folder(state, user.user_id, folder).await)
+}
+
+// The conversations in it are only unfiled
+// Errors: invalid_path, not_found, internal_error
+pub async fn delete_folder(
+    Path(folder_id): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let deleted = diesel::delete(
+        folders
+            .filter(table_folder_id.eq(folder_id))
+            .filter(folder_user_id.eq(user.user_id))
+    ).execute(&mut state.database);
+    match deleted {
+        Ok(0) => error(StatusCode::NOT_FOUND, "Folder not found"),
+        Ok(_) => {
+            send_packet_to_user(&mut state.packet_queue, user.user_id, || Box::new(FolderDeleted {
+                folder_id
+            })).await;
+            no_content()
+        }
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete folder")
+    }
+}
+
+// Errors: invalid_path, not_found, forbidden, internal_error
+pub async fn add_folder_channel(
+    Path((folder_id, channel_id)): Path<(i64, i64)>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<FolderResponse> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let folder = match find_folder(&mut state.database, folder_id, user.user_id) {
+        Ok(folder) => folder,
+        Err(diesel::NotFound) => return error(StatusCode::NOT_FOUND, "Folder not found"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to add conversation to folder")
+    };
+    let is_member = diesel::select(exists(
+        channel_members
+            .filter(member_channel_id.eq(channel_id))
+            .filter(member_user_id.eq(user.user_id))
+    )).get_result::<bool>(&mut state.database);
+    match is_member {
+        Ok(true) => {}
+        Ok(false) => return error(StatusCode::FORBIDDEN, "You are not a member of this channel"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to add conversation to folder")
+    }
+
+    let filed = diesel::insert_into(folder_channels)
+        .values(&FolderChannel {
+            folder_id,
+            channel_id
+        })
+        .on_conflict_do_nothing()
+        .execute(&mut state.database);
+    let folder = match filed.and_then(|_| load_folder(&mut state.database, folder)) {
+        Ok(folder) => folder,
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to add conversation to folder")
+    };
+    ok(announce_folder(state, user.user_id, folder).await)
+}
+
+// Errors: invalid_path, not_found, internal_error
+pub async fn remove_folder_channel(
+    Path((folder_id, channel_id)): Path<(i64, i64)>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<FolderResponse> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let folder = match find_folder(&mut state.database, folder_id, user.user_id) {
+        Ok(folder) => folder,
+        Err(diesel::NotFound) => return error(StatusCode::NOT_FOUND, "Folder not found"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove conversation from folder")
+    };
+    let unfiled = diesel::delete(
+        folder_channels
+            .filter(filed_folder_id.eq(folder_id))
+            .filter(filed_channel_id.eq(channel_id))
+    ).execute(&mut state.database);
+    let folder = match unfiled.and_then(|_| load_folder(&mut state.database, folder)) {
+        Ok(folder) => folder,
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove conversation from folder")
+    };
+    ok(announce_folder(state, user.user_id, folder).await)
+}
+
+fn validate_folder_name(name: &str) -> Result<(), ValidationError> {
+    if name.is_empty() {
+        return Err(ValidationError::Blank);
+    }
+    validate_length(name, MAX_FOLDER_NAME_LENGTH)
+}
+
+fn find_folder(connection: &mut PgConnection, folder_id: i64, user: i64) -> QueryResult<Folder> {
+    folders
+        .filter(table_folder_id.eq(folder_id))
+        .filter(folder_user_id.eq(user))
+        .select(Folder::as_select())
+        .first::<Folder>(connection)
+}
+
+fn load_folder(connection: &mut PgConnection, folder: Folder) -> QueryResult<FolderResponse> {
+    let channel_ids = folder_channels
+        .filter(filed_folder_id.eq(folder.folder_id))
+        .select(filed_channel_id)
+        .load::<i64>(connection)?;
+    Ok(FolderResponse {
+        id: folder.folder_id,
+        name: folder.name,
+        channel_ids
+    })
+}
+
+// Keeps the user's other devices in sync
+async fn announce_folder(state: &mut AppState, user: i64, folder: FolderResponse) -> FolderResponse {
+    send_packet_to_user(&mut state.packet_queue, user, || Box::new(FolderUpdated {
+        folder: folder.clone()
+    })).await;
+    folder
+}
+
+#[derive(Deserialize)]
+pub struct FolderRequest {
+    pub name: String
+}
+
+impl Validate for FolderRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        check("name", validate_folder_name(self.name.trim())).into_iter().collect()
+    }
+}
@@ -0,0 +1,353 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Extension;
+use chrono::Utc;
+use diesel::dsl::exists;
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, JoinOnDsl, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
+use serde::{Deserialize, Serialize};
+
+use crate::schema::friends::{FriendRequest, Friendship};
+use crate::schema::friends::friend_requests::dsl::friend_requests;
+use crate::schema::friends::friend_requests::{created_at as request_created_at, recipient_id, sender_id};
+use crate::schema::friends::friendships::dsl::friendships;
+use crate::schema::friends::friendships::{friend_id, user_id as friendship_user_id};
+use crate::schema::users::{lower, User};
+use crate::schema::users::users::dsl::users;
+use crate::schema::users::users::{user_id as table_user_id, username};
+use crate::server::gateway::context::send_packet_to_user;
+use crate::server::gateway::messages::{FriendAdded, FriendRemoved, FriendRequestReceived, FriendRequestRemoved};
+use crate::server::rest::blocks::is_blocked_between;
+use crate::server::rest::contacts::{accept_dm, find_dm};
+use crate::server::rest::extract::Path;
+use crate::server::rest::{current_user, error, error_with_code, IrisResponse, no_content, ok, read_json, StandardUser};
+use crate::{AppState, SharedState};
+
+// Errors: internal_error
+pub async fn get_friends(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<Vec<StandardUser>> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let friends = users
+        .filter(table_user_id.eq_any(friendships.filter(friendship_user_id.eq(user.user_id)).select(friend_id)))
+        .select(User::as_select())
+        .load::<User>(&mut state.database);
+    match friends {
+        Ok(friends) => ok(friends.into_iter().map(StandardUser::from).collect()),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get friends")
+    }
+}
+
+// Errors: internal_error
+pub async fn get_friend_requests(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<FriendRequestsResponse> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let incoming = friend_requests
+        .inner_join(users.on(table_user_id.eq(sender_id)))
+        .filter(recipient_id.eq(user.user_id))
+        .order(request_created_at.desc())
+        .select((User::as_select(), request_created_at))
+        .load::<(User, chrono::NaiveDateTime)>(&mut state.database);
+    let outgoing = friend_requests
+        .inner_join(users.on(table_user_id.eq(recipient_id)))
+        .filter(sender_id.eq(user.user_id))
+        .order(request_created_at.desc())
+        .select((User::as_select(), request_created_at))
+        .load::<(User, chrono::NaiveDateTime)>(&mut state.database);
+    let (Ok(incoming), Ok(outgoing)) = (incoming, outgoing) else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get friend requests");
+    };
+
+    let to_response = |(user, created_at): (User, chrono::NaiveDateTime)| FriendRequestResponse {
+        user: StandardUser::from(user),
+        created_at: created_at.and_utc().timestamp()
+    };
+    ok(FriendRequestsResponse {
+        incoming: incoming.into_iter().map(to_response).collect(),
+        outgoing: outgoing.into_iter().map(to_response).collect()
+    })
+}
+
+// Sending a request to someone who already sent one accepts theirs
+// Errors: invalid_body, blocked, already_friends, not_found, bad_request, internal_error
+pub async fn send_friend_request(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<FriendshipResponse> {
+    let user = current_user(&request)?;
+    let request = read_json::<FriendRequestCreation>(request).await?;
+
+    let state = &mut state.write().await;
+    let target = users
+        .filter(lower(username).eq(lower(request.username.trim())))
+        .select(User::as_select())
+        .first::<User>(&mut state.database);
+    let target = match target {
+        Ok(target) => target,
+        Err(diesel::NotFound) => return error(StatusCode::NOT_FOUND, "User not found"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to send friend request")
+    };
+    if target.user_id == user.user_id {
+        return error(StatusCode::BAD_REQUEST, "You can't add yourself");
+    }
+
+    match is_blocked_between(&mut state.database, user.user_id, target.user_id) {
+        Ok(false) => {}
+        Ok(true) => return error_with_code(StatusCode::FORBIDDEN, "blocked", "You can't add this user"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to send friend request")
+    }
+    match are_friends(&mut state.database, user.user_id, target.user_id) {
+        Ok(false) => {}
+        Ok(true) => return error_with_code(StatusCode::CONFLICT, "already_friends", "You are already friends"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to send friend request")
+    }
+
+    let reverse = diesel::select(exists(
+        friend_requests
+            .filter(sender_id.eq(target.user_id))
+            .filter(recipient_id.eq(user.user_id))
+    )).get_result::<bool>(&mut state.database);
+    match reverse {
+        Ok(true) => {
+            if befriend(&mut state.database, target.user_id, user.user_id).is_err() {
+                return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to accept friend request");
+            }
+            announce_friendship(state, &user, &target).await;
+            return ok(FriendshipResponse {
+                user: StandardUser::from(target),
+                status: FriendshipStatus::Friends
+            });
+        }
+        Ok(false) => {}
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to send friend request")
+    }
+
+    let friend_request = FriendRequest {
+        sender_id: user.user_id,
+        recipient_id: target.user_id,
+        created_at: Utc::now().naive_utc()
+    };
+    let inserted = diesel::insert_into(friend_requests)
+        .values(&friend_request)
+        .on_conflict_do_nothing()
+        .execute(&mut state.database);
+    match inserted {
+        // Already pending, nothing to announce again
+        Ok(0) => {}
+        Ok(_) => {
+            let sender = StandardUser::from(user.clone());
+            let created_at = friend_request.created_at.and_utc().timestamp();
+            send_packet_to_user(&mut state.packet_queue, target.user_id, || Box::new(FriendRequestReceived {
+                user: sender.clone(),
+                created_at
+            })).await;
+        }
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to send friend request")
+    }
+
+    ok(FriendshipResponse {
+        user: StandardUser::from(target),
+        status: FriendshipStatus::Pending
+    })
+}
+
+// Errors: invalid_path, not_found, internal_error
+pub async fn accept_friend_request(
+    Path(sender): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<StandardUser> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let sender = users
+        .filter(table_user_id.eq(sender))
+        .select(User::as_select())
+        .first::<User>(&mut state.database);
+    let Ok(sender) = sender else {
+        return error(StatusCode::NOT_FOUND, "Friend request not found");
+    };
+    match befriend(&mut state.database, sender.user_id, user.user_id) {
+        Ok(true) => {}
+        Ok(false) => return error(StatusCode::NOT_FOUND, "Friend request not found"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to accept friend request")
+    }
+
+    announce_friendship(state, &user, &sender).await;
+    ok(StandardUser::from(sender))
+}
+
+// Errors: invalid_path, not_found, internal_error
+pub async fn decline_friend_request(
+    Path(sender): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    remove_friend_request(state, sender, user.user_id, sender).await
+}
+
+// Errors: invalid_path, not_found, internal_error
+pub async fn cancel_friend_request(
+    Path(recipient): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    remove_friend_request(state, user.user_id, recipient, recipient).await
+}
+
+// Errors: invalid_path, not_found, internal_error
+pub async fn remove_friend(
+    Path(friend): Path<i64>,
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let deleted = diesel::delete(
+        friendships.filter(
+            friendship_user_id.eq(user.user_id).and(friend_id.eq(friend))
+                .or(friendship_user_id.eq(friend).and(friend_id.eq(user.user_id)))
+        )
+    ).execute(&mut state.database);
+    match deleted {
+        Ok(0) => error(StatusCode::NOT_FOUND, "Friend not found"),
+        Ok(_) => {
+            send_packet_to_user(&mut state.packet_queue, friend, || Box::new(FriendRemoved {
+                user_id: user.user_id
+            })).await;
+            no_content()
+        }
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove friend")
+    }
+}
+
+pub fn are_friends(connection: &mut PgConnection, user: i64, other: i64) -> QueryResult<bool> {
+    diesel::select(exists(
+        friendships
+            .filter(friendship_user_id.eq(user))
+            .filter(friend_id.eq(other))
+    )).get_result::<bool>(connection)
+}
+
+// Drops the friendship and pending requests between two users, returning whether they were friends
+pub fn end_relationship(connection: &mut PgConnection, user: i64, other: i64) -> QueryResult<bool> {
+    connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        diesel::delete(
+            friend_requests.filter(
+                sender_id.eq(user).and(recipient_id.eq(other))
+                    .or(sender_id.eq(other).and(recipient_id.eq(user)))
+            )
+        ).execute(connection)?;
+        let deleted = diesel::delete(
+            friendships.filter(
+                friendship_user_id.eq(user).and(friend_id.eq(other))
+                    .or(friendship_user_id.eq(other).and(friend_id.eq(user)))
+            )
+        ).execute(connection)?;
+        Ok(deleted > 0)
+    })
+}
+
+// Turns the pending request from `sender` to `recipient` into a friendship, false if there was none
+fn befriend(connection: &mut PgConnection, sender: i64, recipient: i64) -> QueryResult<bool> {
+    connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        let deleted = diesel::delete(
+            friend_requests
+                .filter(sender_id.eq(sender))
+                .filter(recipient_id.eq(recipient))
+        ).execute(connection)?;
+        if deleted == 0 {
+            return Ok(false);
+        }
+        let now = Utc::now().naive_utc();
+        diesel::insert_into(friendships)
+            .values(&vec![
+                Friendship { user_id: sender, friend_id: recipient, created_at: now },
+                Friendship { user_id: recipient, friend_id: sender, created_at: now }
+            ])
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+        if let Some(channel_id) = find_dm(connection, sender, recipient)? {
+            accept_dm(connection, channel_id, sender)?;
+            accept_dm(connection, channel_id, recipient)?;
+        }
+        Ok(true)
+    })
+}
+
+// Both sides learn about the new friendship, on every connection
+async fn announce_friendship(state: &mut AppState, user: &User, friend: &User) {
+    for (recipient, other) in [(user, friend), (friend, user)] {
+        let other = StandardUser::from(other.clone());
+        send_packet_to_user(&mut state.packet_queue, recipient.user_id, || Box::new(FriendAdded {
+            user: other.clone()
+        })).await;
+    }
+}
+
+// Used for both declining and cancelling, `notified` is the other side of the request
+async fn remove_friend_request(
+    state: SharedState,
+    sender: i64,
+    recipient: i64,
+    notified: i64
+) -> IrisResponse<()> {
+    let state = &mut state.write().await;
+
+    let deleted = diesel::delete(
+        friend_requests
+            .filter(sender_id.eq(sender))
+            .filter(recipient_id.eq(recipient))
+    ).execute(&mut state.database);
+    match deleted {
+        Ok(0) => error(StatusCode::NOT_FOUND, "Friend request not found"),
+        Ok(_) => {
+            let other = if notified == sender { recipient } else { sender };
+            send_packet_to_user(&mut state.packet_queue, notified, || Box::new(FriendRequestRemoved {
+                user_id: other
+            })).await;
+            no_content()
+        }
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove friend request")
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FriendRequestCreation {
+    pub username: String
+}
+
+#[derive(Serialize)]
+pub struct FriendRequestResponse {
+    pub user: StandardUser,
+    // Unix timestamp in seconds
+    pub created_at: i64
+}
+
+#[derive(Serialize)]
+pub struct FriendRequestsResponse {
+    pub incoming: Vec<FriendRequestResponse>,
+    pub outgoing: Vec<FriendRequestResponse>
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FriendshipStatus {
+    Pending,
+    Friends
+}
+
+#[derive(Serialize)]
+pub struct FriendshipResponse {
+    pub user: StandardUser,
+    pub status: FriendshipStatus
+}
@@ -1,38 +1,65 @@
-use axum::{debug_handler, Extension, Json};
+use axum::{debug_handler, Extension};
 use axum::body::Body;
-use axum::extract::Path;
 use axum::http::{Request, StatusCode};
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, sql_query, Table};
-use diesel::dsl::{exists};
-use diesel::sql_types::{BigInt, Bool, Nullable, SmallInt, Text};
-use http_body_util::BodyExt;
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl, Table};
+use diesel::dsl::{exists, max};
 use serde::Deserialize;
 use crate::schema::channels::channel_members::dsl::channel_members;
-use crate::schema::ctes::select_messages_from;
+use crate::schema::channels::channel_members::{channel_id as member_channel_id, last_read_message_id, unread_count, user_id as member_user_id};
+use crate::schema::channels::channels::dsl::channels;
+use crate::schema::channels::channels::{channel_id as channels_channel_id, last_message_id};
+use crate::schema::message_query::MessageQuery;
 use crate::schema::messages::{CompleteMessage, Message};
-use crate::schema::messages::messages::{channel_id as messageChannelId, message_id as messageId, user_id};
+use crate::schema::messages::messages::{channel_id as messageChannelId, content as messageContent, edited as messageEdited, message_id as messageId, user_id};
 use crate::schema::messages::messages::dsl::messages as messagesTable;
 use crate::schema::messages::messages::dsl::messages;
-use crate::schema::users::User;
 use crate::server::gateway::context::{send_packet_to_channel};
+use crate::server::rest::blocks::is_blocked_dm;
+use crate::server::rest::contacts::{accept_dm, dm_recipient, unhide_conversation};
+use crate::server::rest::settings::can_message;
 use crate::server::gateway::messages::{MessageCreated, MessageDeleted, MessageEdited};
-use crate::server::rest::{error, IrisResponse, MessageObject, no_content, ok, StandardUser};
+use crate::server::rest::emojis::resolve_message_emojis;
+use crate::server::rest::extract::{Path, Query};
+use crate::server::rest::validation::{check, Validate};
+use crate::server::rest::{current_user, error, error_with_code, FieldError, IrisError, IrisResponse, MessageObject, no_content, ok, read_valid_json, ReplyPreview, StandardUser};
+use crate::util::validation::validate_content;
 use crate::SharedState;
 
+const MAX_MESSAGES_LIMIT: i64 = 100;
+
+// Errors: invalid_path, invalid_body, invalid_fields, forbidden, email_unverified, blocked, dm_not_allowed, not_found, internal_error
 #[debug_handler]
 pub async fn create_message(
     Path(channel_id): Path<i64>,
     Extension(state): Extension<SharedState>,
     request: Request<Body>
 ) -> IrisResponse<MessageObject> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
-    let message = Json::from_bytes(request.into_body().collect().await.unwrap().to_bytes().as_ref());
-    if message.is_err() {
-        return error(StatusCode::BAD_REQUEST, "Invalid message")
-    }
-    let message: MessageCreationRequest = message.unwrap().0;
+    let user = current_user(&request)?;
+    let message = read_valid_json::<MessageCreationRequest>(request).await?;
 
     let mut state = state.write().await;
+    if !is_channel_member(&mut state.database, channel_id, user.user_id)? {
+        return error(StatusCode::FORBIDDEN, "You are not a member of this channel");
+    }
+    if state.config.auth.require_verified_email && !user.email_verified {
+        return error_with_code(StatusCode::FORBIDDEN, "email_unverified", "Verify your email before sending messages");
+    }
+    match is_blocked_dm(&mut state.database, channel_id, user.user_id) {
+        Ok(false) => {}
+        Ok(true) => return error_with_code(StatusCode::FORBIDDEN, "blocked", "You can't message this user"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Error inserting message")
+    }
+    // Once the recipient accepted the DM their settings no longer matter, until then they decide who can write
+    let allowed = match dm_recipient(&mut state.database, channel_id, user.user_id) {
+        Ok(Some((recipient, false))) => can_message(&mut state.database, user.user_id, recipient),
+        Ok(_) => Ok(true),
+        Err(err) => Err(err)
+    };
+    match allowed {
+        Ok(true) => {}
+        Ok(false) => return error_with_code(StatusCode::FORBIDDEN, "dm_not_allowed", "This user doesn't accept messages from you"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Error inserting message")
+    }
 
     if let Some(reply) = message.reply_to {
         let query = diesel::select(exists(
@@ -40,55 +67,34 @@ pub async fn create_message(
                 .filter(messageChannelId.eq(channel_id))
                 .filter(messageId.eq(reply))
         )).get_result::<bool>(&mut state.database);
-
-        if query.is_err() {
-            return error(StatusCode::NOT_FOUND, "Reply not found");
-        }
-        let result = query.unwrap();
-        if !result {
+        if !matches!(query, Ok(true)) {
             return error(StatusCode::NOT_FOUND, "Reply not found");
         }
     }
 
     let id: i64 = { state.snowflake_issuer.generate().value() as i64 };
-    let query = sql_query(select_messages_from(
-        r#"
-        INSERT INTO messages (user_id, message_id, content, channel_id, reception_status, edited, reply_to)
-        VALUES ($1, $2, $3, $4, $5, $6, $7)
-        RETURNING *
-        "#
-    ))
-        .bind::<BigInt, _>(user.user_id)
-        .bind::<BigInt, _>(id)
-        .bind::<Text, _>(message.content.clone())
-        .bind::<BigInt, _>(channel_id)
-        .bind::<SmallInt, _>(0)
-        .bind::<Bool, _>(false)
-        .bind::<Nullable<BigInt>, _>(message.reply_to)
-        .get_result::<CompleteMessage>(&mut state.database);
-
-    if query.is_err() {
-        query.unwrap();
+    let new_message = Message {
+        message_id: id,
+        user_id: user.user_id,
+        content: message.content,
+        channel_id,
+        reception_status: 0,
+        edited: false,
+        reply_to: message.reply_to
+    };
+    let query = state.database.transaction::<_, diesel::result::Error, _>(|connection| {
+        diesel::insert_into(messages).values(&new_message).execute(connection)?;
+        record_message(connection, channel_id, id, user.user_id)?;
+        MessageQuery::viewed_by(user.user_id).id(id).complete().first(connection)
+    });
+
+    let Ok(inserted_message) = query else {
         return error(StatusCode::INTERNAL_SERVER_ERROR, "Error inserting message");
-    }
-    let inserted_message = query.unwrap();
-
-    let message = MessageObject {
-        id: inserted_message.message_id,
-        user_id: inserted_message.user_id,
-        content: inserted_message.content,
-        channel_id: inserted_message.channel_id,
-        receipt: inserted_message.reception_status,
-        edited: inserted_message.edited,
-        author: StandardUser {
-            id: inserted_message.user_id,
-            name: inserted_message.author_name.clone(),
-            username: inserted_message.author_username.clone()
-        },
-        reply_to: inserted_message.reply_to,
-        reactions: vec![]
     };
-    send_packet_to_channel(&mut state, channel_id, || Box::new(MessageCreated {
+    let _ = accept_dm(&mut state.database, channel_id, user.user_id);
+    let _ = unhide_conversation(&mut state.database, channel_id);
+    let message = message_object(&mut state.database, inserted_message)?;
+    send_packet_to_channel(&mut state, channel_id, user.user_id, || Box::new(MessageCreated {
         message: message.clone()
     })).await;
 
@@ -96,129 +102,103 @@ pub async fn create_message(
 }
 
 // This method will get the messages between the user and the specified contact
+// Errors: invalid_path, invalid_query, forbidden
 pub async fn get_messages(
     Path(channel_id): Path<i64>,
     Extension(state): Extension<SharedState>,
+    Query(params): Query<MessagesParams>,
     request: Request<Body>
 ) -> IrisResponse<Vec<MessageObject>> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
+    let user = current_user(&request)?;
 
     let connection = &mut state.write().await.database;
 
-    {
-        let is_member = diesel::select(
-            exists(
-                channel_members
-                    .filter(crate::schema::channels::channel_members::user_id.eq(user.user_id))
-                    .filter(crate::schema::channels::channel_members::channel_id.eq(channel_id))
-            )
-        ).get_result::<bool>(connection).expect("Error checking if user is a member");
-
-        if !is_member {
-            return error(StatusCode::FORBIDDEN, "You are not a member of this channel");
-        }
+    if !is_channel_member(connection, channel_id, user.user_id)? {
+        return error(StatusCode::FORBIDDEN, "You are not a member of this channel");
     }
 
-    let query = sql_query(select_messages_from(
-        "SELECT * FROM messages WHERE channel_id = $2"
-    ))
-        .bind::<BigInt, _>(user.user_id)
-        .bind::<BigInt, _>(channel_id);
-    let bilateral_messages = query.load::<CompleteMessage>(connection).expect("Error loading messages");
+    let mut query = MessageQuery::viewed_by(user.user_id)
+        .in_channel(channel_id)
+        .complete();
+    if let Some(limit) = params.limit {
+        query = query.limit(limit.clamp(1, MAX_MESSAGES_LIMIT));
+    }
+    if let Some(before) = params.before {
+        query = query.before(before);
+    }
+    if let Some(after) = params.after {
+        query = query.after(after);
+    }
+    if let Some(author) = params.author {
+        query = query.by_author(author);
+    }
+    let bilateral_messages = query.load(connection)?;
 
-    ok(bilateral_messages.iter().map(|m| {
-        MessageObject {
-            id: m.message_id,
-            user_id: m.user_id,
-            content: m.content.clone(),
-            channel_id: m.channel_id,
-            receipt: m.reception_status,
-            edited: m.edited,
-            author: StandardUser {
-                id: m.user_id,
-                name: m.author_name.clone(),
-                username: m.author_username.clone()
-            },
-            reply_to: m.reply_to,
-            reactions: serde_json::from_str(&m.reactions).unwrap()
-        }
-    }).collect())
+    ok(message_objects(connection, bilateral_messages)?)
 }
 
+// Errors: invalid_path, invalid_body, invalid_fields, not_found, internal_error
 pub async fn edit_message(
     Path((channel_id, message_id)): Path<(i64, i64)>,
     Extension(state): Extension<SharedState>,
     request: Request<Body>
 ) -> IrisResponse<MessageObject> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
-    let message = Json::from_bytes(request.into_body().collect().await.unwrap().to_bytes().as_ref());
-    if message.is_err() {
-        return error(StatusCode::BAD_REQUEST, "Invalid message")
-    }
-    let message: Json<MessageCreationRequest> = message.unwrap();
-
-    let new_content = message.0.content;
+    let user = current_user(&request)?;
+    let new_content = read_valid_json::<MessageCreationRequest>(request).await?.content;
     let mut state = state.write().await;
-    let message = diesel::sql_query(select_messages_from(
-        "UPDATE messages SET content = $2, edited = true WHERE channel_id = $3 AND message_id = $4 and user_id=$1 RETURNING *"
-    ))
-        .bind::<BigInt, _>(user.user_id)
-        .bind::<Text, _>(new_content.clone())
-        .bind::<BigInt, _>(channel_id)
-        .bind::<BigInt, _>(message_id)
-        .get_result::<CompleteMessage>(&mut state.database);
-
-    if message.is_err() {
-        message.unwrap();
+    let updated = diesel::update(
+        messages
+            .filter(messageChannelId.eq(channel_id))
+            .filter(messageId.eq(message_id))
+            .filter(user_id.eq(user.user_id))
+    )
+        .set((messageContent.eq(&new_content), messageEdited.eq(true)))
+        .execute(&mut state.database);
+    let message = match updated {
+        Ok(0) => Err(diesel::NotFound),
+        Ok(_) => MessageQuery::viewed_by(user.user_id).id(message_id).complete().first(&mut state.database),
+        Err(err) => Err(err)
+    };
+
+    let Ok(message) = message else {
         return error(StatusCode::NOT_FOUND, "Message not found");
-    }
-    let message = message.unwrap();
-    let object = MessageObject {
-        id: message.message_id,
-        user_id: message.user_id,
-        content: message.content,
-        channel_id: message.channel_id,
-        receipt: message.reception_status,
-        edited: message.edited,
-        author: StandardUser {
-            id: message.user_id,
-            name: message.author_name.clone(),
-            username: message.author_username.clone()
-        },
-        reply_to: message.reply_to,
-        reactions: serde_json::from_str(&message.reactions).unwrap()
     };
+    let object = message_object(&mut state.database, message)?;
 
-    send_packet_to_channel(&mut state, channel_id, || Box::new(MessageEdited {
+    send_packet_to_channel(&mut state, channel_id, user.user_id, || Box::new(MessageEdited {
         new_content: new_content.clone(),
         editor_id: user.user_id,
-        message_id: message.message_id,
-        channel_id: message.channel_id,
+        message_id: object.id,
+        channel_id: object.channel_id,
     })).await;
 
     ok(object)
 }
 
+// Errors: invalid_path, not_found
 pub async fn delete_message(
     Path((channel_id, message_id)): Path<(i64, i64)>,
     Extension(state): Extension<SharedState>,
     request: Request<Body>
 ) -> IrisResponse<()> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
+    let user = current_user(&request)?;
 
     let mut state = state.write().await;
     let query = messages
         .filter(messageChannelId.eq(channel_id))
         .filter(messageId.eq(message_id))
         .filter(user_id.eq(user.user_id));
-    let deleted = diesel::delete(query).returning(messagesTable::all_columns()).get_result::<Message>(&mut state.database);
+    let deleted = state.database.transaction::<_, diesel::result::Error, _>(|connection| {
+        let deleted = diesel::delete(query).returning(messagesTable::all_columns()).get_result::<Message>(connection)?;
+        forget_message(connection, &deleted)?;
+        Ok(deleted)
+    });
 
-    if deleted.is_err() {
+    let Ok(message) = deleted else {
         return error(StatusCode::NOT_FOUND, "Message not found");
-    }
-    let message = deleted.unwrap();
+    };
 
-    send_packet_to_channel(&mut state, channel_id, || Box::new(MessageDeleted {
+    send_packet_to_channel(&mut state, channel_id, user.user_id, || Box::new(MessageDeleted {
         message_id: message.message_id,
         channel_id: message.channel_id
     })).await;
@@ -226,9 +206,112 @@ pub async fn delete_message(
     no_content()
 }
 
+pub fn is_channel_member(connection: &mut PgConnection, channel_identifier: i64, user_identifier: i64) -> QueryResult<bool> {
+    diesel::select(exists(
+        channel_members
+            .filter(member_user_id.eq(user_identifier))
+            .filter(member_channel_id.eq(channel_identifier))
+    )).get_result::<bool>(connection)
+}
+
+fn message_object(connection: &mut PgConnection, loaded: CompleteMessage) -> Result<MessageObject, IrisError> {
+    message_objects(connection, vec![loaded])?.pop().ok_or_else(|| IrisError::internal("Failed to load message"))
+}
+
+fn message_objects(connection: &mut PgConnection, loaded: Vec<CompleteMessage>) -> Result<Vec<MessageObject>, serde_json::Error> {
+    let emojis = resolve_message_emojis(
+        connection,
+        &loaded.iter().map(|m| (m.user_id, m.channel_id, m.content.as_str())).collect::<Vec<_>>()
+    );
+
+    loaded.into_iter().zip(emojis).map(|(m, emojis)| {
+        let reactions = match m.reactions {
+            Some(reactions) => serde_json::from_str(&reactions)?,
+            None => Vec::new()
+        };
+        Ok(MessageObject {
+            id: m.message_id,
+            user_id: m.user_id,
+            content: m.content,
+            channel_id: m.channel_id,
+            receipt: m.reception_status,
+            edited: m.edited,
+            author: StandardUser {
+                id: m.user_id,
+                name: m.author_name.unwrap_or_default(),
+                username: m.author_username.unwrap_or_default()
+            },
+            reply: m.reply_to.zip(m.reply_user_id).zip(m.reply_content).map(|((id, author), content)| ReplyPreview {
+                id,
+                user_id: author,
+                content
+            }),
+            reply_to: m.reply_to,
+            reactions,
+            emojis
+        })
+    }).collect()
+}
+
+// Channels keep their latest message and each member's unread count, so listing conversations doesn't scan messages
+fn record_message(connection: &mut PgConnection, channel_id: i64, message_id: i64, sender: i64) -> QueryResult<()> {
+    diesel::update(channels.filter(channels_channel_id.eq(channel_id)))
+        .set(last_message_id.eq(message_id))
+        .execute(connection)?;
+    diesel::update(
+        channel_members
+            .filter(member_channel_id.eq(channel_id))
+            .filter(member_user_id.ne(sender))
+    )
+        .set(unread_count.eq(unread_count + 1))
+        .execute(connection)?;
+    Ok(())
+}
+
+fn forget_message(connection: &mut PgConnection, message: &Message) -> QueryResult<()> {
+    // Only members who hadn't read up to it were counting it
+    diesel::update(
+        channel_members
+            .filter(member_channel_id.eq(message.channel_id))
+            .filter(member_user_id.ne(message.user_id))
+            .filter(unread_count.gt(0))
+            .filter(last_read_message_id.is_null().or(last_read_message_id.lt(message.message_id)))
+    )
+        .set(unread_count.eq(unread_count - 1))
+        .execute(connection)?;
+    let latest = messages
+        .filter(messageChannelId.eq(message.channel_id))
+        .select(max(messageId))
+        .single_value();
+    diesel::update(
+        channels
+            .filter(channels_channel_id.eq(message.channel_id))
+            .filter(last_message_id.eq(message.message_id))
+    )
+        .set(last_message_id.eq(latest))
+        .execute(connection)?;
+    Ok(())
+}
+
+// Newest first, `before` and `after` page from a message ID
+// Without a `limit` the whole history is returned
+#[derive(Deserialize)]
+pub struct MessagesParams {
+    pub before: Option<i64>,
+    pub after: Option<i64>,
+    pub author: Option<i64>,
+    pub limit: Option<i64>
+}
+
 #[derive(Deserialize)]
 pub struct MessageCreationRequest {
     pub content: String,
     #[serde(default)]
     pub reply_to: Option<i64>
+}
+
+impl Validate for MessageCreationRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        check("content", validate_content(&self.content)).into_iter().collect()
+    }
 }
\ No newline at end of file
@@ -1,4 +1,7 @@
 use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Instant;
 
 use axum::{
     extract::Request,
@@ -6,57 +9,226 @@ use axum::{
     middleware::Next,
     response::Response,
 };
+use axum::body::Body;
+use axum::extract::{ConnectInfo, MatchedPath};
+use axum::http::HeaderValue;
 use axum::response::IntoResponse;
+use chrono::{Duration, Utc};
 use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
 use jwt::VerifyWithKey;
 
+use crate::schema::sessions::Session;
+use crate::schema::sessions::sessions::dsl::sessions;
+use crate::schema::sessions::sessions::{last_used_at, session_id};
 use crate::schema::users::User;
 use crate::schema::users::users::dsl::users;
 use crate::schema::users::users::user_id as table_user_id;
-use crate::server::rest::error;
+use crate::server::rest::auth::find_by_identifier;
+use crate::server::rest::{error, error_with_code, IrisError};
+use crate::util::rate_limit::{Decision, RateLimiter};
 use crate::SharedState;
 
+// How stale a session's last use may get before it's written again, to avoid a write per request
+const LAST_USED_PRECISION: i64 = 60;
+
+// Login bodies are tiny, anything bigger isn't a login attempt
+const MAX_LOCKOUT_BODY: usize = 16 * 1024;
+
+// What users without 2FA can still reach when the server requires it
+const TWO_FACTOR_ENROLLMENT_PATHS: [&str; 3] = ["/api/users/@me", "/api/users/@me/2fa", "/api/users/@me/2fa/confirm"];
+
 pub async fn authorize(mut req: Request, next: Next) -> Response {
     let headers = req.headers().clone();
-    let auth = headers.get("Authorization").or(headers.get("Sec-Websocket-Protocol"));
-    if auth.is_none() {
+    let Some(auth) = headers.get("Authorization").or(headers.get("Sec-Websocket-Protocol")) else {
         return error::<String>(StatusCode::UNAUTHORIZED, "No authorization header provided").into_response();
+    };
+    let Ok(auth) = auth.to_str() else {
+        return error::<String>(StatusCode::UNAUTHORIZED, "Invalid authorization header").into_response();
+    };
+    let Some(token) = auth.split_whitespace().last() else {
+        return error_with_code::<String>(StatusCode::UNAUTHORIZED, "invalid_token", "Invalid token").into_response();
+    };
+
+    let path = req.uri().path().to_owned();
+    let extensions = req.extensions_mut();
+    let Some(state) = extensions.get::<SharedState>().cloned() else {
+        return IrisError::internal("Something went wrong").into_response();
+    };
+    let (user, require_two_factor) = {
+        let mut state = state.write().await;
+        let claims: Result<BTreeMap<String, i64>, jwt::error::Error> = token.verify_with_key(&state.jwt_key);
+        let Ok(claims) = claims else {
+            return error_with_code::<String>(StatusCode::UNAUTHORIZED, "invalid_token", "Invalid token").into_response();
+        };
+
+        // Tokens without an expiry predate sessions and are no longer honored
+        let (Some(user_id), Some(session), Some(expiry)) = (claims.get("id"), claims.get("sid"), claims.get("exp")) else {
+            return error_with_code::<String>(StatusCode::UNAUTHORIZED, "invalid_token", "Invalid token").into_response();
+        };
+        if *expiry <= Utc::now().timestamp() {
+            return error_with_code::<String>(StatusCode::UNAUTHORIZED, "token_expired", "Token has expired").into_response();
+        }
+
+        // Revoked sessions stop working right away instead of when their access token expires
+        let found = users
+            .inner_join(sessions)
+            .filter(table_user_id.eq(*user_id))
+            .filter(session_id.eq(*session))
+            .select((User::as_select(), Session::as_select()))
+            .first::<(User, Session)>(&mut state.database);
+
+        if let Ok((_, session)) = &found {
+            let now = Utc::now().naive_utc();
+            if now - session.last_used_at >= Duration::seconds(LAST_USED_PRECISION) {
+                let _ = diesel::update(sessions.filter(session_id.eq(session.session_id)))
+                    .set(last_used_at.eq(now))
+                    .execute(&mut state.database);
+            }
+        }
+        (found, state.config.auth.require_two_factor)
+    };
+    let Ok((user, session)) = user else {
+        return error_with_code::<String>(StatusCode::UNAUTHORIZED, "invalid_token", "Invalid token").into_response();
+    };
+    if require_two_factor && !user.two_factor_enabled && !TWO_FACTOR_ENROLLMENT_PATHS.contains(&path.as_str()) {
+        return error_with_code::<String>(
+            StatusCode::FORBIDDEN,
+            "two_factor_enrollment_required",
+            "Two-factor authentication must be enabled first"
+        ).into_response();
     }
 
-    let auth = auth.unwrap().to_str();
-    if auth.is_err() {
-        return error::<String>(StatusCode::UNAUTHORIZED, "Invalid authorization header").into_response();
+    extensions.insert(user);
+    extensions.insert(session);
+    next.run(req).await
+}
+
+// The address requests are attributed to, set by `limit_by_ip` for everything after it
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+pub async fn limit_by_ip(mut req: Request, next: Next) -> Response {
+    let limiter = req.extensions().get::<Arc<RateLimiter>>().cloned().expect("Rate limiter not found");
+    let Some(ip) = client_ip(&req, limiter.config().trust_proxy) else {
+        return next.run(req).await;
+    };
+    req.extensions_mut().insert(ClientIp(ip));
+    if !limiter.config().enabled {
+        return next.run(req).await;
     }
 
-    let parts: Vec<&str> = auth.unwrap().split_whitespace().collect();
-    let token = parts.last();
-    if token.is_none() {
-        return error::<String>(StatusCode::UNAUTHORIZED, "Invalid token").into_response();
+    let now = Instant::now();
+    let route = req.extensions().get::<MatchedPath>().map(|path| path.as_str().to_owned()).unwrap_or_default();
+    let route_limit = limiter.route(&route).cloned().unwrap_or_default();
+    // Routes with their own limit get their own bucket, the rest share one per address
+    let decision = match route_limit.ip {
+        Some(limit) => limiter.check(&format!("ip:{}:{}", ip, route), limit, now),
+        None => limiter.check(&format!("ip:{}", ip), limiter.config().ip, now)
+    };
+    if decision.retry_after.is_some() {
+        return too_many_requests(&decision, "rate_limited", "Too many requests");
+    }
+    if !route_limit.lockout {
+        return with_rate_limit_headers(next.run(req).await, &decision);
     }
 
-    let extensions = req.extensions_mut();
-    let user = {
-        let mut state = extensions.get_mut::<SharedState>().unwrap().write().await;
-        let claims: Result<BTreeMap<String, i64>, jwt::error::Error> = token.unwrap().verify_with_key(&state.jwt_key);
-        if claims.is_err() {
-            return error::<String>(StatusCode::UNAUTHORIZED, "Invalid token").into_response();
-        }
-        let claims = claims.unwrap();
+    // The account is locked out along with the address, so spreading attempts over addresses doesn't help
+    let (parts, body) = req.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_LOCKOUT_BODY).await else {
+        return error::<String>(StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large").into_response();
+    };
+    let identifier = serde_json::from_slice::<serde_json::Value>(&bytes).ok()
+        .and_then(|body| body.get("identifier")?.as_str().map(|identifier| identifier.trim().to_lowercase()));
+    let req = Request::from_parts(parts, Body::from(bytes));
+    let state = req.extensions().get::<SharedState>().cloned();
+    let account = match identifier {
+        Some(identifier) => Some(lockout_account(state, &identifier).await),
+        None => None
+    };
+
+    let mut keys = vec![format!("lockout:ip:{}", ip)];
+    keys.extend(account.map(|account| format!("lockout:account:{}", account)));
+    if let Some(wait) = keys.iter().filter_map(|key| limiter.locked_for(key, now)).max() {
+        let locked = Decision { retry_after: Some(wait), ..decision };
+        return too_many_requests(&locked, "too_many_failed_attempts", "Too many failed attempts, try again later");
+    }
 
-        let user_id: Option<&i64> = claims.get("id");
-        if user_id.is_none() {
-            return error::<String>(StatusCode::UNAUTHORIZED, "Invalid token").into_response();
+    let response = next.run(req).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        for key in &keys {
+            limiter.record_failure(key, now);
         }
+    } else if response.status().is_success() {
+        // Only the account is forgiven, an address trying many accounts keeps its count
+        for key in keys.iter().skip(1) {
+            limiter.record_success(key);
+        }
+    }
+    with_rate_limit_headers(response, &decision)
+}
 
-        users
-            .filter(table_user_id.eq(*user_id.unwrap()))
-            .select(User::as_select())
-            .first::<User>(&mut state.database)
+// Accounts are counted per user, so alternating between the username and the email doesn't get more attempts.
+// Identifiers that don't belong to anyone are counted as written.
+async fn lockout_account(state: Option<SharedState>, identifier: &str) -> String {
+    let Some(state) = state else {
+        return format!("identifier:{}", identifier);
     };
-    if user.is_err() {
-        return error::<String>(StatusCode::UNAUTHORIZED, "Invalid token").into_response();
+    let found = find_by_identifier(&mut state.write().await.database, identifier);
+    match found {
+        Ok(user) => format!("user:{}", user.user_id),
+        Err(_) => format!("identifier:{}", identifier)
     }
+}
 
-    extensions.insert(user.unwrap());
-    next.run(req).await
-}
\ No newline at end of file
+// Runs after `authorize`, so authenticated routes are also limited per user whatever the address
+pub async fn limit_by_user(req: Request, next: Next) -> Response {
+    let limiter = req.extensions().get::<Arc<RateLimiter>>().cloned().expect("Rate limiter not found");
+    let Some(user) = req.extensions().get::<User>().map(|user| user.user_id) else {
+        return next.run(req).await;
+    };
+    if !limiter.config().enabled {
+        return next.run(req).await;
+    }
+
+    let route = req.extensions().get::<MatchedPath>().map(|path| path.as_str().to_owned()).unwrap_or_default();
+    let decision = match limiter.route(&route).and_then(|route_limit| route_limit.user) {
+        Some(limit) => limiter.check(&format!("user:{}:{}", user, route), limit, Instant::now()),
+        None => limiter.check(&format!("user:{}", user), limiter.config().user, Instant::now())
+    };
+    if decision.retry_after.is_some() {
+        return too_many_requests(&decision, "rate_limited", "Too many requests");
+    }
+    with_rate_limit_headers(next.run(req).await, &decision)
+}
+
+// Behind a trusted proxy the last `X-Forwarded-For` entry is the one the proxy added itself
+fn client_ip(req: &Request, trust_proxy: bool) -> Option<IpAddr> {
+    let forwarded = req.headers().get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|_| trust_proxy);
+    forwarded.or_else(|| req.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(address)| address.ip()))
+}
+
+fn too_many_requests(decision: &Decision, code: &'static str, message: &str) -> Response {
+    let mut response = error_with_code::<String>(StatusCode::TOO_MANY_REQUESTS, code, message).into_response();
+    let retry_after = decision.retry_after.unwrap_or_default().as_secs_f64().ceil() as u64;
+    response.headers_mut().insert("Retry-After", HeaderValue::from(retry_after.max(1)));
+    with_rate_limit_headers(response, decision)
+}
+
+// When both the address and the user are limited, the headers describe whichever is closer to running out
+fn with_rate_limit_headers(mut response: Response, decision: &Decision) -> Response {
+    let headers = response.headers_mut();
+    let tighter = headers.get("X-RateLimit-Remaining")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u32>().ok())
+        .is_some_and(|remaining| remaining <= decision.remaining);
+    if !tighter {
+        headers.insert("X-RateLimit-Limit", HeaderValue::from(decision.limit));
+        headers.insert("X-RateLimit-Remaining", HeaderValue::from(decision.remaining));
+        headers.insert("X-RateLimit-Reset", HeaderValue::from(decision.reset.as_secs_f64().ceil() as u64));
+    }
+    response
+}
@@ -1,45 +1,275 @@
-use axum::http::StatusCode;
+use std::fmt::{Display, Formatter};
+
+use axum::body::Body;
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::Json;
-use axum_extra::either::Either;
+use http_body_util::BodyExt;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use tokio::task::JoinError;
+use crate::schema::emojis::CustomEmojiSummary;
 use crate::schema::reactions::ReactionSummary;
+use crate::server::rest::validation::{validated, Validate};
 pub use crate::schema::users::User;
+use crate::util::validation::ValidationError;
 
 pub mod auth;
 pub mod contacts;
 pub mod user;
 pub mod middlewares;
 pub mod messages;
+pub mod emojis;
+pub mod verification;
+pub mod password;
+pub mod two_factor;
+pub mod passkeys;
+pub mod sessions;
+pub mod blocks;
+pub mod friends;
+pub mod settings;
+pub mod folders;
+pub mod extract;
+pub mod validation;
 pub(crate) mod reactions;
 pub(crate) mod search;
 
-pub type IrisResponse<T> = (StatusCode, Either<Json<T>, Json<IrisError>>);
+pub type IrisResponse<T> = Result<(StatusCode, Json<T>), IrisError>;
 
-#[derive(Serialize)]
+// Every error carries a stable machine-readable code, the message is only meant for people.
+// Handlers list the codes they answer with in an `Errors:` comment, `internal_error` included when they
+// return it themselves. Any endpoint may still answer with `database_error`, or `internal_error` from a
+// failed blocking task, and authenticated ones with `unauthorized`, `invalid_token`, `token_expired`,
+// `two_factor_enrollment_required` or `rate_limited`.
+#[derive(Debug)]
 pub struct IrisError {
-    pub status: u16,
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+    // One entry per rejected field of the request
+    pub errors: Vec<FieldError>
+}
+
+#[derive(Serialize)]
+struct IrisErrorBody<'a> {
+    status: u16,
+    code: &'static str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    errors: &'a [FieldError]
+}
+
+impl IrisError {
+    pub fn new(status: StatusCode, code: &'static str, message: &str) -> IrisError {
+        IrisError {
+            status,
+            code,
+            message: String::from(message),
+            errors: Vec::new()
+        }
+    }
+
+    // For errors where the status says it all
+    pub fn from_status(status: StatusCode, message: &str) -> IrisError {
+        IrisError::new(status, default_code(status), message)
+    }
+
+    pub fn internal(message: &str) -> IrisError {
+        IrisError::from_status(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+
+    pub fn invalid_fields(errors: Vec<FieldError>) -> IrisError {
+        IrisError {
+            errors,
+            ..IrisError::from_status(StatusCode::UNPROCESSABLE_ENTITY, "Some fields are invalid")
+        }
+    }
+}
+
+pub fn default_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::GONE => "gone",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::UNPROCESSABLE_ENTITY => "invalid_fields",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        _ if status.is_client_error() => "bad_request",
+        _ => "internal_error"
+    }
+}
+
+impl IntoResponse for IrisError {
+    fn into_response(self) -> Response {
+        let body = IrisErrorBody {
+            status: self.status.as_u16(),
+            code: self.code,
+            message: &self.message,
+            errors: &self.errors
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl Display for IrisError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}: {}", self.status.as_u16(), self.code, self.message)
+    }
+}
+
+// Missing rows are the caller's problem, anything else the database does wrong is ours
+impl From<diesel::result::Error> for IrisError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            diesel::result::Error::NotFound => IrisError::from_status(StatusCode::NOT_FOUND, "Not found"),
+            err => {
+                tracing::error!("Database error: {:?}", err);
+                IrisError::new(StatusCode::INTERNAL_SERVER_ERROR, "database_error", "Something went wrong")
+            }
+        }
+    }
+}
+
+// Request bodies go through `JsonRejection`, so these are payloads the server built or stored itself
+impl From<serde_json::Error> for IrisError {
+    fn from(err: serde_json::Error) -> Self {
+        tracing::error!("Serialization error: {:?}", err);
+        IrisError::internal("Something went wrong")
+    }
+}
+
+impl From<JsonRejection> for IrisError {
+    fn from(rejection: JsonRejection) -> Self {
+        IrisError::new(StatusCode::BAD_REQUEST, "invalid_body", &rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for IrisError {
+    fn from(rejection: PathRejection) -> Self {
+        IrisError::new(StatusCode::BAD_REQUEST, "invalid_path", &rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for IrisError {
+    fn from(rejection: QueryRejection) -> Self {
+        IrisError::new(StatusCode::BAD_REQUEST, "invalid_query", &rejection.body_text())
+    }
+}
+
+impl From<jwt::Error> for IrisError {
+    fn from(_: jwt::Error) -> Self {
+        IrisError::new(StatusCode::UNAUTHORIZED, "invalid_token", "Invalid token")
+    }
+}
+
+impl From<JoinError> for IrisError {
+    fn from(err: JoinError) -> Self {
+        tracing::error!("Blocking task failed: {:?}", err);
+        IrisError::internal("Something went wrong")
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
     pub message: String
 }
 
+impl FieldError {
+    pub fn new(field: &'static str, code: &'static str, message: &str) -> FieldError {
+        FieldError {
+            field,
+            code,
+            message: String::from(message)
+        }
+    }
+
+    pub fn from_validation(field: &'static str, error: ValidationError) -> FieldError {
+        FieldError {
+            field,
+            code: error.code(),
+            message: error.to_string()
+        }
+    }
+}
+
 pub fn ok<T: Serialize>(data: T) -> IrisResponse<T> {
-    (StatusCode::OK, Either::E1(Json(data)))
+    Ok((StatusCode::OK, Json(data)))
 }
 
 pub fn no_content() -> IrisResponse<()> {
-    (StatusCode::NO_CONTENT, Either::E1(Json(())))
+    Ok((StatusCode::NO_CONTENT, Json(())))
 }
 
 pub fn error<T: Serialize>(status: StatusCode, message: &str) -> IrisResponse<T> {
-    (status, Either::E2(Json(IrisError {
-        status: status.as_u16(),
-        message: String::from(message)
-    })))
+    Err(IrisError::from_status(status, message))
+}
+
+pub fn error_with_code<T: Serialize>(status: StatusCode, code: &'static str, message: &str) -> IrisResponse<T> {
+    Err(IrisError::new(status, code, message))
+}
+
+pub fn field_errors<T: Serialize>(status: StatusCode, code: &'static str, message: &str, errors: Vec<FieldError>) -> IrisResponse<T> {
+    Err(IrisError {
+        status,
+        code,
+        message: String::from(message),
+        errors
+    })
+}
+
+// The user the `authorize` middleware resolved for this request
+pub fn current_user(request: &Request<Body>) -> Result<User, IrisError> {
+    request.extensions().get::<User>().cloned()
+        .ok_or_else(|| IrisError::from_status(StatusCode::UNAUTHORIZED, "Not authenticated"))
+}
+
+pub async fn read_json<T: DeserializeOwned>(request: Request<Body>) -> Result<T, IrisError> {
+    let body = request.into_body().collect().await
+        .map_err(|_| IrisError::new(StatusCode::BAD_REQUEST, "invalid_body", "Failed to read the request body"))?;
+    Ok(Json::<T>::from_bytes(body.to_bytes().as_ref())?.0)
+}
+
+pub async fn read_valid_json<T: DeserializeOwned + Validate>(request: Request<Body>) -> Result<T, IrisError> {
+    validated(read_json(request).await?)
 }
 
 #[derive(Serialize)]
 pub struct UserAuthResponse {
     pub user: UserSelfResponse,
-    pub token: String
+    #[serde(flatten)]
+    pub tokens: TokenResponse
+}
+
+// Users with 2FA get a challenge from the password step instead of tokens
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(Box<UserAuthResponse>),
+    TwoFactorRequired(TwoFactorChallengeResponse)
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorChallengeResponse {
+    pub two_factor_required: bool,
+    // Exchanged together with a code at `/login/2fa`
+    pub challenge: String,
+    pub expires_in: i64
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    pub refresh_token: String,
+    // Seconds until `token` expires
+    pub expires_in: i64
 }
 
 #[derive(Serialize)]
@@ -47,20 +277,76 @@ pub struct UserSelfResponse {
     pub id: i64,
     pub name: String,
     pub username: String,
-    pub email: String
+    pub email: String,
+    pub email_verified: bool,
+    pub two_factor_enabled: bool,
+    pub bio: Option<String>,
+    pub pronouns: Option<String>,
+    pub status: Option<CustomStatus>,
+    pub avatar_url: Option<String>
 }
 
 impl From<User> for UserSelfResponse {
     fn from(user: User) -> Self {
         UserSelfResponse {
             id: user.user_id,
+            avatar_url: user.avatar_url(),
+            status: CustomStatus::of(&user),
             name: user.name,
             username: user.username,
-            email: user.email
+            email: user.email,
+            email_verified: user.email_verified,
+            two_factor_enabled: user.two_factor_enabled,
+            bio: user.bio,
+            pronouns: user.pronouns
         }
     }
 }
 
+// What anyone can see of a user
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UserProfileResponse {
+    pub id: i64,
+    pub name: String,
+    pub username: String,
+    pub bio: Option<String>,
+    pub pronouns: Option<String>,
+    pub status: Option<CustomStatus>,
+    pub avatar_url: Option<String>
+}
+
+impl From<User> for UserProfileResponse {
+    fn from(user: User) -> Self {
+        UserProfileResponse {
+            id: user.user_id,
+            avatar_url: user.avatar_url(),
+            status: CustomStatus::of(&user),
+            name: user.name,
+            username: user.username,
+            bio: user.bio,
+            pronouns: user.pronouns
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CustomStatus {
+    pub text: Option<String>,
+    pub emoji: Option<String>
+}
+
+impl CustomStatus {
+    pub fn of(user: &User) -> Option<CustomStatus> {
+        if user.status_text.is_none() && user.status_emoji.is_none() {
+            return None;
+        }
+        Some(CustomStatus {
+            text: user.status_text.clone(),
+            emoji: user.status_emoji.clone()
+        })
+    }
+}
+
 #[derive(Serialize)]
 pub struct PrimordialMessage {
     pub id: i64,
@@ -75,16 +361,39 @@ pub struct StandardUser {
     pub username: String
 }
 
+impl From<User> for StandardUser {
+    fn from(user: User) -> Self {
+        StandardUser {
+            id: user.user_id,
+            name: user.name,
+            username: user.username
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct ContactResponse {
     pub user_id: i64,
-    pub channel_id: i64,
+    // Friends don't have a channel until one of them opens the DM
+    pub channel_id: Option<i64>,
     pub name: String,
     pub username: String,
+    // Only visible to the user who set it
+    pub nickname: Option<String>,
+    pub folders: Vec<i64>,
+    pub pinned: bool,
+    pub archived: bool,
     pub last_message: Option<PrimordialMessage>,
     pub unread_count: i64
 }
 
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct FolderResponse {
+    pub id: i64,
+    pub name: String,
+    pub channel_ids: Vec<i64>
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct MessageObject {
     pub id: i64,
@@ -95,7 +404,17 @@ pub struct MessageObject {
     pub edited: bool,
     pub author: StandardUser,
     pub reply_to: Option<i64>,
-    pub reactions: Vec<ReactionSummary>
+    // Enough of the replied message to render it above the reply, gone once it's deleted
+    pub reply: Option<ReplyPreview>,
+    pub reactions: Vec<ReactionSummary>,
+    pub emojis: Vec<CustomEmojiSummary>
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReplyPreview {
+    pub id: i64,
+    pub user_id: i64,
+    pub content: String
 }
 
 #[derive(Deserialize)]
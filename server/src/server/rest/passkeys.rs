@@ -0,0 +1,488 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Extension;
+use chrono::{Duration, Utc};
+use diesel::{ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
+use serde::{Deserialize, Serialize};
+
+use crate::schema::credentials::{Credential, WebauthnChallenge};
+use crate::schema::credentials::credentials::dsl::credentials;
+use crate::schema::credentials::credentials::{credential_id, external_id, last_used_at, sign_count, user_id as credential_user_id};
+use crate::schema::credentials::webauthn_challenges::dsl::webauthn_challenges;
+use crate::schema::credentials::webauthn_challenges::{ceremony, challenge_id, expires_at};
+use crate::schema::users::{lower, User};
+use crate::schema::users::users::dsl::users;
+use crate::schema::users::users::{user_id as table_user_id, username};
+use crate::server::rest::auth::start_session;
+use crate::server::rest::sessions::DeviceInfo;
+use crate::server::rest::extract::{JsonBody, Path};
+use crate::server::rest::validation::{check, Validate};
+use crate::server::rest::{current_user, error, error_with_code, FieldError, IrisResponse, no_content, ok, read_valid_json, UserAuthResponse, UserSelfResponse};
+use crate::util::identity::MAX_PASSKEY_NAME_LENGTH;
+use crate::util::validation::validate_length;
+use crate::util::webauthn::{decode, encode, generate_challenge, verify_assertion, verify_registration, RelyingParty, SUPPORTED_ALGORITHMS};
+use crate::{AppState, SharedState};
+
+const REGISTRATION: &str = "registration";
+const AUTHENTICATION: &str = "authentication";
+
+// Errors: internal_error
+pub async fn start_registration(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<CeremonyResponse<CreationOptions>> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let existing = credentials
+        .filter(credential_user_id.eq(user.user_id))
+        .select(external_id)
+        .load::<String>(&mut state.database);
+    let Ok(existing) = existing else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start passkey registration");
+    };
+    let Ok((id, challenge)) = create_challenge(state, Some(user.user_id), REGISTRATION) else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start passkey registration");
+    };
+
+    let passkeys = &state.config.passkeys;
+    ok(CeremonyResponse {
+        challenge_id: id,
+        options: CreationOptions {
+            rp: RelyingPartyEntity {
+                id: passkeys.rp_id.clone(),
+                name: passkeys.rp_name.clone()
+            },
+            user: UserEntity {
+                id: encode(&user.user_id.to_be_bytes()),
+                name: user.username.clone(),
+                display_name: user.name.clone()
+            },
+            challenge,
+            pub_key_cred_params: SUPPORTED_ALGORITHMS.iter()
+                .map(|alg| CredentialParameters { kind: "public-key", alg: *alg })
+                .collect(),
+            timeout: passkeys.challenge_ttl * 1000,
+            // Stops the same authenticator from being registered twice
+            exclude_credentials: existing.into_iter().map(CredentialDescriptor::new).collect(),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "required",
+                require_resident_key: true,
+                user_verification: "required"
+            },
+            attestation: "none"
+        }
+    })
+}
+
+// Errors: invalid_body, invalid_fields, invalid_passkey_challenge, invalid_passkey_response, passkey_already_registered, internal_error
+pub async fn finish_registration(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<PasskeyResponse> {
+    let user = current_user(&request)?;
+    let request = read_valid_json::<FinishRegistrationRequest>(request).await?;
+
+    let state = &mut state.write().await;
+    let Some(challenge) = take_challenge(state, request.challenge_id, REGISTRATION) else {
+        return error_with_code(StatusCode::BAD_REQUEST, "invalid_passkey_challenge", "Invalid or expired passkey challenge");
+    };
+    if challenge.user_id != Some(user.user_id) {
+        return error_with_code(StatusCode::BAD_REQUEST, "invalid_passkey_challenge", "Invalid or expired passkey challenge");
+    }
+
+    let response = &request.credential.response;
+    let (Some(client_data), Some(attestation)) = (decode(&response.client_data_json), decode(&response.attestation_object)) else {
+        return error_with_code(StatusCode::BAD_REQUEST, "invalid_passkey_response", "Malformed passkey response");
+    };
+    let origins = state.config.passkey_origins();
+    let rp = RelyingParty {
+        rp_id: &state.config.passkeys.rp_id,
+        origins: &origins,
+        challenge: &challenge.challenge
+    };
+    let registered = match verify_registration(&rp, &client_data, &attestation) {
+        Ok(registered) => registered,
+        Err(err) => return error_with_code(StatusCode::BAD_REQUEST, "invalid_passkey_response", &err.to_string())
+    };
+
+    let credential = Credential {
+        credential_id: state.snowflake_issuer.generate().value() as i64,
+        user_id: user.user_id,
+        external_id: encode(&registered.credential_id),
+        public_key: registered.public_key,
+        sign_count: registered.sign_count as i64,
+        name: request.name.map(|name| name.trim().to_string()).filter(|name| !name.is_empty())
+            .unwrap_or_else(|| String::from("Passkey")),
+        created_at: Utc::now().naive_utc(),
+        last_used_at: None
+    };
+    let inserted = diesel::insert_into(credentials)
+        .values(&credential)
+        .on_conflict_do_nothing()
+        .execute(&mut state.database);
+
+    match inserted {
+        Ok(1) => ok(PasskeyResponse::from(credential)),
+        Ok(_) => error_with_code(StatusCode::CONFLICT, "passkey_already_registered", "This passkey is already registered"),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save passkey")
+    }
+}
+
+// Errors: internal_error
+pub async fn get_passkeys(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<Vec<PasskeyResponse>> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let passkeys = credentials
+        .filter(credential_user_id.eq(user.user_id))
+        .order(credential_id.asc())
+        .select(Credential::as_select())
+        .load::<Credential>(&mut state.database);
+    match passkeys {
+        Ok(passkeys) => ok(passkeys.into_iter().map(PasskeyResponse::from).collect()),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get passkeys")
+    }
+}
+
+// Errors: invalid_path, not_found, internal_error
+pub async fn delete_passkey(
+    Extension(state): Extension<SharedState>,
+    Path(passkey_id): Path<i64>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let deleted = diesel::delete(
+        credentials
+            .filter(credential_id.eq(passkey_id))
+            .filter(credential_user_id.eq(user.user_id))
+    )
+        .execute(&mut state.database);
+    match deleted {
+        Ok(0) => error(StatusCode::NOT_FOUND, "Passkey not found"),
+        Ok(_) => no_content(),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete passkey")
+    }
+}
+
+// Without a username the browser offers every passkey it holds for the site
+// Errors: invalid_body, internal_error
+pub async fn start_login(
+    Extension(state): Extension<SharedState>,
+    JsonBody(request): JsonBody<StartLoginRequest>,
+) -> IrisResponse<CeremonyResponse<RequestOptions>> {
+    let state = &mut state.write().await;
+
+    let mut user = None;
+    let mut allowed = Vec::new();
+    if let Some(name) = &request.username {
+        let found = users
+            .filter(lower(username).eq(lower(name.trim())))
+            .select(table_user_id)
+            .first::<i64>(&mut state.database);
+        if let Ok(found) = found {
+            user = Some(found);
+            allowed = credentials
+                .filter(credential_user_id.eq(found))
+                .select(external_id)
+                .load::<String>(&mut state.database)
+                .unwrap_or_default();
+        }
+    }
+    let Ok((id, challenge)) = create_challenge(state, user, AUTHENTICATION) else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start passkey login");
+    };
+
+    ok(CeremonyResponse {
+        challenge_id: id,
+        options: RequestOptions {
+            challenge,
+            rp_id: state.config.passkeys.rp_id.clone(),
+            timeout: state.config.passkeys.challenge_ttl * 1000,
+            allow_credentials: allowed.into_iter().map(CredentialDescriptor::new).collect(),
+            user_verification: "required"
+        }
+    })
+}
+
+// A verified passkey already covers both factors, so no TOTP challenge follows
+// Errors: invalid_body, invalid_passkey_challenge, unknown_passkey, invalid_passkey_response, internal_error
+pub async fn finish_login(
+    Extension(state): Extension<SharedState>,
+    device: DeviceInfo,
+    JsonBody(request): JsonBody<FinishLoginRequest>,
+) -> IrisResponse<UserAuthResponse> {
+    let state = &mut state.write().await;
+    let Some(challenge) = take_challenge(state, request.challenge_id, AUTHENTICATION) else {
+        return error_with_code(StatusCode::UNAUTHORIZED, "invalid_passkey_challenge", "Invalid or expired passkey challenge");
+    };
+
+    let credential = credentials
+        .filter(external_id.eq(request.credential.id.trim_end_matches('=')))
+        .select(Credential::as_select())
+        .first::<Credential>(&mut state.database);
+    let Ok(credential) = credential else {
+        return error_with_code(StatusCode::UNAUTHORIZED, "unknown_passkey", "Unknown passkey");
+    };
+    if challenge.user_id.is_some_and(|expected| expected != credential.user_id) {
+        return error_with_code(StatusCode::UNAUTHORIZED, "unknown_passkey", "Unknown passkey");
+    }
+
+    let response = &request.credential.response;
+    if let Some(handle) = &response.user_handle {
+        if decode(handle).as_deref() != Some(credential.user_id.to_be_bytes().as_slice()) {
+            return error_with_code(StatusCode::UNAUTHORIZED, "invalid_passkey_response", "Passkey belongs to another user");
+        }
+    }
+    let (Some(client_data), Some(authenticator_data), Some(signature)) = (
+        decode(&response.client_data_json),
+        decode(&response.authenticator_data),
+        decode(&response.signature)
+    ) else {
+        return error_with_code(StatusCode::UNAUTHORIZED, "invalid_passkey_response", "Malformed passkey response");
+    };
+
+    let origins = state.config.passkey_origins();
+    let rp = RelyingParty {
+        rp_id: &state.config.passkeys.rp_id,
+        origins: &origins,
+        challenge: &challenge.challenge
+    };
+    let counter = match verify_assertion(&rp, &client_data, &authenticator_data, &signature, &credential.public_key) {
+        Ok(counter) => counter as i64,
+        Err(err) => return error_with_code(StatusCode::UNAUTHORIZED, "invalid_passkey_response", &err.to_string())
+    };
+    if counter_regressed(credential.sign_count, counter) {
+        tracing::warn!("Passkey {} of {} reported a stale signature counter", credential.credential_id, credential.user_id);
+        return error_with_code(StatusCode::UNAUTHORIZED, "invalid_passkey_response", "Passkey signature counter went backwards");
+    }
+
+    let _ = diesel::update(credentials.filter(credential_id.eq(credential.credential_id)))
+        .set((sign_count.eq(counter), last_used_at.eq(Some(Utc::now().naive_utc()))))
+        .execute(&mut state.database);
+    let user = users
+        .filter(table_user_id.eq(credential.user_id))
+        .select(User::as_select())
+        .first::<User>(&mut state.database);
+    let Ok(user) = user else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session");
+    };
+
+    match start_session(state, user.user_id, &device) {
+        Ok(tokens) => ok(UserAuthResponse {
+            user: UserSelfResponse::from(user),
+            tokens
+        }),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session")
+    }
+}
+
+// Synced passkeys always report 0, a counter that stops increasing otherwise points to a cloned authenticator
+fn counter_regressed(stored: i64, reported: i64) -> bool {
+    (reported != 0 || stored != 0) && reported <= stored
+}
+
+fn create_challenge(state: &mut AppState, user: Option<i64>, kind: &str) -> QueryResult<(i64, String)> {
+    let id = { state.snowflake_issuer.generate().value() as i64 };
+    let now = Utc::now().naive_utc();
+    let challenge = WebauthnChallenge {
+        challenge_id: id,
+        user_id: user,
+        ceremony: kind.to_string(),
+        challenge: generate_challenge(),
+        expires_at: now + Duration::seconds(state.config.passkeys.challenge_ttl)
+    };
+
+    // Abandoned ceremonies are cleaned up whenever a new one starts
+    diesel::delete(webauthn_challenges.filter(expires_at.le(now))).execute(&mut state.database)?;
+    diesel::insert_into(webauthn_challenges)
+        .values(&challenge)
+        .execute(&mut state.database)?;
+    Ok((id, challenge.challenge))
+}
+
+// Challenges are single-use, deleting one hands it to exactly one request
+fn take_challenge(state: &mut AppState, id: i64, kind: &str) -> Option<WebauthnChallenge> {
+    diesel::delete(
+        webauthn_challenges
+            .filter(challenge_id.eq(id))
+            .filter(ceremony.eq(kind))
+    )
+        .returning(WebauthnChallenge::as_returning())
+        .get_result::<WebauthnChallenge>(&mut state.database)
+        .ok()
+        .filter(|challenge| challenge.expires_at > Utc::now().naive_utc())
+}
+
+#[derive(Serialize)]
+pub struct CeremonyResponse<T> {
+    pub challenge_id: i64,
+    // Passed to `navigator.credentials` as `publicKey`
+    pub options: T
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub rp: RelyingPartyEntity,
+    pub user: UserEntity,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: i64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: &'static str
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: i64,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: &'static str
+}
+
+#[derive(Serialize)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String
+}
+
+#[derive(Serialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub alg: i64
+}
+
+#[derive(Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String
+}
+
+impl CredentialDescriptor {
+    fn new(id: String) -> Self {
+        CredentialDescriptor { kind: "public-key", id }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: &'static str,
+    pub require_resident_key: bool,
+    pub user_verification: &'static str
+}
+
+#[derive(Serialize)]
+pub struct PasskeyResponse {
+    pub id: i64,
+    pub name: String,
+    // Unix timestamps in seconds
+    pub created_at: i64,
+    pub last_used_at: Option<i64>
+}
+
+impl From<Credential> for PasskeyResponse {
+    fn from(credential: Credential) -> Self {
+        PasskeyResponse {
+            id: credential.credential_id,
+            name: credential.name,
+            created_at: credential.created_at.and_utc().timestamp(),
+            last_used_at: credential.last_used_at.map(|used| used.and_utc().timestamp())
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FinishRegistrationRequest {
+    pub challenge_id: i64,
+    pub name: Option<String>,
+    pub credential: RegistrationCredential
+}
+
+impl Validate for FinishRegistrationRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        self.name.as_deref()
+            .and_then(|name| check("name", validate_length(name.trim(), MAX_PASSKEY_NAME_LENGTH)))
+            .into_iter()
+            .collect()
+    }
+}
+
+// The JSON form of a `PublicKeyCredential`, binary fields are base64url
+#[derive(Deserialize, Debug)]
+pub struct RegistrationCredential {
+    pub response: AttestationResponse
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StartLoginRequest {
+    pub username: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FinishLoginRequest {
+    pub challenge_id: i64,
+    pub credential: AssertionCredential
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssertionCredential {
+    pub id: String,
+    pub response: AssertionResponse
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>
+}
+
+#[cfg(test)]
+mod tests {
+    use super::counter_regressed;
+
+    #[test]
+    fn counter_has_to_increase() {
+        assert!(!counter_regressed(0, 1));
+        assert!(!counter_regressed(41, 42));
+        assert!(counter_regressed(42, 42));
+        assert!(counter_regressed(42, 7));
+        assert!(counter_regressed(42, 0));
+    }
+
+    #[test]
+    fn counter_can_stay_at_zero() {
+        assert!(!counter_regressed(0, 0));
+    }
+}
@@ -0,0 +1,178 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Extension;
+use chrono::{Duration, Utc};
+use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
+use serde::Deserialize;
+
+use crate::mail::{dispatch, Mail};
+use crate::schema::resets::PasswordReset;
+use crate::schema::resets::password_resets::dsl::password_resets;
+use crate::schema::resets::password_resets::{reset_id, user_id as reset_user_id};
+use crate::schema::users::{lower, User};
+use crate::schema::users::users::dsl::users;
+use crate::schema::users::users::{email as table_email, password as table_password, user_id as table_user_id};
+use crate::server::rest::auth::{end_all_sessions, start_session};
+use crate::server::rest::sessions::DeviceInfo;
+use crate::server::rest::extract::{JsonBody, ValidJson};
+use crate::server::rest::validation::{check, Validate};
+use crate::server::rest::{current_user, error, error_with_code, FieldError, IrisResponse, no_content, ok, read_valid_json, TokenResponse};
+use crate::util::identity::validate_password;
+use crate::util::passwords::{hash_password, verify_password, PasswordCheck};
+use crate::util::tokens::{constant_time_eq, format_token, generate_secret, hash_secret, parse_token};
+use crate::{AppState, SharedState};
+
+// Always answers the same way so the endpoint can't be used to find out which emails are registered
+// Errors: invalid_body
+pub async fn forgot_password(
+    Extension(state): Extension<SharedState>,
+    JsonBody(request): JsonBody<ForgotPasswordRequest>,
+) -> IrisResponse<()> {
+    let state = &mut state.write().await;
+    let user = users
+        .filter(lower(table_email).eq(lower(request.email.trim())))
+        .select(User::as_select())
+        .first::<User>(&mut state.database);
+
+    if let Ok(user) = user {
+        if let Err(err) = send_reset(state, &user) {
+            tracing::error!("Failed to create a password reset for {}: {}", user.user_id, err);
+        }
+    }
+    no_content()
+}
+
+// Errors: invalid_body, invalid_fields, invalid_reset_token, reset_token_expired, internal_error
+pub async fn reset_password(
+    Extension(state): Extension<SharedState>,
+    ValidJson(request): ValidJson<ResetPasswordRequest>,
+) -> IrisResponse<()> {
+    let state = &mut state.write().await;
+    let Some((id, secret)) = parse_token(&request.token) else {
+        return error_with_code(StatusCode::BAD_REQUEST, "invalid_reset_token", "Invalid password reset token");
+    };
+
+    let reset = password_resets
+        .filter(reset_id.eq(id))
+        .select(PasswordReset::as_select())
+        .first::<PasswordReset>(&mut state.database);
+    let reset = match reset {
+        Ok(reset) if constant_time_eq(&reset.token_hash, &hash_secret(secret)) => reset,
+        _ => return error_with_code(StatusCode::BAD_REQUEST, "invalid_reset_token", "Invalid password reset token")
+    };
+    if reset.expires_at <= Utc::now().naive_utc() {
+        let _ = diesel::delete(password_resets.filter(reset_id.eq(id))).execute(&mut state.database);
+        return error_with_code(StatusCode::BAD_REQUEST, "reset_token_expired", "Password reset token has expired");
+    }
+
+    let Ok(hashed_password) = hash_password(&state.argon, &request.password) else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to reset password");
+    };
+    // Consuming every reset token for the user makes each of them single-use
+    let updated = state.database.transaction::<_, diesel::result::Error, _>(|connection| {
+        diesel::delete(password_resets.filter(reset_user_id.eq(reset.user_id)))
+            .execute(connection)?;
+        diesel::update(users.filter(table_user_id.eq(reset.user_id)))
+            .set(table_password.eq(hashed_password))
+            .execute(connection)?;
+        Ok(())
+    });
+    if updated.is_err() {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to reset password");
+    }
+
+    match end_all_sessions(state, reset.user_id) {
+        Ok(_) => no_content(),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to end sessions")
+    }
+}
+
+// Every other session is ended, the caller gets a fresh one so they stay logged in
+// Errors: invalid_body, invalid_fields, invalid_password, internal_error
+pub async fn change_password(
+    Extension(state): Extension<SharedState>,
+    device: DeviceInfo,
+    request: Request<Body>
+) -> IrisResponse<TokenResponse> {
+    let user = current_user(&request)?;
+    let request = read_valid_json::<ChangePasswordRequest>(request).await?;
+
+    let state = &mut state.write().await;
+    if verify_password(&state.argon, None, &user.password, &request.current_password) == PasswordCheck::Invalid {
+        return error_with_code(StatusCode::FORBIDDEN, "invalid_password", "Current password is incorrect");
+    }
+
+    let Ok(hashed_password) = hash_password(&state.argon, &request.new_password) else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to change password");
+    };
+    let updated = diesel::update(users.filter(table_user_id.eq(user.user_id)))
+        .set(table_password.eq(hashed_password))
+        .execute(&mut state.database);
+    if updated.is_err() {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to change password");
+    }
+
+    let _ = diesel::delete(password_resets.filter(reset_user_id.eq(user.user_id))).execute(&mut state.database);
+    if end_all_sessions(state, user.user_id).is_err() {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to end sessions");
+    }
+    match start_session(state, user.user_id, &device) {
+        Ok(tokens) => ok(tokens),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session")
+    }
+}
+
+fn send_reset(state: &mut AppState, user: &User) -> diesel::QueryResult<()> {
+    let id = { state.snowflake_issuer.generate().value() as i64 };
+    let secret = generate_secret();
+    let reset = PasswordReset {
+        reset_id: id,
+        user_id: user.user_id,
+        token_hash: hash_secret(&secret),
+        expires_at: Utc::now().naive_utc() + Duration::seconds(state.config.auth.password_reset_ttl)
+    };
+    diesel::insert_into(password_resets)
+        .values(&reset)
+        .execute(&mut state.database)?;
+
+    let token = format_token(id, &secret);
+    dispatch(state.mailer.clone(), Mail {
+        to: user.email.clone(),
+        subject: String::from("Reset your Iris password"),
+        body: format!(
+            "Hi {},\n\nSomeone asked to reset your password. If it was you, open the link below:\n\n{}/reset-password?token={}\n\nOr submit this token: {}\n\nThe link expires in {} minutes. If you didn't ask for this, you can ignore this email.\n",
+            user.name, state.config.server.frontend_url, token, token, state.config.auth.password_reset_ttl / 60
+        )
+    });
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForgotPasswordRequest {
+    pub email: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String
+}
+
+impl Validate for ResetPasswordRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        check("password", validate_password(&self.password)).into_iter().collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String
+}
+
+// The current password is only checked against the stored hash
+impl Validate for ChangePasswordRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        check("new_password", validate_password(&self.new_password)).into_iter().collect()
+    }
+}
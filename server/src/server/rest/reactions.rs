@@ -1,109 +1,95 @@
-use axum::{Extension, Json};
+use axum::Extension;
 use axum::body::Body;
-use axum::extract::Path;
 use axum::http::{Request, StatusCode};
-use diesel::{Connection, OptionalExtension, RunQueryDsl};
+use diesel::{Connection, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
+use diesel::dsl::exists;
+use diesel::upsert::excluded;
 
+use crate::schema::messages::messages::dsl::messages as messagesTable;
+use crate::schema::messages::messages::{channel_id as messageChannelId, message_id as messageId};
+use crate::schema::emojis::CustomEmoji;
+use crate::schema::emojis::custom_emoji::dsl::custom_emoji as customEmojiTable;
+use crate::schema::emojis::custom_emoji::emoji_id as custom_emoji_id;
 use crate::schema::reactions::{ReactionInsert, ReactionUserInsert};
 use crate::schema::reactions::reactions::dsl::reactions as reactionsTable;
 use crate::schema::reactions::reaction_users::dsl::reaction_users as reactionUsersTable;
 use crate::schema::reactions::reaction_users::reaction_id as reactionUsersTableReactionId;
 use crate::schema::reactions::reactions::{emoji, message_id, reaction_count};
 use crate::schema::reactions::reactions::reaction_id;
-use crate::schema::users::User;
-use crate::server::rest::{error, IrisResponse, no_content, ok, ReactionAddRequest, ReactionAddResponse};
+use crate::server::rest::extract::Path;
+use crate::server::rest::validation::Validate;
+use crate::server::rest::{current_user, error, FieldError, IrisError, IrisResponse, no_content, ok, ReactionAddRequest, ReactionAddResponse, read_valid_json};
 use crate::SharedState;
-use http_body_util::BodyExt;
 use diesel::ExpressionMethods;
 use crate::schema::reactions::reaction_users::user_id;
 use crate::server::gateway::context::{send_packet_to_channel};
 use crate::server::gateway::messages::{ReactionAdded, ReactionRemoved};
+use crate::util::emoji::{EmojiReference, format_custom_emoji};
 
+// Errors: invalid_path, invalid_body, invalid_fields, not_found, forbidden, internal_error
 pub async fn add_reaction(
     Path((channel_id, message_identifier)): Path<(i64, i64)>,
     Extension(state): Extension<SharedState>,
     request: Request<Body>
 ) -> IrisResponse<ReactionAddResponse> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
-    let request = Json::from_bytes(request.into_body().collect().await.unwrap().to_bytes().as_ref());
-    if request.is_err() {
-        return error(StatusCode::BAD_REQUEST, "Invalid reaction");
-    }
-    let request: ReactionAddRequest = request.unwrap().0;
-    let emoticon = request.reaction_type.clone();
+    let user = current_user(&request)?;
+    let request = read_valid_json::<ReactionAddRequest>(request).await?;
 
     let mut state = state.write().await;
-    let transaction_result = {
-        state.database.transaction::<_, diesel::result::Error, _>(|connection| {
-            let reaction_details: Option<(i32, i32)> = match request.reaction_id {
-                Some(message_reaction_id) => {
-                    let count = diesel::update(reactionsTable)
-                        .filter(reaction_id.eq(message_reaction_id))
-                        .set(reaction_count.eq(reaction_count + 1))
-                        .returning(reaction_count)
-                        .get_result::<i32>(connection)?;
-                    Some((message_reaction_id, count))
-                }
-                None => {
-                    let reaction = diesel::update(reactionsTable)
-                        .filter(message_id.eq(message_identifier))
-                        .filter(emoji.eq(request.reaction_type.clone()))
-                        .set(reaction_count.eq(reaction_count + 1))
-                        .returning((reaction_id, reaction_count))
-                        .get_result::<(i32, i32)>(connection)
-                        .optional()?;
-
-                    if let Some(tuple) = reaction {
-                        println!("Already found a reaction, no need for new one: {:?}", tuple);
-                        Some(tuple)
-                    } else {
-                        println!("Inserted");
-                        let new_reaction = ReactionInsert {
-                            message_id: message_identifier,
-                            emoji: request.reaction_type,
-                        };
-                        let query = diesel::insert_into(reactionsTable)
-                            .values(&new_reaction)
-                            .returning(reaction_id)
-                            .get_result::<i32>(connection)?;
-
-                        Some((query, 1))
-                    }
+    if !message_in_channel(&mut state.database, channel_id, message_identifier) {
+        return error(StatusCode::NOT_FOUND, "Message not found");
+    }
+
+    // Reacting through an existing reaction id reuses its emoji, so only new emojis are resolved here
+    let new_reaction = match request.reaction_id {
+        Some(_) => None,
+        None => match EmojiReference::parse(&request.reaction_type) {
+            Some(EmojiReference::Unicode(unicode)) => Some((unicode, None)),
+            Some(EmojiReference::Custom { name, id }) => {
+                let custom = customEmojiTable
+                    .filter(custom_emoji_id.eq(id))
+                    .select(CustomEmoji::as_select())
+                    .first::<CustomEmoji>(&mut state.database);
+                let custom = match custom {
+                    Ok(custom) if custom.name == name => custom,
+                    _ => return error(StatusCode::NOT_FOUND, "Emoji not found")
+                };
+                let text = format_custom_emoji(&custom.name, custom.emoji_id);
+                if !custom.usable_by(user.user_id, channel_id) && !reaction_exists(&mut state.database, message_identifier, &text) {
+                    return error(StatusCode::FORBIDDEN, "You cannot use this emoji here");
                 }
-            };
-            if reaction_details.is_none() {
-                return Err(diesel::result::Error::NotFound);
+                Some((text, Some(custom.emoji_id)))
             }
+            None => return Err(IrisError::invalid_fields(vec![invalid_emoji()]))
+        }
+    };
 
-            let (message_reaction_id, count) = reaction_details.unwrap();
-            let reaction_user = ReactionUserInsert {
-                reaction_id: message_reaction_id,
-                user_id: user.user_id,
-            };
+    let transaction_result = add_reaction_user(
+        &mut state.database,
+        message_identifier,
+        request.reaction_id,
+        new_reaction,
+        user.user_id
+    );
 
-            diesel::insert_into(reactionUsersTable)
-                .values(&reaction_user)
-                .execute(connection)?;
-            Ok((message_reaction_id, count))
-        })
+    let (message_reaction_id, emoticon, count, inserted) = match transaction_result {
+        Ok(result) => result,
+        Err(diesel::result::Error::NotFound) => return error(StatusCode::NOT_FOUND, "Reaction not found"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to add reaction")
     };
 
-    if transaction_result.is_err() {
-        transaction_result.unwrap();
-        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to add reaction");
+    if inserted {
+        send_packet_to_channel(&mut state, channel_id, user.user_id, || {
+            Box::new(ReactionAdded {
+                message_id: message_identifier,
+                user_id: user.user_id,
+                emoji: emoticon.clone(),
+                reaction_count: count,
+                reaction_id: message_reaction_id,
+                channel_id
+            })
+        }).await;
     }
-    let (message_reaction_id, count) = transaction_result.unwrap();
-
-    send_packet_to_channel(&mut state, channel_id.clone(), || {
-        Box::new(ReactionAdded {
-            message_id: message_identifier,
-            user_id: user.user_id,
-            emoji: emoticon.clone(),
-            reaction_count: count,
-            reaction_id: message_reaction_id,
-            channel_id
-        })
-    }).await;
 
     ok(ReactionAddResponse {
         reaction_id: message_reaction_id,
@@ -111,46 +97,274 @@ pub async fn add_reaction(
     })
 }
 
+// Errors: invalid_path, not_found, internal_error
 pub async fn remove_reaction(
     Path((channel_id, message_identifier, reaction_identifier)): Path<(i64, i64, i32)>,
     Extension(state): Extension<SharedState>,
     request: Request<Body>
 ) -> IrisResponse<()> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
+    let user = current_user(&request)?;
 
     let mut state = state.write().await;
-    let transaction_result = {
-        state.database.transaction::<_, diesel::result::Error, _>(|connection| {
-            // reduce one from reaction count
-            let count = diesel::update(reactionsTable)
+    if !message_in_channel(&mut state.database, channel_id, message_identifier) {
+        return error(StatusCode::NOT_FOUND, "Message not found");
+    }
+
+    let transaction_result = remove_reaction_user(&mut state.database, message_identifier, reaction_identifier, user.user_id);
+
+    let (emoticon, count, removed) = match transaction_result {
+        Ok(result) => result,
+        Err(diesel::result::Error::NotFound) => return error(StatusCode::NOT_FOUND, "Reaction not found"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove reaction")
+    };
+
+    if removed {
+        send_packet_to_channel(&mut state, channel_id, user.user_id, || {
+            Box::new(ReactionRemoved {
+                message_id: message_identifier,
+                user_id: user.user_id,
+                emoji: emoticon.clone(),
+                reaction_count: count,
+                reaction_id: reaction_identifier,
+                channel_id
+            })
+        }).await;
+    }
+
+    no_content()
+}
+
+// Adds the user to an existing reaction, or to a new one for the given emoji.
+// Returns the reaction id, its emoji, its count and whether the user was added.
+fn add_reaction_user(
+    connection: &mut PgConnection,
+    message_identifier: i64,
+    existing: Option<i32>,
+    new_reaction: Option<(String, Option<i64>)>,
+    reacting_user: i64
+) -> QueryResult<(i32, String, i32, bool)> {
+    connection.transaction(|connection| {
+        // Both branches lock the reaction row, so concurrent adds to the same
+        // reaction are serialized until this transaction commits.
+        let (message_reaction_id, emoticon) = match (existing, new_reaction) {
+            (Some(message_reaction_id), _) => reactionsTable
+                .filter(reaction_id.eq(message_reaction_id))
+                .filter(message_id.eq(message_identifier))
+                .select((reaction_id, emoji))
+                .for_update()
+                .get_result::<(i32, String)>(connection)?,
+            (None, Some((emoji_text, emoji_identifier))) => {
+                let new_reaction = ReactionInsert {
+                    message_id: message_identifier,
+                    emoji: emoji_text,
+                    reaction_count: 0,
+                    custom_emoji_id: emoji_identifier
+                };
+                diesel::insert_into(reactionsTable)
+                    .values(&new_reaction)
+                    .on_conflict((message_id, emoji))
+                    .do_update()
+                    .set(emoji.eq(excluded(emoji)))
+                    .returning((reaction_id, emoji))
+                    .get_result::<(i32, String)>(connection)?
+            }
+            (None, None) => return Err(diesel::result::Error::NotFound)
+        };
+
+        let reaction_user = ReactionUserInsert {
+            reaction_id: message_reaction_id,
+            user_id: reacting_user,
+        };
+        let inserted = diesel::insert_into(reactionUsersTable)
+            .values(&reaction_user)
+            .on_conflict((reactionUsersTableReactionId, user_id))
+            .do_nothing()
+            .execute(connection)? > 0;
+
+        // The count only moves when a reaction user row actually changed, which
+        // keeps it equal to the number of rows in reaction_users.
+        let count = if inserted {
+            diesel::update(reactionsTable)
+                .filter(reaction_id.eq(message_reaction_id))
+                .set(reaction_count.eq(reaction_count + 1))
+                .returning(reaction_count)
+                .get_result::<i32>(connection)?
+        } else {
+            reactionsTable
+                .filter(reaction_id.eq(message_reaction_id))
+                .select(reaction_count)
+                .get_result::<i32>(connection)?
+        };
+        Ok((message_reaction_id, emoticon, count, inserted))
+    })
+}
+
+// Returns the reaction's emoji, its count and whether the user was removed
+fn remove_reaction_user(
+    connection: &mut PgConnection,
+    message_identifier: i64,
+    reaction_identifier: i32,
+    reacting_user: i64
+) -> QueryResult<(String, i32, bool)> {
+    connection.transaction(|connection| {
+        let emoticon = reactionsTable
+            .filter(reaction_id.eq(reaction_identifier))
+            .filter(message_id.eq(message_identifier))
+            .select(emoji)
+            .for_update()
+            .get_result::<String>(connection)?;
+
+        let removed = diesel::delete(reactionUsersTable)
+            .filter(reactionUsersTableReactionId.eq(reaction_identifier))
+            .filter(user_id.eq(reacting_user))
+            .execute(connection)? > 0;
+
+        let count = if removed {
+            diesel::update(reactionsTable)
                 .filter(reaction_id.eq(reaction_identifier))
                 .set(reaction_count.eq(reaction_count - 1))
                 .returning(reaction_count)
-                .get_result::<i32>(connection)?;
-
-            diesel::delete(reactionUsersTable)
-                .filter(reactionUsersTableReactionId.eq(reaction_identifier))
-                .filter(user_id.eq(user.user_id))
-                .execute(connection)?;
-            Ok(count)
-        })
-    };
+                .get_result::<i32>(connection)?
+        } else {
+            reactionsTable
+                .filter(reaction_id.eq(reaction_identifier))
+                .select(reaction_count)
+                .get_result::<i32>(connection)?
+        };
+        Ok((emoticon, count, removed))
+    })
+}
+
+fn message_in_channel(connection: &mut diesel::PgConnection, channel_id: i64, message_identifier: i64) -> bool {
+    diesel::select(exists(
+        messagesTable
+            .filter(messageChannelId.eq(channel_id))
+            .filter(messageId.eq(message_identifier))
+    )).get_result::<bool>(connection).unwrap_or(false)
+}
+
+fn reaction_exists(connection: &mut diesel::PgConnection, message_identifier: i64, emoticon: &str) -> bool {
+    diesel::select(exists(
+        reactionsTable
+            .filter(message_id.eq(message_identifier))
+            .filter(emoji.eq(emoticon))
+            .filter(reaction_count.gt(0))
+    )).get_result::<bool>(connection).unwrap_or(false)
+}
 
-    if transaction_result.is_err() {
-        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to remove reaction");
+// Reacting through an existing reaction id doesn't need a usable emoji
+impl Validate for ReactionAddRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        match self.reaction_id {
+            None if EmojiReference::parse(&self.reaction_type).is_none() => vec![invalid_emoji()],
+            _ => Vec::new()
+        }
     }
-    let count = transaction_result.unwrap();
-
-    send_packet_to_channel(&mut state, channel_id.clone(), || {
-        Box::new(ReactionRemoved {
-            message_id: message_identifier,
-            user_id: user.user_id,
-            emoji: "".to_string(),
-            reaction_count: count,
-            reaction_id: reaction_identifier,
-            channel_id
-        })
-    }).await;
+}
 
-    no_content()
-}
\ No newline at end of file
+fn invalid_emoji() -> FieldError {
+    FieldError::new("reaction_type", "invalid_emoji", "Not a known emoji")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    use diesel::dsl::count_star;
+
+    use super::*;
+    use crate::database::testing::{connect, database_url, insert_dm, insert_message, insert_user};
+
+    const RACERS: usize = 16;
+
+    type Task<T> = Box<dyn FnOnce(&mut PgConnection) -> T + Send>;
+
+    // Starts every task at the same time, each on its own connection
+    fn race<T: Send + 'static>(url: &str, tasks: Vec<Task<T>>) -> Vec<T> {
+        let barrier = Arc::new(Barrier::new(tasks.len()));
+        let handles: Vec<_> = tasks.into_iter().map(|task| {
+            let (url, barrier) = (url.to_string(), barrier.clone());
+            thread::spawn(move || {
+                let mut connection = connect(&url);
+                barrier.wait();
+                task(&mut connection)
+            })
+        }).collect();
+        handles.into_iter().map(|handle| handle.join().expect("Racer panicked")).collect()
+    }
+
+    // The stored count next to the number of reaction users it should match
+    fn counts(connection: &mut PgConnection, reaction: i32) -> (i32, i64) {
+        let count = reactionsTable
+            .filter(reaction_id.eq(reaction))
+            .select(reaction_count)
+            .get_result::<i32>(connection)
+            .unwrap();
+        let rows = reactionUsersTable
+            .filter(reactionUsersTableReactionId.eq(reaction))
+            .select(count_star())
+            .get_result::<i64>(connection)
+            .unwrap();
+        (count, rows)
+    }
+
+    fn add(message: i64, emoticon: &str, user: i64) -> Task<QueryResult<(i32, String, i32, bool)>> {
+        let emoticon = emoticon.to_string();
+        Box::new(move |connection| add_reaction_user(connection, message, None, Some((emoticon, None)), user))
+    }
+
+    fn remove(message: i64, reaction: i32, user: i64) -> Task<QueryResult<(String, i32, bool)>> {
+        Box::new(move |connection| remove_reaction_user(connection, message, reaction, user))
+    }
+
+    #[test]
+    fn racing_adds_and_removes_by_one_user_count_once() {
+        let Some(url) = database_url() else { return; };
+        let connection = &mut connect(&url);
+        let (user, other) = (insert_user(connection), insert_user(connection));
+        let channel = insert_dm(connection, [user, other]);
+        let message = insert_message(connection, channel, other, "react to me");
+
+        let added = race(&url, (0..RACERS).map(|_| add(message, "👍", user)).collect());
+        let added: Vec<_> = added.into_iter().map(|result| result.expect("Add failed")).collect();
+        let reaction = added[0].0;
+        assert!(added.iter().all(|(id, _, _, _)| *id == reaction), "every add should land on one reaction");
+        assert_eq!(added.iter().filter(|(_, _, _, inserted)| *inserted).count(), 1);
+        assert_eq!(counts(connection, reaction), (1, 1));
+
+        let removed = race(&url, (0..RACERS).map(|_| remove(message, reaction, user)).collect());
+        let removed: Vec<_> = removed.into_iter().map(|result| result.expect("Remove failed")).collect();
+        assert_eq!(removed.iter().filter(|(_, _, removed)| *removed).count(), 1);
+        assert_eq!(counts(connection, reaction), (0, 0));
+    }
+
+    #[test]
+    fn racing_adds_and_removes_by_many_users_keep_the_count() {
+        let Some(url) = database_url() else { return; };
+        let connection = &mut connect(&url);
+        let reactors: Vec<i64> = (0..RACERS).map(|_| insert_user(connection)).collect();
+        let channel = insert_dm(connection, [reactors[0], reactors[1]]);
+        let message = insert_message(connection, channel, reactors[0], "react to me");
+
+        // Every user adds twice
+        let tasks = reactors.iter().chain(reactors.iter()).map(|user| add(message, "🎉", *user)).collect();
+        let added: Vec<_> = race(&url, tasks).into_iter().map(|result| result.expect("Add failed")).collect();
+        let reaction = added[0].0;
+        assert_eq!(added.iter().filter(|(_, _, _, inserted)| *inserted).count(), RACERS);
+        assert_eq!(counts(connection, reaction), (RACERS as i32, RACERS as i64));
+
+        // Half of them remove twice while the other half add again
+        let mut tasks: Vec<Task<bool>> = Vec::new();
+        for (index, user) in reactors.iter().chain(reactors.iter()).enumerate() {
+            let user = *user;
+            tasks.push(match (index % RACERS) % 2 {
+                0 => Box::new(move |connection| remove(message, reaction, user)(connection).expect("Remove failed").2),
+                _ => Box::new(move |connection| add(message, "🎉", user)(connection).expect("Add failed").3)
+            });
+        }
+        let changed = race(&url, tasks);
+        assert_eq!(changed.iter().filter(|changed| **changed).count(), RACERS / 2, "only the removals change anything");
+        assert_eq!(counts(connection, reaction), ((RACERS / 2) as i32, (RACERS / 2) as i64));
+    }
+}
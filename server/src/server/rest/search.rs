@@ -1,6 +1,6 @@
 use axum::body::Body;
 use axum::debug_handler;
-use axum::extract::{Extension, Query, Request};
+use axum::extract::{Extension, Request};
 use axum::http::StatusCode;
 use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper, TextExpressionMethods};
 use serde::Deserialize;
@@ -9,7 +9,9 @@ use crate::schema::users::users::dsl::users;
 use crate::schema::users::users::user_id as table_user_id;
 use crate::schema::users::users::name as table_users_name;
 use crate::schema::users::users::username as table_users_username;
-use crate::server::rest::{error, GeneralSearchResponse, IrisResponse, ok, StandardUser};
+use crate::server::rest::blocks::blocked_relations;
+use crate::server::rest::extract::Query;
+use crate::server::rest::{current_user, error, GeneralSearchResponse, IrisResponse, ok, StandardUser};
 use crate::SharedState;
 
 #[derive(Deserialize)]
@@ -17,20 +19,25 @@ pub struct Params {
     pub term: String
 }
 
+// Errors: invalid_query, bad_request
 #[debug_handler]
 pub async fn search(
     Extension(state): Extension<SharedState>,
     Query(params): Query<Params>,
     request: Request<Body>
 ) -> IrisResponse<GeneralSearchResponse> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
+    let user = current_user(&request)?;
     let term = params.term.trim();
     if term.is_empty() {
         return error(StatusCode::BAD_REQUEST, "Empty search term");
     }
 
     let state = &mut state.write().await;
+    let Ok(hidden) = blocked_relations(&mut state.database, user.user_id) else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to search users");
+    };
     let user_results = users
+        .filter(table_user_id.ne_all(hidden))
         .filter(
             table_user_id.ne(user.user_id)
                 .and(
@@ -39,12 +46,11 @@ pub async fn search(
         )
         .select(User::as_select())
         .load::<User>(&mut state.database);
-
-    if user_results.is_err() {
+    let Ok(user_results) = user_results else {
         return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to search users");
-    }
+    };
 
-    let user_objects = user_results.unwrap().iter().map(|user| StandardUser {
+    let user_objects = user_results.iter().map(|user| StandardUser {
         id: user.user_id,
         name: user.name.clone(),
         username: user.username.clone()
@@ -0,0 +1,145 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use axum::async_trait;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::header::USER_AGENT;
+use axum::http::request::Parts;
+use axum::http::{Request, StatusCode};
+use axum::Extension;
+use chrono::Utc;
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
+use serde::Serialize;
+
+use crate::schema::sessions::Session;
+use crate::schema::sessions::sessions::dsl::sessions;
+use crate::schema::sessions::sessions::{expires_at, last_used_at, session_id, user_id as session_user_id};
+use crate::server::gateway::context::disconnect_session;
+use crate::server::rest::middlewares::ClientIp;
+use crate::server::rest::extract::Path;
+use crate::server::rest::{current_user, error, IrisResponse, no_content, ok};
+use crate::SharedState;
+
+const MAX_HEADER_LENGTH: usize = 256;
+
+// Describes where a login comes from, recorded on the session it creates
+#[derive(Debug, Clone, Default)]
+pub struct DeviceInfo {
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for DeviceInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name: &str| parts.headers.get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().chars().take(MAX_HEADER_LENGTH).collect::<String>())
+            .filter(|value| !value.is_empty());
+
+        // Clients can name themselves, otherwise the name is guessed from the user agent
+        let user_agent = header(USER_AGENT.as_str());
+        let device_name = header("X-Device-Name").or_else(|| user_agent.as_deref().map(describe_user_agent));
+        let ip_address = parts.extensions.get::<ClientIp>().map(|ClientIp(address)| address.to_string())
+            .or_else(|| parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(address)| address.ip().to_string()));
+
+        Ok(DeviceInfo {
+            device_name,
+            user_agent,
+            ip_address
+        })
+    }
+}
+
+// Errors: unauthorized, internal_error
+pub async fn get_sessions(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<Vec<SessionResponse>> {
+    let user = current_user(&request)?;
+    let Some(current) = request.extensions().get::<Session>().map(|session| session.session_id) else {
+        return error(StatusCode::UNAUTHORIZED, "Unauthorized");
+    };
+    let state = &mut state.write().await;
+
+    let active = sessions
+        .filter(session_user_id.eq(user.user_id))
+        .filter(expires_at.gt(Utc::now().naive_utc()))
+        .order(last_used_at.desc())
+        .select(Session::as_select())
+        .load::<Session>(&mut state.database);
+    let Ok(active) = active else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get sessions");
+    };
+
+    ok(active.into_iter().map(|session| SessionResponse {
+        id: session.session_id,
+        current: session.session_id == current,
+        device_name: session.device_name,
+        user_agent: session.user_agent,
+        ip_address: session.ip_address,
+        created_at: session.created_at.and_utc().timestamp(),
+        last_used_at: session.last_used_at.and_utc().timestamp()
+    }).collect())
+}
+
+// Revoking a session ends its refresh token and access token, and closes its gateway connection
+// Errors: invalid_path, not_found, internal_error
+pub async fn delete_session(
+    Extension(state): Extension<SharedState>,
+    Path(target): Path<i64>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    let deleted = diesel::delete(
+        sessions
+            .filter(session_id.eq(target))
+            .filter(session_user_id.eq(user.user_id))
+    )
+        .execute(&mut state.database);
+    match deleted {
+        Ok(0) => error(StatusCode::NOT_FOUND, "Session not found"),
+        Ok(_) => {
+            disconnect_session(&state.packet_queue, user.user_id, target);
+            no_content()
+        }
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to revoke session")
+    }
+}
+
+// Good enough to tell devices apart, e.g. "Firefox on Linux"
+fn describe_user_agent(user_agent: &str) -> String {
+    let browser = [("Edg/", "Edge"), ("OPR/", "Opera"), ("Firefox/", "Firefox"), ("Chrome/", "Chrome"), ("Safari/", "Safari")]
+        .iter()
+        .find(|(marker, _)| user_agent.contains(marker))
+        .map(|(_, name)| *name);
+    let system = [("iPhone", "iOS"), ("iPad", "iPadOS"), ("Android", "Android"), ("Windows", "Windows"), ("Mac OS X", "macOS"), ("Linux", "Linux")]
+        .iter()
+        .find(|(marker, _)| user_agent.contains(marker))
+        .map(|(_, name)| *name);
+
+    match (browser, system) {
+        (Some(browser), Some(system)) => format!("{} on {}", browser, system),
+        (Some(name), None) | (None, Some(name)) => name.to_string(),
+        (None, None) => user_agent.split('/').next().unwrap_or(user_agent).to_string()
+    }
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub id: i64,
+    // Whether this is the session making the request
+    pub current: bool,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    // Unix timestamps in seconds
+    pub created_at: i64,
+    pub last_used_at: i64
+}
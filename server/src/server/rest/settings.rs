@@ -0,0 +1,95 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Extension;
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
+use serde::{Deserialize, Serialize};
+
+use crate::schema::settings::{DmPrivacy, UserSettings};
+use crate::schema::settings::user_settings::dsl::user_settings;
+use crate::schema::settings::user_settings::user_id as settings_user_id;
+use crate::server::rest::friends::are_friends;
+use crate::server::rest::{current_user, error, IrisResponse, ok, read_json};
+use crate::SharedState;
+
+// Errors: internal_error
+pub async fn get_settings(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<SettingsResponse> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    match load_settings(&mut state.database, user.user_id) {
+        Ok(settings) => ok(SettingsResponse::from(settings)),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get settings")
+    }
+}
+
+// Only the fields present are changed
+// Errors: invalid_body, internal_error
+pub async fn update_settings(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<SettingsResponse> {
+    let user = current_user(&request)?;
+    let request = read_json::<UpdateSettingsRequest>(request).await?;
+
+    let state = &mut state.write().await;
+    let Ok(mut settings) = load_settings(&mut state.database, user.user_id) else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update settings");
+    };
+    if let Some(dm_privacy) = request.dm_privacy {
+        settings.dm_privacy = dm_privacy.as_i16();
+    }
+
+    let saved = diesel::insert_into(user_settings)
+        .values(&settings)
+        .on_conflict(settings_user_id)
+        .do_update()
+        .set(&settings)
+        .execute(&mut state.database);
+    match saved {
+        Ok(_) => ok(SettingsResponse::from(settings)),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update settings")
+    }
+}
+
+// Users only get a row once they change something
+pub fn load_settings(connection: &mut PgConnection, user: i64) -> QueryResult<UserSettings> {
+    let settings = user_settings
+        .filter(settings_user_id.eq(user))
+        .select(UserSettings::as_select())
+        .first::<UserSettings>(connection)
+        .optional()?;
+    Ok(settings.unwrap_or(UserSettings {
+        user_id: user,
+        dm_privacy: DmPrivacy::default().as_i16()
+    }))
+}
+
+// Whether the recipient's privacy settings let the sender message them
+pub fn can_message(connection: &mut PgConnection, sender: i64, recipient: i64) -> QueryResult<bool> {
+    match DmPrivacy::from_i16(load_settings(connection, recipient)?.dm_privacy) {
+        DmPrivacy::Everyone => Ok(true),
+        DmPrivacy::Contacts => are_friends(connection, sender, recipient),
+        DmPrivacy::Nobody => Ok(false)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateSettingsRequest {
+    pub dm_privacy: Option<DmPrivacy>
+}
+
+#[derive(Serialize)]
+pub struct SettingsResponse {
+    pub dm_privacy: DmPrivacy
+}
+
+impl From<UserSettings> for SettingsResponse {
+    fn from(settings: UserSettings) -> Self {
+        SettingsResponse {
+            dm_privacy: DmPrivacy::from_i16(settings.dm_privacy)
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Extension;
+use chrono::{Duration, Utc};
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
+use diesel::upsert::excluded;
+use serde::{Deserialize, Serialize};
+
+use crate::schema::two_factor::{LoginChallenge, RecoveryCode, TwoFactor};
+use crate::schema::two_factor::login_challenges::dsl::login_challenges;
+use crate::schema::two_factor::login_challenges::{attempts, challenge_id, expires_at as challenge_expires_at};
+use crate::schema::two_factor::recovery_codes::dsl::recovery_codes;
+use crate::schema::two_factor::recovery_codes::{code_hash, user_id as recovery_user_id};
+use crate::schema::two_factor::two_factor::dsl::two_factor;
+use crate::schema::two_factor::two_factor::{confirmed, last_used_step, secret, user_id as two_factor_user_id};
+use crate::schema::users::User;
+use crate::schema::users::users::dsl::users;
+use crate::schema::users::users::{two_factor_enabled, user_id as table_user_id};
+use crate::server::rest::auth::start_session;
+use crate::server::rest::sessions::DeviceInfo;
+use crate::server::rest::extract::JsonBody;
+use crate::server::rest::{current_user, error, error_with_code, IrisResponse, no_content, ok, read_json, TwoFactorChallengeResponse, UserAuthResponse, UserSelfResponse};
+use crate::util::tokens::{constant_time_eq, format_token, generate_secret, hash_secret, parse_token};
+use crate::util::totp::{generate_recovery_codes, generate_totp_secret, normalize_recovery_code, otpauth_uri, verify_totp};
+use crate::{AppState, SharedState};
+
+// Wrong codes allowed per login challenge before the password has to be entered again
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+// Starts (or restarts) enrolment, 2FA only takes effect once a code is confirmed
+// Errors: two_factor_already_enabled, internal_error
+pub async fn enroll(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<TwoFactorEnrollmentResponse> {
+    let user = current_user(&request)?;
+    if user.two_factor_enabled {
+        return error_with_code(StatusCode::CONFLICT, "two_factor_already_enabled", "Two-factor authentication is already enabled");
+    }
+
+    let state = &mut state.write().await;
+    let new_secret = generate_totp_secret();
+    let Some(uri) = otpauth_uri(&new_secret, &user.username) else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create two-factor secret");
+    };
+    let pending = TwoFactor {
+        user_id: user.user_id,
+        secret: new_secret.clone(),
+        confirmed: false,
+        last_used_step: 0
+    };
+    let stored = diesel::insert_into(two_factor)
+        .values(&pending)
+        .on_conflict(two_factor_user_id)
+        .do_update()
+        .set((secret.eq(excluded(secret)), confirmed.eq(false), last_used_step.eq(0)))
+        .execute(&mut state.database);
+
+    match stored {
+        Ok(_) => ok(TwoFactorEnrollmentResponse {
+            secret: new_secret,
+            otpauth_uri: uri
+        }),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create two-factor secret")
+    }
+}
+
+// Errors: invalid_body, two_factor_already_enabled, two_factor_not_enrolled, invalid_two_factor_code, internal_error
+pub async fn confirm(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<RecoveryCodesResponse> {
+    let user = current_user(&request)?;
+    let request = read_json::<TwoFactorCodeRequest>(request).await?;
+    if user.two_factor_enabled {
+        return error_with_code(StatusCode::CONFLICT, "two_factor_already_enabled", "Two-factor authentication is already enabled");
+    }
+
+    let state = &mut state.write().await;
+    let pending = two_factor
+        .filter(two_factor_user_id.eq(user.user_id))
+        .select(TwoFactor::as_select())
+        .first::<TwoFactor>(&mut state.database);
+    let Ok(pending) = pending else {
+        return error_with_code(StatusCode::CONFLICT, "two_factor_not_enrolled", "Two-factor enrolment hasn't been started");
+    };
+    let Some(step) = verify_totp(&pending.secret, &request.code, pending.last_used_step) else {
+        return error_with_code(StatusCode::FORBIDDEN, "invalid_two_factor_code", "Invalid two-factor code");
+    };
+
+    let codes = generate_recovery_codes();
+    let rows = recovery_code_rows(state, user.user_id, &codes);
+    let enabled = state.database.transaction::<_, diesel::result::Error, _>(|connection| {
+        diesel::update(two_factor.filter(two_factor_user_id.eq(user.user_id)))
+            .set((confirmed.eq(true), last_used_step.eq(step)))
+            .execute(connection)?;
+        diesel::update(users.filter(table_user_id.eq(user.user_id)))
+            .set(two_factor_enabled.eq(true))
+            .execute(connection)?;
+        replace_recovery_codes(connection, user.user_id, &rows)
+    });
+
+    match enabled {
+        Ok(()) => ok(RecoveryCodesResponse { recovery_codes: codes }),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to enable two-factor authentication")
+    }
+}
+
+// Invalidates the previous recovery codes, which takes a code from the authenticator app
+// Errors: invalid_body, two_factor_not_enabled, invalid_two_factor_code, internal_error
+pub async fn regenerate_recovery_codes(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<RecoveryCodesResponse> {
+    let user = current_user(&request)?;
+    let request = read_json::<TwoFactorCodeRequest>(request).await?;
+    if !user.two_factor_enabled {
+        return error_with_code(StatusCode::CONFLICT, "two_factor_not_enabled", "Two-factor authentication isn't enabled");
+    }
+
+    let state = &mut state.write().await;
+    match check_totp(&mut state.database, user.user_id, &request.code) {
+        Ok(true) => (),
+        Ok(false) => return error_with_code(StatusCode::FORBIDDEN, "invalid_two_factor_code", "Invalid two-factor code"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to check two-factor code")
+    }
+
+    let codes = generate_recovery_codes();
+    let rows = recovery_code_rows(state, user.user_id, &codes);
+    let replaced = state.database.transaction::<_, diesel::result::Error, _>(|connection| {
+        replace_recovery_codes(connection, user.user_id, &rows)
+    });
+
+    match replaced {
+        Ok(()) => ok(RecoveryCodesResponse { recovery_codes: codes }),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create recovery codes")
+    }
+}
+
+// Errors: invalid_body, two_factor_not_enabled, two_factor_required, invalid_two_factor_code, internal_error
+pub async fn disable(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    let request = read_json::<TwoFactorCodeRequest>(request).await?;
+    if !user.two_factor_enabled {
+        return error_with_code(StatusCode::CONFLICT, "two_factor_not_enabled", "Two-factor authentication isn't enabled");
+    }
+
+    let state = &mut state.write().await;
+    if state.config.auth.require_two_factor {
+        return error_with_code(StatusCode::FORBIDDEN, "two_factor_required", "Two-factor authentication is required on this server");
+    }
+    match check_code(&mut state.database, user.user_id, &request.code) {
+        Ok(true) => (),
+        Ok(false) => return error_with_code(StatusCode::FORBIDDEN, "invalid_two_factor_code", "Invalid two-factor code"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to check two-factor code")
+    }
+
+    let disabled = state.database.transaction::<_, diesel::result::Error, _>(|connection| {
+        diesel::delete(two_factor.filter(two_factor_user_id.eq(user.user_id))).execute(connection)?;
+        diesel::delete(recovery_codes.filter(recovery_user_id.eq(user.user_id))).execute(connection)?;
+        diesel::update(users.filter(table_user_id.eq(user.user_id)))
+            .set(two_factor_enabled.eq(false))
+            .execute(connection)?;
+        Ok(())
+    });
+
+    match disabled {
+        Ok(()) => no_content(),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to disable two-factor authentication")
+    }
+}
+
+// Second step of the login, exchanging the challenge from `auth::login` and a code for tokens
+// Errors: invalid_body, invalid_two_factor_challenge, two_factor_challenge_expired, invalid_two_factor_code, internal_error
+pub async fn verify_login(
+    Extension(state): Extension<SharedState>,
+    device: DeviceInfo,
+    JsonBody(request): JsonBody<TwoFactorLoginRequest>,
+) -> IrisResponse<UserAuthResponse> {
+    let state = &mut state.write().await;
+    let Some((id, challenge_secret)) = parse_token(&request.challenge) else {
+        return error_with_code(StatusCode::UNAUTHORIZED, "invalid_two_factor_challenge", "Invalid two-factor challenge");
+    };
+
+    let challenge = login_challenges
+        .filter(challenge_id.eq(id))
+        .select(LoginChallenge::as_select())
+        .first::<LoginChallenge>(&mut state.database);
+    let challenge = match challenge {
+        Ok(challenge) if constant_time_eq(&challenge.token_hash, &hash_secret(challenge_secret)) => challenge,
+        _ => return error_with_code(StatusCode::UNAUTHORIZED, "invalid_two_factor_challenge", "Invalid two-factor challenge")
+    };
+    if challenge.expires_at <= Utc::now().naive_utc() {
+        let _ = diesel::delete(login_challenges.filter(challenge_id.eq(id))).execute(&mut state.database);
+        return error_with_code(StatusCode::UNAUTHORIZED, "two_factor_challenge_expired", "Two-factor challenge has expired");
+    }
+
+    match check_code(&mut state.database, challenge.user_id, &request.code) {
+        Ok(true) => (),
+        Ok(false) => {
+            // The challenge is dropped after too many guesses, so codes can't be brute forced
+            if challenge.attempts + 1 >= MAX_CHALLENGE_ATTEMPTS {
+                let _ = diesel::delete(login_challenges.filter(challenge_id.eq(id))).execute(&mut state.database);
+            } else {
+                let _ = diesel::update(login_challenges.filter(challenge_id.eq(id)))
+                    .set(attempts.eq(attempts + 1))
+                    .execute(&mut state.database);
+            }
+            return error_with_code(StatusCode::UNAUTHORIZED, "invalid_two_factor_code", "Invalid two-factor code");
+        }
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to check two-factor code")
+    }
+
+    let consumed = diesel::delete(login_challenges.filter(challenge_id.eq(id))).execute(&mut state.database);
+    if consumed != Ok(1) {
+        return error_with_code(StatusCode::UNAUTHORIZED, "invalid_two_factor_challenge", "Invalid two-factor challenge");
+    }
+    let user = users
+        .filter(table_user_id.eq(challenge.user_id))
+        .select(User::as_select())
+        .first::<User>(&mut state.database);
+    let Ok(user) = user else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session");
+    };
+
+    match start_session(state, user.user_id, &device) {
+        Ok(tokens) => ok(UserAuthResponse {
+            user: UserSelfResponse::from(user),
+            tokens
+        }),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session")
+    }
+}
+
+pub fn create_challenge(state: &mut AppState, user_identifier: i64) -> QueryResult<TwoFactorChallengeResponse> {
+    let id = { state.snowflake_issuer.generate().value() as i64 };
+    let challenge_secret = generate_secret();
+    let expires_in = state.config.auth.two_factor_challenge_ttl;
+    let now = Utc::now().naive_utc();
+
+    let challenge = LoginChallenge {
+        challenge_id: id,
+        user_id: user_identifier,
+        token_hash: hash_secret(&challenge_secret),
+        attempts: 0,
+        expires_at: now + Duration::seconds(expires_in)
+    };
+    // Abandoned challenges are cleaned up whenever a new one is made
+    diesel::delete(login_challenges.filter(challenge_expires_at.le(now))).execute(&mut state.database)?;
+    diesel::insert_into(login_challenges)
+        .values(&challenge)
+        .execute(&mut state.database)?;
+
+    Ok(TwoFactorChallengeResponse {
+        two_factor_required: true,
+        challenge: format_token(id, &challenge_secret),
+        expires_in
+    })
+}
+
+// Accepts either a code from the authenticator app or an unused recovery code
+fn check_code(connection: &mut PgConnection, user_identifier: i64, code: &str) -> QueryResult<bool> {
+    if check_totp(connection, user_identifier, code)? {
+        return Ok(true);
+    }
+
+    let hash = hash_secret(&normalize_recovery_code(code));
+    let used = diesel::delete(
+        recovery_codes
+            .filter(recovery_user_id.eq(user_identifier))
+            .filter(code_hash.eq(hash))
+    )
+        .execute(connection)?;
+    Ok(used == 1)
+}
+
+fn check_totp(connection: &mut PgConnection, user_identifier: i64, code: &str) -> QueryResult<bool> {
+    let enrolled = two_factor
+        .filter(two_factor_user_id.eq(user_identifier))
+        .filter(confirmed.eq(true))
+        .select(TwoFactor::as_select())
+        .first::<TwoFactor>(connection);
+    let Ok(enrolled) = enrolled else {
+        return Ok(false);
+    };
+    let Some(step) = verify_totp(&enrolled.secret, code, enrolled.last_used_step) else {
+        return Ok(false);
+    };
+
+    // Only one request can move the step forward, so a code is accepted at most once
+    let accepted = diesel::update(
+        two_factor
+            .filter(two_factor_user_id.eq(user_identifier))
+            .filter(last_used_step.lt(step))
+    )
+        .set(last_used_step.eq(step))
+        .execute(connection)?;
+    Ok(accepted == 1)
+}
+
+fn recovery_code_rows(state: &mut AppState, user_identifier: i64, codes: &[String]) -> Vec<RecoveryCode> {
+    codes.iter()
+        .map(|code| RecoveryCode {
+            code_id: state.snowflake_issuer.generate().value() as i64,
+            user_id: user_identifier,
+            code_hash: hash_secret(&normalize_recovery_code(code))
+        })
+        .collect()
+}
+
+fn replace_recovery_codes(connection: &mut PgConnection, user_identifier: i64, rows: &[RecoveryCode]) -> QueryResult<()> {
+    diesel::delete(recovery_codes.filter(recovery_user_id.eq(user_identifier))).execute(connection)?;
+    diesel::insert_into(recovery_codes)
+        .values(rows)
+        .execute(connection)?;
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TwoFactorCodeRequest {
+    pub code: String
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TwoFactorLoginRequest {
+    pub challenge: String,
+    pub code: String
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorEnrollmentResponse {
+    // Base32, for apps where the URI can't be scanned
+    pub secret: String,
+    pub otpauth_uri: String
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>
+}
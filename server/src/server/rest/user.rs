@@ -1,14 +1,312 @@
+use std::path::PathBuf;
+
 use axum::body::Body;
-use axum::http::Request;
+use axum::extract::{FromRequest, Multipart};
+use axum::http::{header, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::dsl::max;
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error::DatabaseError;
+use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
+use serde::Deserialize;
+use tokio::sync::RwLockWriteGuard;
 
-use crate::schema::users::User;
+use crate::schema::users::{ProfileChangeset, User, UsernameChange};
+use crate::schema::users::users::dsl::users;
+use crate::schema::users::users::{avatar_id as table_avatar_id, user_id as table_user_id, username as table_username};
+use crate::schema::users::username_history::dsl::username_history;
+use crate::schema::users::username_history::{changed_at, user_id as history_user_id};
+use crate::server::gateway::context::send_packet_to_related_users;
+use crate::server::gateway::messages::UserUpdated;
 use crate::server::rest;
-use crate::server::rest::{IrisResponse, UserSelfResponse};
+use crate::server::rest::auth::{identity_conflicts, username_taken};
+use crate::server::rest::extract::Path;
+use crate::server::rest::validation::{check, Validate};
+use crate::server::rest::{current_user, error, error_with_code, field_errors, FieldError, IrisError, IrisResponse, ok, read_valid_json, UserProfileResponse, UserSelfResponse};
+use crate::util::avatar::{resize_avatar, AvatarError};
+use crate::util::emoji::{format_custom_emoji, EmojiReference};
+use crate::util::identity::{validate_name, validate_username, MAX_BIO_LENGTH, MAX_PRONOUNS_LENGTH, MAX_STATUS_LENGTH};
+use crate::util::validation::validate_length;
+use crate::{AppState, SharedState};
 
+// Errors: unauthorized
 pub async fn get_self(
     request: Request<Body>
 ) -> IrisResponse<UserSelfResponse> {
-    let user = request.extensions().get::<User>().cloned().expect("User not found");
+    let user = current_user(&request)?;
 
     rest::ok(UserSelfResponse::from(user))
-}
\ No newline at end of file
+}
+
+// Errors: invalid_path, not_found, internal_error
+pub async fn get_user(
+    Extension(state): Extension<SharedState>,
+    Path(target): Path<i64>
+) -> IrisResponse<UserProfileResponse> {
+    let state = &mut state.write().await;
+
+    let user = users
+        .filter(table_user_id.eq(target))
+        .select(User::as_select())
+        .first::<User>(&mut state.database);
+    match user {
+        Ok(user) => ok(UserProfileResponse::from(user)),
+        Err(diesel::NotFound) => error(StatusCode::NOT_FOUND, "User not found"),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get user")
+    }
+}
+
+// Only the fields present are changed, an empty string clears an optional one
+// Errors: invalid_body, invalid_fields, internal_error
+pub async fn update_profile(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<UserSelfResponse> {
+    let user = current_user(&request)?;
+    let changeset = profile_changeset(read_valid_json::<UpdateProfileRequest>(request).await?);
+    let state = &mut state.write().await;
+    let updated = diesel::update(users.filter(table_user_id.eq(user.user_id)))
+        .set(&changeset)
+        .returning(User::as_returning())
+        .get_result::<User>(&mut state.database);
+    match updated {
+        Ok(updated) => ok(broadcast_profile(state, updated).await),
+        // Nothing to change
+        Err(diesel::result::Error::QueryBuilderError(_)) => ok(UserSelfResponse::from(user)),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to update profile")
+    }
+}
+
+// Usernames can only change once per cooldown, and the old one stays reserved for a while
+// Errors: invalid_body, invalid_fields, username_cooldown, fields_taken, internal_error
+pub async fn change_username(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<UserSelfResponse> {
+    let user = current_user(&request)?;
+    let new_username = read_valid_json::<ChangeUsernameRequest>(request).await?.username.trim().to_string();
+    if new_username == user.username {
+        return ok(UserSelfResponse::from(user));
+    }
+
+    let state = &mut state.write().await;
+    let now = Utc::now().naive_utc();
+    let last_change = username_history
+        .filter(history_user_id.eq(user.user_id))
+        .select(max(changed_at))
+        .first::<Option<NaiveDateTime>>(&mut state.database);
+    let Ok(last_change) = last_change else {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to change username");
+    };
+    let cooldown = Duration::seconds(state.config.usernames.change_cooldown);
+    if last_change.is_some_and(|last_change| last_change + cooldown > now) {
+        return error_with_code(StatusCode::TOO_MANY_REQUESTS, "username_cooldown", "You changed your username too recently");
+    }
+
+    match identity_conflicts(state, Some(&new_username), None, Some(user.user_id)) {
+        Ok(taken) if !taken.is_empty() => {
+            return field_errors(StatusCode::CONFLICT, "fields_taken", "Some fields are already in use", taken);
+        }
+        Ok(_) => {}
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to change username")
+    }
+
+    let change = UsernameChange {
+        change_id: state.snowflake_issuer.generate().value() as i64,
+        user_id: user.user_id,
+        username: user.username.clone(),
+        changed_at: now
+    };
+    let updated = state.database.transaction::<_, diesel::result::Error, _>(|connection| {
+        diesel::insert_into(username_history).values(&change).execute(connection)?;
+        diesel::update(users.filter(table_user_id.eq(user.user_id)))
+            .set(table_username.eq(&new_username))
+            .returning(User::as_returning())
+            .get_result::<User>(connection)
+    });
+    match updated {
+        Ok(updated) => ok(broadcast_profile(state, updated).await),
+        // Someone else took it in the meantime
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            field_errors(StatusCode::CONFLICT, "fields_taken", "Some fields are already in use", vec![username_taken()])
+        }
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to change username")
+    }
+}
+
+// Errors: bad_request, payload_too_large, unsupported_media_type, internal_error
+pub async fn upload_avatar(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<UserSelfResponse> {
+    let user = current_user(&request)?;
+    let max_avatar_size = { state.read().await.config.limits.max_avatar_size };
+    let Ok(mut multipart) = Multipart::from_request(request, &()).await else {
+        return error(StatusCode::BAD_REQUEST, "Expected a multipart body");
+    };
+
+    let mut image: Option<Vec<u8>> = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() == Some("image") {
+            image = field.bytes().await.ok().map(|bytes| bytes.to_vec());
+        }
+    }
+    let image = match image {
+        Some(image) if image.len() <= max_avatar_size => image,
+        Some(_) => return error(StatusCode::PAYLOAD_TOO_LARGE, &format!("Avatars must be at most {} bytes", max_avatar_size)),
+        None => return error(StatusCode::BAD_REQUEST, "Missing avatar image")
+    };
+
+    // Decoding and resampling are CPU bound, so they stay off the async workers
+    let resized = tokio::task::spawn_blocking(move || resize_avatar(&image)).await;
+    let resized = match resized {
+        Ok(Ok(resized)) => resized,
+        Ok(Err(AvatarError::Unsupported)) => return error(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Avatars must be PNG, GIF, JPEG or WebP"),
+        Ok(Err(AvatarError::Invalid)) => return error(StatusCode::BAD_REQUEST, "Avatar image couldn't be read"),
+        Err(_) => return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store avatar")
+    };
+
+    let state = &mut state.write().await;
+    let avatar = state.snowflake_issuer.generate().value() as i64;
+    let path = avatar_path(&state.config.server.uploads_dir, user.user_id, avatar);
+    if let Some(parent) = path.parent() {
+        if tokio::fs::create_dir_all(parent).await.is_err() {
+            return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store avatar");
+        }
+    }
+    if tokio::fs::write(&path, &resized).await.is_err() {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store avatar");
+    }
+
+    match set_avatar(state, &user, Some(avatar)).await {
+        Ok(response) => ok(response),
+        Err(_) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to store avatar")
+        }
+    }
+}
+
+// Errors: internal_error
+pub async fn delete_avatar(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<UserSelfResponse> {
+    let user = current_user(&request)?;
+    let state = &mut state.write().await;
+
+    match set_avatar(state, &user, None).await {
+        Ok(response) => ok(response),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete avatar")
+    }
+}
+
+// Avatars are public, and immutable since every upload gets a new id
+// Errors: invalid_path, not_found
+pub async fn get_avatar_image(
+    Path((target, avatar)): Path<(i64, i64)>,
+    Extension(state): Extension<SharedState>
+) -> Response {
+    let path = {
+        let state = state.read().await;
+        avatar_path(&state.config.server.uploads_dir, target, avatar)
+    };
+
+    match tokio::fs::read(path).await {
+        Ok(bytes) => (
+            [(header::CONTENT_TYPE, "image/png"), (header::CACHE_CONTROL, "public, max-age=31536000, immutable")],
+            bytes
+        ).into_response(),
+        Err(_) => IrisError::from_status(StatusCode::NOT_FOUND, "Avatar not found").into_response()
+    }
+}
+
+// Replaces the user's avatar and removes the previous file
+async fn set_avatar(
+    state: &mut RwLockWriteGuard<'_, AppState>,
+    user: &User,
+    avatar: Option<i64>
+) -> diesel::QueryResult<UserSelfResponse> {
+    let updated = diesel::update(users.filter(table_user_id.eq(user.user_id)))
+        .set(table_avatar_id.eq(avatar))
+        .returning(User::as_returning())
+        .get_result::<User>(&mut state.database)?;
+
+    if let Some(previous) = user.avatar_id.filter(|previous| Some(*previous) != avatar) {
+        let _ = tokio::fs::remove_file(avatar_path(&state.config.server.uploads_dir, user.user_id, previous)).await;
+    }
+    Ok(broadcast_profile(state, updated).await)
+}
+
+async fn broadcast_profile(state: &mut RwLockWriteGuard<'_, AppState>, user: User) -> UserSelfResponse {
+    let profile = UserProfileResponse::from(user.clone());
+    send_packet_to_related_users(state, user.user_id, || Box::new(UserUpdated {
+        user: profile.clone()
+    })).await;
+    UserSelfResponse::from(user)
+}
+
+// Fields left out stay as they are, empty ones are unset
+fn profile_changeset(request: UpdateProfileRequest) -> ProfileChangeset {
+    let optional = |value: Option<String>| {
+        let value = value.map(|value| value.trim().to_string())?;
+        Some(Some(value).filter(|value| !value.is_empty()))
+    };
+    let status_emoji = request.status_emoji.map(|emoji| match EmojiReference::parse(emoji.trim()) {
+        Some(EmojiReference::Unicode(emoji)) => Some(emoji),
+        Some(EmojiReference::Custom { name, id }) => Some(format_custom_emoji(&name, id)),
+        None => None
+    });
+    ProfileChangeset {
+        name: request.name.map(|name| name.trim().to_string()),
+        bio: optional(request.bio),
+        pronouns: optional(request.pronouns),
+        status_text: optional(request.status_text),
+        status_emoji
+    }
+}
+
+fn avatar_path(uploads_dir: &std::path::Path, user_identifier: i64, avatar: i64) -> PathBuf {
+    uploads_dir.join("avatars").join(user_identifier.to_string()).join(format!("{}.png", avatar))
+}
+
+#[derive(Deserialize)]
+pub struct ChangeUsernameRequest {
+    pub username: String
+}
+
+impl Validate for ChangeUsernameRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        check("username", validate_username(self.username.trim())).into_iter().collect()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateProfileRequest {
+    pub name: Option<String>,
+    pub bio: Option<String>,
+    pub pronouns: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>
+}
+
+impl Validate for UpdateProfileRequest {
+    fn validate(&self) -> Vec<FieldError> {
+        let optional = |field: &'static str, value: &Option<String>, max: usize| {
+            check(field, validate_length(value.as_deref()?.trim(), max))
+        };
+        let status_emoji = self.status_emoji.as_deref()
+            .map(str::trim)
+            .filter(|emoji| !emoji.is_empty() && EmojiReference::parse(emoji).is_none())
+            .map(|_| FieldError::new("status_emoji", "invalid_emoji", "Not a known emoji"));
+        [
+            self.name.as_deref().and_then(|name| check("name", validate_name(name.trim()))),
+            optional("bio", &self.bio, MAX_BIO_LENGTH),
+            optional("pronouns", &self.pronouns, MAX_PRONOUNS_LENGTH),
+            optional("status_text", &self.status_text, MAX_STATUS_LENGTH),
+            status_emoji
+        ].into_iter().flatten().collect()
+    }
+}
@@ -0,0 +1,92 @@
+use crate::server::rest::{FieldError, IrisError};
+use crate::util::validation::ValidationError;
+
+// Request bodies that check their own fields before a handler acts on them. Every rejected
+// field is reported at once, in a 422 `invalid_fields` error.
+pub trait Validate {
+    fn validate(&self) -> Vec<FieldError>;
+}
+
+pub fn validated<T: Validate>(value: T) -> Result<T, IrisError> {
+    let errors = value.validate();
+    if !errors.is_empty() {
+        return Err(IrisError::invalid_fields(errors));
+    }
+    Ok(value)
+}
+
+// The error for a field that failed one of the text checks
+pub fn check(field: &'static str, result: Result<(), ValidationError>) -> Option<FieldError> {
+    result.err().map(|err| FieldError::from_validation(field, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::server::rest::auth::RegisterRequest;
+    use crate::server::rest::contacts::NicknameRequest;
+    use crate::server::rest::messages::MessageCreationRequest;
+    use crate::server::rest::ReactionAddRequest;
+    use crate::util::identity::MAX_NICKNAME_LENGTH;
+    use crate::util::validation::MAX_MESSAGE_LENGTH;
+
+    fn fields<T: Validate>(value: T) -> Vec<(&'static str, &'static str)> {
+        value.validate().into_iter().map(|error| (error.field, error.code)).collect()
+    }
+
+    fn message(content: String) -> MessageCreationRequest {
+        MessageCreationRequest { content, reply_to: None }
+    }
+
+    fn reaction(reaction_id: Option<i32>, reaction_type: &str) -> ReactionAddRequest {
+        ReactionAddRequest { reaction_id, reaction_type: String::from(reaction_type) }
+    }
+
+    #[test]
+    fn every_rejected_field_is_reported_at_once() {
+        let request = RegisterRequest {
+            name: String::from(" "),
+            username: String::from("a"),
+            password: String::from("short"),
+            email: String::from("nope")
+        };
+        let error = validated(request).expect_err("Invalid request was accepted");
+        assert_eq!(error.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.code, "invalid_fields");
+        let rejected: Vec<_> = error.errors.iter().map(|error| (error.field, error.code)).collect();
+        assert_eq!(rejected, [("name", "blank"), ("username", "too_short"), ("email", "invalid_email"), ("password", "too_short")]);
+    }
+
+    #[test]
+    fn valid_requests_pass_through() {
+        let request = validated(message(String::from("hello"))).expect("Valid request was rejected");
+        assert_eq!(request.content, "hello");
+    }
+
+    #[test]
+    fn message_content_is_checked() {
+        assert_eq!(fields(message(String::from(" \n "))), [("content", "blank")]);
+        assert_eq!(fields(message("x".repeat(MAX_MESSAGE_LENGTH + 1))), [("content", "too_long")]);
+        assert!(fields(message("x".repeat(MAX_MESSAGE_LENGTH))).is_empty());
+    }
+
+    #[test]
+    fn reactions_need_a_known_emoji_unless_custom() {
+        assert!(fields(reaction(None, "👍")).is_empty());
+        assert!(fields(reaction(None, "<:party:123>")).is_empty());
+        assert_eq!(fields(reaction(None, "thumbs")), [("reaction_type", "invalid_emoji")]);
+        assert_eq!(fields(reaction(None, "")), [("reaction_type", "invalid_emoji")]);
+        // Reacting through an existing reaction reuses its emoji, so the type isn't looked at
+        assert!(fields(reaction(Some(1), "thumbs")).is_empty());
+    }
+
+    #[test]
+    fn nicknames_are_length_limited_after_trimming() {
+        let nickname = |value: String| NicknameRequest { nickname: Some(value) };
+        assert!(fields(NicknameRequest { nickname: None }).is_empty());
+        assert!(fields(nickname(format!("  {}  ", "x".repeat(MAX_NICKNAME_LENGTH)))).is_empty());
+        assert_eq!(fields(nickname("x".repeat(MAX_NICKNAME_LENGTH + 1))), [("nickname", "too_long")]);
+    }
+}
@@ -0,0 +1,128 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Extension;
+use chrono::{Duration, Utc};
+use diesel::{Connection, ExpressionMethods, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
+use serde::Deserialize;
+
+use crate::mail::{dispatch, Mail};
+use crate::schema::users::User;
+use crate::schema::users::users::dsl::users;
+use crate::schema::users::users::{email as table_email, email_verified, user_id as table_user_id};
+use crate::schema::verifications::EmailVerification;
+use crate::schema::verifications::email_verifications::dsl::email_verifications;
+use crate::schema::verifications::email_verifications::{user_id as verification_user_id, verification_id};
+use crate::server::rest::extract::JsonBody;
+use crate::server::rest::{current_user, error, error_with_code, IrisResponse, no_content};
+use crate::util::tokens::{constant_time_eq, format_token, generate_secret, hash_secret, parse_token};
+use crate::{AppState, SharedState};
+
+// Errors: invalid_body, invalid_verification_token, verification_token_expired, internal_error
+pub async fn verify_email(
+    Extension(state): Extension<SharedState>,
+    JsonBody(request): JsonBody<VerifyEmailRequest>,
+) -> IrisResponse<()> {
+    let state = &mut state.write().await;
+    let Some((id, secret)) = parse_token(&request.token) else {
+        return error_with_code(StatusCode::BAD_REQUEST, "invalid_verification_token", "Invalid verification token");
+    };
+
+    let verification = email_verifications
+        .filter(verification_id.eq(id))
+        .select(EmailVerification::as_select())
+        .first::<EmailVerification>(&mut state.database);
+    let verification = match verification {
+        Ok(verification) if constant_time_eq(&verification.token_hash, &hash_secret(secret)) => verification,
+        _ => return error_with_code(StatusCode::BAD_REQUEST, "invalid_verification_token", "Invalid verification token")
+    };
+    if verification.expires_at <= Utc::now().naive_utc() {
+        return error_with_code(StatusCode::BAD_REQUEST, "verification_token_expired", "Verification token has expired");
+    }
+
+    let verified = state.database.transaction::<_, diesel::result::Error, _>(|connection| {
+        // Only the address the token was sent to gets verified
+        let updated = diesel::update(
+            users
+                .filter(table_user_id.eq(verification.user_id))
+                .filter(table_email.eq(&verification.email))
+        )
+            .set(email_verified.eq(true))
+            .execute(connection)?;
+        diesel::delete(email_verifications.filter(verification_user_id.eq(verification.user_id)))
+            .execute(connection)?;
+        Ok(updated)
+    });
+
+    match verified {
+        Ok(0) => error_with_code(StatusCode::BAD_REQUEST, "invalid_verification_token", "Invalid verification token"),
+        Ok(_) => no_content(),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to verify email")
+    }
+}
+
+// Errors: email_already_verified, internal_error
+pub async fn resend_verification(
+    Extension(state): Extension<SharedState>,
+    request: Request<Body>
+) -> IrisResponse<()> {
+    let user = current_user(&request)?;
+    if user.email_verified {
+        return error_with_code(StatusCode::CONFLICT, "email_already_verified", "Email is already verified");
+    }
+
+    let state = &mut state.write().await;
+    match send_verification(state, &user) {
+        Ok(()) => no_content(),
+        Err(_) => error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to send verification email")
+    }
+}
+
+pub fn mark_verified(state: &mut AppState, user_identifier: i64) -> QueryResult<()> {
+    diesel::update(users.filter(table_user_id.eq(user_identifier)))
+        .set(email_verified.eq(true))
+        .execute(&mut state.database)
+        .map(|_| ())
+}
+
+pub fn send_verification(state: &mut AppState, user: &User) -> QueryResult<()> {
+    let mail = create_verification(state, user)?;
+    dispatch(state.mailer.clone(), mail);
+    Ok(())
+}
+
+// Replaces any outstanding verification token for the user, returning the mail carrying the new one
+pub fn create_verification(state: &mut AppState, user: &User) -> QueryResult<Mail> {
+    let id = { state.snowflake_issuer.generate().value() as i64 };
+    let secret = generate_secret();
+    let verification = EmailVerification {
+        verification_id: id,
+        user_id: user.user_id,
+        email: user.email.clone(),
+        token_hash: hash_secret(&secret),
+        expires_at: Utc::now().naive_utc() + Duration::seconds(state.config.auth.verification_token_ttl)
+    };
+
+    state.database.transaction::<_, diesel::result::Error, _>(|connection| {
+        diesel::delete(email_verifications.filter(verification_user_id.eq(user.user_id)))
+            .execute(connection)?;
+        diesel::insert_into(email_verifications)
+            .values(&verification)
+            .execute(connection)?;
+        Ok(())
+    })?;
+
+    let token = format_token(id, &secret);
+    Ok(Mail {
+        to: user.email.clone(),
+        subject: String::from("Verify your Iris email"),
+        body: format!(
+            "Hi {},\n\nConfirm your email address by opening the link below:\n\n{}/verify-email?token={}\n\nOr submit this token: {}\n",
+            user.name, state.config.server.frontend_url, token, token
+        )
+    })
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VerifyEmailRequest {
+    pub token: String
+}
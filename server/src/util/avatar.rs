@@ -0,0 +1,108 @@
+use std::io::Cursor;
+
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader, Limits};
+
+pub const AVATAR_SIZE: u32 = 256;
+// Checked from the header, before anything is decoded
+const MAX_DIMENSION: u32 = 4096;
+
+#[derive(Debug, PartialEq)]
+pub enum AvatarError {
+    Unsupported,
+    Invalid
+}
+
+// Crops to a centred square no larger than `AVATAR_SIZE` and re-encodes it as PNG,
+// which also drops whatever metadata the upload carried
+pub fn resize_avatar(bytes: &[u8]) -> Result<Vec<u8>, AvatarError> {
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|_| AvatarError::Invalid)?;
+    if !matches!(reader.format(), Some(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP)) {
+        return Err(AvatarError::Unsupported);
+    }
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+
+    let image = reader.decode().map_err(|_| AvatarError::Invalid)?;
+    let size = AVATAR_SIZE.min(image.width()).min(image.height());
+    let resized = image.resize_to_fill(size, size, FilterType::Lanczos3);
+
+    let mut output = Cursor::new(Vec::new());
+    resized.write_to(&mut output, ImageFormat::Png).map_err(|_| AvatarError::Invalid)?;
+    Ok(output.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+
+    use super::*;
+
+    const RED: Rgb<u8> = Rgb([255, 0, 0]);
+    const GREEN: Rgb<u8> = Rgb([0, 255, 0]);
+
+    fn encode(image: RgbImage, format: ImageFormat) -> Vec<u8> {
+        let mut output = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(image).write_to(&mut output, format).unwrap();
+        output.into_inner()
+    }
+
+    fn decode(bytes: &[u8]) -> DynamicImage {
+        image::load_from_memory_with_format(bytes, ImageFormat::Png).expect("Avatars should be PNG")
+    }
+
+    #[test]
+    fn crops_the_centre_to_a_square() {
+        // Green in the middle square, red on both sides of it
+        let image = RgbImage::from_fn(600, 300, |x, _| if (150..450).contains(&x) { GREEN } else { RED });
+        let avatar = decode(&resize_avatar(&encode(image, ImageFormat::Png)).unwrap());
+        assert_eq!(avatar.dimensions(), (AVATAR_SIZE, AVATAR_SIZE));
+        for (x, y) in [(0, 0), (AVATAR_SIZE - 1, 0), (AVATAR_SIZE / 2, AVATAR_SIZE / 2), (0, AVATAR_SIZE - 1)] {
+            let pixel = avatar.to_rgb8().get_pixel(x, y).0;
+            assert!(pixel[0] < 16 && pixel[1] > 240, "pixel ({}, {}) is {:?}", x, y, pixel);
+        }
+    }
+
+    #[test]
+    fn keeps_small_images_at_their_size() {
+        let image = RgbImage::from_pixel(100, 40, GREEN);
+        let avatar = decode(&resize_avatar(&encode(image, ImageFormat::Jpeg)).unwrap());
+        assert_eq!(avatar.dimensions(), (40, 40));
+    }
+
+    #[test]
+    fn accepts_every_supported_format() {
+        for format in [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Gif, ImageFormat::WebP] {
+            let image = RgbImage::from_pixel(300, 300, GREEN);
+            let avatar = decode(&resize_avatar(&encode(image, format)).unwrap());
+            assert_eq!(avatar.dimensions(), (AVATAR_SIZE, AVATAR_SIZE), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn rejects_other_formats_and_broken_images() {
+        // A BMP header is recognised, but BMP isn't accepted
+        assert_eq!(resize_avatar(b"BM\x3a\x00\x00\x00\x00\x00\x00\x00\x36\x00\x00\x00"), Err(AvatarError::Unsupported));
+        assert_eq!(resize_avatar(b"definitely not an image"), Err(AvatarError::Unsupported));
+        assert_eq!(resize_avatar(b""), Err(AvatarError::Unsupported));
+
+        let mut truncated = encode(RgbImage::from_pixel(64, 64, GREEN), ImageFormat::Png);
+        truncated.truncate(truncated.len() / 2);
+        assert_eq!(resize_avatar(&truncated), Err(AvatarError::Invalid));
+    }
+
+    #[test]
+    fn rejects_images_past_the_dimension_limit() {
+        let wide = encode(RgbImage::from_pixel(MAX_DIMENSION + 1, 1, GREEN), ImageFormat::Png);
+        assert_eq!(resize_avatar(&wide), Err(AvatarError::Invalid));
+        let tall = encode(RgbImage::from_pixel(1, MAX_DIMENSION + 1, GREEN), ImageFormat::Png);
+        assert_eq!(resize_avatar(&tall), Err(AvatarError::Invalid));
+
+        let widest = encode(RgbImage::from_pixel(MAX_DIMENSION, 1, GREEN), ImageFormat::Png);
+        assert_eq!(decode(&resize_avatar(&widest).unwrap()).dimensions(), (1, 1));
+    }
+}
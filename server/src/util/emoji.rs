@@ -0,0 +1,134 @@
+pub const MAX_EMOJI_NAME_LENGTH: usize = 32;
+pub const MIN_EMOJI_NAME_LENGTH: usize = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmojiReference {
+    Unicode(String),
+    Custom {
+        name: String,
+        id: i64
+    }
+}
+
+impl EmojiReference {
+    // Parses either a known unicode emoji or a `<:name:id>` custom emoji reference
+    pub fn parse(input: &str) -> Option<EmojiReference> {
+        let input = input.trim();
+        if let Some((name, id)) = parse_custom_reference(input) {
+            return Some(EmojiReference::Custom { name, id });
+        }
+        emojis::get(input).map(|_| EmojiReference::Unicode(input.to_string()))
+    }
+}
+
+pub fn format_custom_emoji(name: &str, id: i64) -> String {
+    format!("<:{}:{}>", name, id)
+}
+
+pub fn is_valid_emoji_name(name: &str) -> bool {
+    (MIN_EMOJI_NAME_LENGTH..=MAX_EMOJI_NAME_LENGTH).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Finds every `<:name:id>` reference inside a message's content
+pub fn find_custom_emojis(content: &str) -> Vec<(String, i64)> {
+    let mut found = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("<:") {
+        let candidate = &rest[start..];
+        match candidate.find('>') {
+            Some(end) => {
+                if let Some(reference) = parse_custom_reference(&candidate[..=end]) {
+                    found.push(reference);
+                }
+                rest = &candidate[2..];
+            }
+            None => break
+        }
+    }
+    found
+}
+
+fn parse_custom_reference(input: &str) -> Option<(String, i64)> {
+    let inner = input.strip_prefix("<:")?.strip_suffix('>')?;
+    let (name, id) = inner.split_once(':')?;
+    if !is_valid_emoji_name(name) {
+        return None;
+    }
+    // `parse` would also take a sign
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let id = id.parse::<i64>().ok()?;
+    Some((name.to_string(), id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_unicode_and_custom_references() {
+        assert_eq!(EmojiReference::parse("👍"), Some(EmojiReference::Unicode(String::from("👍"))));
+        assert_eq!(EmojiReference::parse(" 👍 "), Some(EmojiReference::Unicode(String::from("👍"))));
+        assert_eq!(
+            EmojiReference::parse("<:party_parrot:123>"),
+            Some(EmojiReference::Custom { name: String::from("party_parrot"), id: 123 })
+        );
+        assert_eq!(EmojiReference::parse("not an emoji"), None);
+        assert_eq!(EmojiReference::parse(""), None);
+    }
+
+    #[test]
+    fn rejects_malformed_custom_references() {
+        for input in [
+            "<:party:>",
+            "<::123>",
+            "<:a:123>",
+            "<:has space:123>",
+            "<:dash-ed:123>",
+            "<:this_name_is_far_too_long_for_an_emoji:123>",
+            "<:party:12a>",
+            "<:party:-123>",
+            "<:party:+123>",
+            "<:party:9223372036854775808>",
+            "<:party:123",
+            ":party:123>",
+            "<:party:1:2>"
+        ] {
+            assert_eq!(EmojiReference::parse(input), None, "{}", input);
+        }
+        assert_eq!(parse_custom_reference("<:party:9223372036854775807>"), Some((String::from("party"), i64::MAX)));
+    }
+
+    #[test]
+    fn finds_every_reference_in_content() {
+        assert_eq!(
+            find_custom_emojis("hi <:wave:1> and <:smile_2:22>!"),
+            vec![(String::from("wave"), 1), (String::from("smile_2"), 22)]
+        );
+        assert_eq!(find_custom_emojis("<:wave:1><:wave:1>"), vec![(String::from("wave"), 1), (String::from("wave"), 1)]);
+        assert!(find_custom_emojis("no emojis <here>").is_empty());
+    }
+
+    #[test]
+    fn finds_references_after_broken_ones() {
+        // A reference opened inside another one still counts on its own
+        assert_eq!(find_custom_emojis("<:a<:wave:1>"), vec![(String::from("wave"), 1)]);
+        assert_eq!(find_custom_emojis("<:<:wave:1>>"), vec![(String::from("wave"), 1)]);
+        assert_eq!(find_custom_emojis("<:bad name:1> <:wave:2>"), vec![(String::from("wave"), 2)]);
+        assert_eq!(find_custom_emojis("<:wave:99999999999999999999> <:wave:3>"), vec![(String::from("wave"), 3)]);
+        // Nothing after an unclosed one can be closed either
+        assert!(find_custom_emojis("<:wave:1").is_empty());
+        assert!(find_custom_emojis("<:wave:1 <:smile:2").is_empty());
+    }
+
+    #[test]
+    fn validates_emoji_names() {
+        assert!(is_valid_emoji_name("ok"));
+        assert!(is_valid_emoji_name(&"a".repeat(MAX_EMOJI_NAME_LENGTH)));
+        assert!(!is_valid_emoji_name("a"));
+        assert!(!is_valid_emoji_name(&"a".repeat(MAX_EMOJI_NAME_LENGTH + 1)));
+        assert!(!is_valid_emoji_name("é_é"));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::util::validation::{validate_length, ValidationError};
+
+pub const MIN_USERNAME_LENGTH: usize = 3;
+pub const MAX_USERNAME_LENGTH: usize = 32;
+pub const MAX_NAME_LENGTH: usize = 64;
+pub const MAX_EMAIL_LENGTH: usize = 254;
+pub const MAX_BIO_LENGTH: usize = 190;
+pub const MAX_PRONOUNS_LENGTH: usize = 40;
+pub const MAX_STATUS_LENGTH: usize = 128;
+pub const MAX_NICKNAME_LENGTH: usize = 64;
+pub const MAX_FOLDER_NAME_LENGTH: usize = 32;
+pub const MAX_PASSKEY_NAME_LENGTH: usize = 64;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+// Names that could pass for the service itself, or for mentions and routes
+const RESERVED_USERNAMES: [&str; 19] = [
+    "admin", "administrator", "root", "system", "iris", "support", "help", "moderator", "mod", "staff",
+    "official", "security", "everyone", "here", "null", "undefined", "api", "login", "signup"
+];
+
+// Usernames keep the case they were registered with, but are unique and matched regardless of it
+pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+    let length = username.chars().count();
+    if length < MIN_USERNAME_LENGTH {
+        return Err(ValidationError::TooShort(MIN_USERNAME_LENGTH));
+    }
+    if length > MAX_USERNAME_LENGTH {
+        return Err(ValidationError::TooLong(MAX_USERNAME_LENGTH));
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+        return Err(ValidationError::InvalidCharacters);
+    }
+    if username.starts_with('.') || username.ends_with('.') || username.contains("..") {
+        return Err(ValidationError::InvalidDots);
+    }
+    if RESERVED_USERNAMES.contains(&username.to_ascii_lowercase().as_str()) {
+        return Err(ValidationError::Reserved);
+    }
+    Ok(())
+}
+
+// Deliverability is proven by verification, this only rejects what clearly isn't an address
+pub fn validate_email(email: &str) -> Result<(), ValidationError> {
+    if email.len() > MAX_EMAIL_LENGTH {
+        return Err(ValidationError::TooLong(MAX_EMAIL_LENGTH));
+    }
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return Err(ValidationError::InvalidEmail);
+    };
+    let valid_domain = domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && domain.split('.').all(|label| !label.is_empty());
+    if local.is_empty() || !valid_domain || email.chars().any(char::is_whitespace) {
+        return Err(ValidationError::InvalidEmail);
+    }
+    Ok(())
+}
+
+pub fn validate_name(name: &str) -> Result<(), ValidationError> {
+    if name.trim().is_empty() {
+        return Err(ValidationError::Blank);
+    }
+    validate_length(name, MAX_NAME_LENGTH)
+}
+
+// Long passwords are fine, but hashing them isn't free, so they're capped too
+pub fn validate_password(password: &str) -> Result<(), ValidationError> {
+    let length = password.chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Err(ValidationError::TooShort(MIN_PASSWORD_LENGTH));
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Err(ValidationError::TooLong(MAX_PASSWORD_LENGTH));
+    }
+    Ok(())
+}
+
+// Usernames can't contain `@`, so identifiers with one are looked up as emails
+pub fn is_email(identifier: &str) -> bool {
+    identifier.contains('@')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usernames_within_the_rules_pass() {
+        for username in ["abc", "Some_User.2", "a.b.c", "x".repeat(MAX_USERNAME_LENGTH).as_str(), "Administrators"] {
+            assert_eq!(validate_username(username), Ok(()), "{}", username);
+        }
+    }
+
+    #[test]
+    fn usernames_are_length_limited_in_characters() {
+        assert_eq!(validate_username(""), Err(ValidationError::TooShort(MIN_USERNAME_LENGTH)));
+        assert_eq!(validate_username("ab"), Err(ValidationError::TooShort(MIN_USERNAME_LENGTH)));
+        assert_eq!(validate_username(&"x".repeat(MAX_USERNAME_LENGTH + 1)), Err(ValidationError::TooLong(MAX_USERNAME_LENGTH)));
+        // Counted in characters, so these are rejected for what they contain rather than for their byte length
+        assert_eq!(validate_username("éé"), Err(ValidationError::TooShort(MIN_USERNAME_LENGTH)));
+        assert_eq!(validate_username("ééé"), Err(ValidationError::InvalidCharacters));
+    }
+
+    #[test]
+    fn usernames_only_take_letters_digits_underscores_and_dots() {
+        for username in ["has space", "dash-ed", "at@sign", "emoji👍", "tab\tbed", "ünïcode"] {
+            assert_eq!(validate_username(username), Err(ValidationError::InvalidCharacters), "{}", username);
+        }
+    }
+
+    #[test]
+    fn usernames_use_dots_only_between_characters() {
+        for username in [".abc", "abc.", "a..b", "..."] {
+            assert_eq!(validate_username(username), Err(ValidationError::InvalidDots), "{}", username);
+        }
+    }
+
+    #[test]
+    fn reserved_usernames_are_rejected_whatever_the_case() {
+        for username in ["admin", "Admin", "ROOT", "Iris", "everyone", "signup"] {
+            assert_eq!(validate_username(username), Err(ValidationError::Reserved), "{}", username);
+        }
+    }
+
+    #[test]
+    fn emails_need_a_local_part_and_a_dotted_domain() {
+        for email in ["someone@example.com", "first.last+tag@mail.example.co.uk", "\"odd@local\"@example.com"] {
+            assert_eq!(validate_email(email), Ok(()), "{}", email);
+        }
+        for email in [
+            "", "plain", "@example.com", "someone@", "someone@localhost", "someone@.example.com",
+            "someone@example.com.", "someone@example..com", "some one@example.com", "someone@example.com "
+        ] {
+            assert_eq!(validate_email(email), Err(ValidationError::InvalidEmail), "{}", email);
+        }
+        let long = format!("{}@example.com", "x".repeat(MAX_EMAIL_LENGTH));
+        assert_eq!(validate_email(&long), Err(ValidationError::TooLong(MAX_EMAIL_LENGTH)));
+    }
+
+    #[test]
+    fn names_and_passwords_are_bounded() {
+        assert_eq!(validate_name("  "), Err(ValidationError::Blank));
+        assert_eq!(validate_name("Iris"), Ok(()));
+        assert_eq!(validate_name(&"x".repeat(MAX_NAME_LENGTH + 1)), Err(ValidationError::TooLong(MAX_NAME_LENGTH)));
+        assert_eq!(validate_password("short"), Err(ValidationError::TooShort(MIN_PASSWORD_LENGTH)));
+        assert_eq!(validate_password(&"x".repeat(MAX_PASSWORD_LENGTH)), Ok(()));
+        assert_eq!(validate_password(&"x".repeat(MAX_PASSWORD_LENGTH + 1)), Err(ValidationError::TooLong(MAX_PASSWORD_LENGTH)));
+    }
+}
@@ -1 +1,10 @@
-pub mod snowflake;
\ No newline at end of file
+pub mod snowflake;
+pub mod emoji;
+pub mod tokens;
+pub mod passwords;
+pub mod totp;
+pub mod webauthn;
+pub mod rate_limit;
+pub mod identity;
+pub mod validation;
+pub mod avatar;
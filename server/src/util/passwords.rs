@@ -0,0 +1,45 @@
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+
+#[derive(Debug, PartialEq)]
+pub enum PasswordCheck {
+    Invalid,
+    Valid,
+    // Correct, but hashed with parameters other than the configured ones
+    Outdated
+}
+
+// Every hash gets its own random salt, which is stored alongside it in the PHC string
+pub fn hash_password(argon: &Argon2, password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    argon.hash_password(password.as_bytes(), &salt).map(|hash| hash.to_string())
+}
+
+// Verification reads the algorithm, parameters and salt from the stored hash itself,
+// so hashes made with older settings (or the old global salt) keep verifying.
+pub fn verify_password(argon: &Argon2, legacy_salt: Option<&str>, stored_hash: &str, password: &str) -> PasswordCheck {
+    let Ok(hash) = PasswordHash::new(stored_hash) else {
+        return PasswordCheck::Invalid;
+    };
+    if argon.verify_password(password.as_bytes(), &hash).is_err() {
+        return PasswordCheck::Invalid;
+    }
+
+    let current = argon.params();
+    let shared_salt = legacy_salt.is_some_and(|legacy| hash.salt.is_some_and(|salt| salt.as_str() == legacy));
+    let up_to_date = !shared_salt
+        && hash.algorithm == Algorithm::Argon2id.ident()
+        && hash.version == Some(Version::V0x13.into())
+        && Params::try_from(&hash).is_ok_and(|params| {
+            params.m_cost() == current.m_cost()
+                && params.t_cost() == current.t_cost()
+                && params.p_cost() == current.p_cost()
+                && params.output_len() == current.output_len()
+        });
+    if up_to_date {
+        PasswordCheck::Valid
+    } else {
+        PasswordCheck::Outdated
+    }
+}
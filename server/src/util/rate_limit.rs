@@ -0,0 +1,291 @@
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+use crate::config::{RateLimit, RateLimitConfig, RouteRateLimit};
+
+// In-process token buckets and failure counters. Every method takes the current instant,
+// so the limiter doesn't depend on the wall clock.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: DashMap<String, Bucket>,
+    failures: DashMap<String, Failures>
+}
+
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant
+}
+
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Decision {
+    pub limit: u32,
+    pub remaining: u32,
+    // Until the bucket is full again
+    pub reset: Duration,
+    // Set when the request is rejected
+    pub retry_after: Option<Duration>
+}
+
+impl Bucket {
+    fn refill_rate(&self) -> f64 {
+        self.limit.capacity as f64 / self.limit.period as f64
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_rate()).min(self.limit.capacity as f64);
+        self.updated = now;
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter {
+            config,
+            buckets: DashMap::new(),
+            failures: DashMap::new()
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    pub fn route(&self, route: &str) -> Option<&RouteRateLimit> {
+        self.config.routes.get(route)
+    }
+
+    // Takes a token from the bucket under `key`, creating it full if it doesn't exist yet
+    pub fn check(&self, key: &str, limit: RateLimit, now: Instant) -> Decision {
+        let mut bucket = self.buckets.entry(key.to_string()).or_insert_with(|| Bucket {
+            limit,
+            tokens: limit.capacity as f64,
+            updated: now
+        });
+        // A changed limit for the same key starts over from the new capacity
+        if bucket.limit != limit {
+            *bucket = Bucket { limit, tokens: limit.capacity as f64, updated: now };
+        }
+        bucket.refill(now);
+
+        let retry_after = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - bucket.tokens) / bucket.refill_rate()))
+        };
+        Decision {
+            limit: limit.capacity,
+            remaining: bucket.tokens.floor() as u32,
+            reset: Duration::from_secs_f64((limit.capacity as f64 - bucket.tokens) / bucket.refill_rate()),
+            retry_after
+        }
+    }
+
+    pub fn locked_for(&self, key: &str, now: Instant) -> Option<Duration> {
+        let failures = self.failures.get(key)?;
+        failures.locked_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    // Every failure past the threshold doubles the lockout, until `max_duration`.
+    // Failures are forgotten once none happened for `max_duration`.
+    pub fn record_failure(&self, key: &str, now: Instant) {
+        let lockout = &self.config.lockout;
+        let max = Duration::from_secs(lockout.max_duration);
+        let mut failures = self.failures.entry(key.to_string()).or_insert_with(|| Failures {
+            count: 0,
+            last_failure: now,
+            locked_until: None
+        });
+        if now.saturating_duration_since(failures.last_failure) > max {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last_failure = now;
+
+        if failures.count >= lockout.threshold {
+            let doublings = (failures.count - lockout.threshold).min(31);
+            let duration = Duration::from_secs(lockout.base_duration.saturating_mul(1 << doublings)).min(max);
+            failures.locked_until = Some(now + duration);
+        }
+    }
+
+    pub fn record_success(&self, key: &str) {
+        self.failures.remove(key);
+    }
+
+    // Drops state that no longer affects any decision: full buckets and expired failure counts
+    pub fn prune(&self, now: Instant) {
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(now);
+            bucket.tokens < bucket.limit.capacity as f64
+        });
+        let max = Duration::from_secs(self.config.lockout.max_duration);
+        self.failures.retain(|_, failures| {
+            failures.locked_until.is_some_and(|until| until > now)
+                || now.saturating_duration_since(failures.last_failure) <= max
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LockoutConfig;
+
+    const LIMIT: RateLimit = RateLimit { capacity: 3, period: 30 };
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            lockout: LockoutConfig { threshold: 3, base_duration: 10, max_duration: 100 },
+            ..RateLimitConfig::default()
+        })
+    }
+
+    fn assert_secs(duration: Option<Duration>, expected: f64) {
+        let duration = duration.expect("Expected a duration").as_secs_f64();
+        assert!((duration - expected).abs() < 0.001, "{} != {}", duration, expected);
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_then_rejects() {
+        let limiter = limiter();
+        let start = Instant::now();
+        for remaining in [2, 1, 0] {
+            let decision = limiter.check("ip", LIMIT, start);
+            assert_eq!((decision.limit, decision.remaining, decision.retry_after), (3, remaining, None));
+        }
+
+        let rejected = limiter.check("ip", LIMIT, start);
+        assert_eq!(rejected.remaining, 0);
+        assert_secs(rejected.retry_after, 10.0);
+        assert_secs(Some(rejected.reset), 30.0);
+
+        // Other keys have their own bucket
+        assert_eq!(limiter.check("other", LIMIT, start).retry_after, None);
+    }
+
+    #[test]
+    fn bucket_refills_evenly_up_to_capacity() {
+        let limiter = limiter();
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.check("ip", LIMIT, start);
+        }
+
+        // A rejected request doesn't take anything, so the wait only shrinks
+        assert_secs(limiter.check("ip", LIMIT, start + Duration::from_secs(5)).retry_after, 5.0);
+        let allowed = limiter.check("ip", LIMIT, start + Duration::from_secs(10));
+        assert_eq!((allowed.remaining, allowed.retry_after), (0, None));
+        assert!(limiter.check("ip", LIMIT, start + Duration::from_secs(11)).retry_after.is_some());
+
+        let refilled = limiter.check("ip", LIMIT, start + Duration::from_secs(3600));
+        assert_eq!((refilled.remaining, refilled.retry_after), (2, None));
+        assert_secs(Some(refilled.reset), 10.0);
+    }
+
+    #[test]
+    fn changed_limit_starts_a_new_bucket() {
+        let limiter = limiter();
+        let start = Instant::now();
+        for _ in 0..4 {
+            limiter.check("ip", LIMIT, start);
+        }
+        let wider = RateLimit { capacity: 10, period: 30 };
+        let decision = limiter.check("ip", wider, start);
+        assert_eq!((decision.limit, decision.remaining, decision.retry_after), (10, 9, None));
+    }
+
+    #[test]
+    fn lockout_starts_at_threshold_and_doubles_up_to_max() {
+        let limiter = limiter();
+        let start = Instant::now();
+        limiter.record_failure("account", start);
+        limiter.record_failure("account", start);
+        assert_eq!(limiter.locked_for("account", start), None);
+
+        for expected in [10.0, 20.0, 40.0, 80.0, 100.0, 100.0] {
+            limiter.record_failure("account", start);
+            assert_secs(limiter.locked_for("account", start), expected);
+        }
+        assert_eq!(limiter.locked_for("other", start), None);
+    }
+
+    #[test]
+    fn lockout_expires() {
+        let limiter = limiter();
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.record_failure("account", start);
+        }
+        assert_secs(limiter.locked_for("account", start + Duration::from_secs(4)), 6.0);
+        assert_eq!(limiter.locked_for("account", start + Duration::from_secs(10)), None);
+        assert_eq!(limiter.locked_for("account", start + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn failures_are_forgotten_after_a_quiet_period() {
+        let limiter = limiter();
+        let start = Instant::now();
+        limiter.record_failure("account", start);
+        limiter.record_failure("account", start);
+
+        let later = start + Duration::from_secs(101);
+        limiter.record_failure("account", later);
+        assert_eq!(limiter.locked_for("account", later), None);
+    }
+
+    #[test]
+    fn success_resets_the_account() {
+        let limiter = limiter();
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.record_failure("account", start);
+        }
+        assert!(limiter.locked_for("account", start).is_some());
+
+        limiter.record_success("account");
+        assert_eq!(limiter.locked_for("account", start), None);
+
+        // The count starts over too, so the next failure is a first one again
+        limiter.record_failure("account", start);
+        limiter.record_failure("account", start);
+        assert_eq!(limiter.locked_for("account", start), None);
+    }
+
+    #[test]
+    fn prune_keeps_only_state_that_still_matters() {
+        let limiter = limiter();
+        let start = Instant::now();
+        limiter.check("full", LIMIT, start);
+        for _ in 0..3 {
+            limiter.check("drained", LIMIT, start);
+            limiter.record_failure("locked", start);
+        }
+        limiter.record_failure("counted", start + Duration::from_secs(50));
+        limiter.record_failure("stale", start);
+
+        let now = start + Duration::from_secs(25);
+        limiter.prune(now);
+        assert!(!limiter.buckets.contains_key("full"));
+        assert!(limiter.buckets.contains_key("drained"));
+        assert!(limiter.failures.contains_key("locked"));
+
+        let now = start + Duration::from_secs(120);
+        limiter.prune(now);
+        assert!(limiter.buckets.is_empty());
+        assert!(limiter.failures.contains_key("counted"));
+        assert!(!limiter.failures.contains_key("locked"));
+        assert!(!limiter.failures.contains_key("stale"));
+    }
+}
@@ -0,0 +1,31 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+// Opaque tokens are `<id>.<secret>`, so lookups go through the id and only the secret's hash is stored
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
+
+pub fn hash_secret(secret: &str) -> String {
+    to_hex(&Sha256::digest(secret.as_bytes()))
+}
+
+pub fn format_token(id: i64, secret: &str) -> String {
+    format!("{}.{}", id, secret)
+}
+
+pub fn parse_token(token: &str) -> Option<(i64, &str)> {
+    let (id, secret) = token.split_once('.')?;
+    Some((id.parse().ok()?, secret))
+}
+
+// Compares two hashes without short-circuiting on the first mismatching byte
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,91 @@
+use chrono::Utc;
+use rand::RngCore;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::util::tokens::constant_time_eq;
+
+const ISSUER: &str = "Iris";
+const STEP: u64 = 30;
+const DIGITS: usize = 6;
+// How many steps either side of the current one are accepted, to allow for clock drift
+const SKEW: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+// 80 random bits, which base32 encodes to 16 characters without padding
+const RECOVERY_CODE_BYTES: usize = 10;
+const RECOVERY_CODE_GROUP: usize = 4;
+
+pub fn generate_totp_secret() -> String {
+    let mut bytes = vec![0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Secret::Raw(bytes).to_encoded().to_string()
+}
+
+pub fn otpauth_uri(secret: &str, account: &str) -> Option<String> {
+    totp(secret, account).map(|totp| totp.get_url())
+}
+
+// Returns the time step the code belongs to, which has to be later than the last accepted one
+pub fn verify_totp(secret: &str, code: &str, last_used_step: i64) -> Option<i64> {
+    let totp = totp(secret, "")?;
+    let code = code.trim();
+    let current = Utc::now().timestamp() / STEP as i64;
+
+    (current - SKEW..=current + SKEW)
+        .filter(|step| *step > last_used_step)
+        .find(|step| constant_time_eq(&totp.generate(*step as u64 * STEP), code))
+}
+
+// Recovery codes are shown once as `xxxx-xxxx-xxxx-xxxx` and only their hashes are kept
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = vec![0u8; RECOVERY_CODE_BYTES];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let encoded = Secret::Raw(bytes).to_encoded().to_string().to_lowercase();
+            encoded.as_bytes()
+                .chunks(RECOVERY_CODE_GROUP)
+                .map(|group| std::str::from_utf8(group).unwrap())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars().filter(|c| *c != '-' && !c.is_whitespace()).collect::<String>().to_lowercase()
+}
+
+fn totp(secret: &str, account: &str) -> Option<TOTP> {
+    let bytes = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+    Some(TOTP::new_unchecked(Algorithm::SHA1, DIGITS, SKEW as u8, STEP, bytes, Some(ISSUER.to_string()), account.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn recovery_codes_carry_80_bits() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in &codes {
+            let groups: Vec<_> = code.split('-').collect();
+            assert_eq!(groups.len(), 4, "{}", code);
+            assert!(groups.iter().all(|group| group.len() == RECOVERY_CODE_GROUP), "{}", code);
+
+            let normalized = normalize_recovery_code(code);
+            assert_eq!(normalized.len(), 16);
+            assert!(normalized.chars().all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c)), "{}", code);
+        }
+        assert_eq!(codes.iter().collect::<HashSet<_>>().len(), codes.len());
+    }
+
+    #[test]
+    fn recovery_codes_normalize_as_typed() {
+        let code = "abcd-efgh-ijkl-mnop";
+        assert_eq!(normalize_recovery_code(code), "abcdefghijklmnop");
+        assert_eq!(normalize_recovery_code(" ABCD EFGH-ijkl-MNOP\n"), "abcdefghijklmnop");
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt::{Display, Formatter};
+
+pub const MAX_MESSAGE_LENGTH: usize = 4000;
+
+// Why a piece of user-provided text was rejected, shared by every field a request can carry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationError {
+    Blank,
+    TooShort(usize),
+    TooLong(usize),
+    InvalidCharacters,
+    InvalidDots,
+    Reserved,
+    InvalidEmail
+}
+
+impl ValidationError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::Blank => "blank",
+            ValidationError::TooShort(_) => "too_short",
+            ValidationError::TooLong(_) => "too_long",
+            ValidationError::InvalidCharacters => "invalid_characters",
+            ValidationError::InvalidDots => "invalid_dots",
+            ValidationError::Reserved => "reserved",
+            ValidationError::InvalidEmail => "invalid_email"
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::Blank => write!(f, "Can't be blank"),
+            ValidationError::TooShort(min) => write!(f, "Must be at least {} characters", min),
+            ValidationError::TooLong(max) => write!(f, "Must be at most {} characters", max),
+            ValidationError::InvalidCharacters => write!(f, "Only letters, digits, `_` and `.` are allowed"),
+            ValidationError::InvalidDots => write!(f, "Can't start or end with `.` or contain `..`"),
+            ValidationError::Reserved => write!(f, "This username is reserved"),
+            ValidationError::InvalidEmail => write!(f, "Not a valid email address")
+        }
+    }
+}
+
+// Messages keep their whitespace, they just can't be made of it alone
+pub fn validate_content(content: &str) -> Result<(), ValidationError> {
+    if content.trim().is_empty() {
+        return Err(ValidationError::Blank);
+    }
+    validate_length(content, MAX_MESSAGE_LENGTH)
+}
+
+// For free-form profile text, where empty means unset
+pub fn validate_length(value: &str, max: usize) -> Result<(), ValidationError> {
+    if value.chars().count() > max {
+        return Err(ValidationError::TooLong(max));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_keeps_its_whitespace_but_cant_be_only_whitespace() {
+        assert_eq!(validate_content("  hi  "), Ok(()));
+        assert_eq!(validate_content(""), Err(ValidationError::Blank));
+        assert_eq!(validate_content(" \n\t "), Err(ValidationError::Blank));
+    }
+
+    #[test]
+    fn content_is_length_limited_in_characters() {
+        assert_eq!(validate_content(&"é".repeat(MAX_MESSAGE_LENGTH)), Ok(()));
+        assert_eq!(validate_content(&"x".repeat(MAX_MESSAGE_LENGTH + 1)), Err(ValidationError::TooLong(MAX_MESSAGE_LENGTH)));
+    }
+
+    #[test]
+    fn lengths_allow_empty_values() {
+        assert_eq!(validate_length("", 4), Ok(()));
+        assert_eq!(validate_length("four", 4), Ok(()));
+        assert_eq!(validate_length("fives", 4), Err(ValidationError::TooLong(4)));
+    }
+
+    #[test]
+    fn every_error_has_its_own_code() {
+        let errors = [
+            ValidationError::Blank,
+            ValidationError::TooShort(1),
+            ValidationError::TooLong(1),
+            ValidationError::InvalidCharacters,
+            ValidationError::InvalidDots,
+            ValidationError::Reserved,
+            ValidationError::InvalidEmail
+        ];
+        let codes: std::collections::HashSet<_> = errors.iter().map(ValidationError::code).collect();
+        assert_eq!(codes.len(), errors.len());
+    }
+}
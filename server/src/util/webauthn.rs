@@ -0,0 +1,538 @@
+use std::fmt::{Display, Formatter};
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ciborium::Value;
+use rand::RngCore;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// COSE algorithm identifiers we accept, in order of preference
+pub const ES256: i64 = -7;
+pub const EDDSA: i64 = -8;
+pub const RS256: i64 = -257;
+pub const SUPPORTED_ALGORITHMS: [i64; 3] = [ES256, EDDSA, RS256];
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Debug, PartialEq)]
+pub enum WebauthnError {
+    Malformed(&'static str),
+    CeremonyMismatch,
+    ChallengeMismatch,
+    OriginMismatch,
+    RpIdMismatch,
+    UserNotVerified,
+    UnsupportedKey,
+    InvalidSignature
+}
+
+impl Display for WebauthnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebauthnError::Malformed(what) => write!(f, "Malformed {}", what),
+            WebauthnError::CeremonyMismatch => write!(f, "Response is for another ceremony"),
+            WebauthnError::ChallengeMismatch => write!(f, "Challenge doesn't match"),
+            WebauthnError::OriginMismatch => write!(f, "Origin isn't allowed"),
+            WebauthnError::RpIdMismatch => write!(f, "Credential is for another relying party"),
+            WebauthnError::UserNotVerified => write!(f, "User presence and verification are required"),
+            WebauthnError::UnsupportedKey => write!(f, "Unsupported public key"),
+            WebauthnError::InvalidSignature => write!(f, "Invalid signature")
+        }
+    }
+}
+
+// What a ceremony's response is checked against
+pub struct RelyingParty<'a> {
+    pub rp_id: &'a str,
+    pub origins: &'a [&'a str],
+    pub challenge: &'a str
+}
+
+pub struct RegisteredCredential {
+    pub credential_id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+    #[serde(rename = "crossOrigin", default)]
+    cross_origin: bool
+}
+
+struct AuthenticatorData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    attested: Option<(Vec<u8>, Vec<u8>)>
+}
+
+enum CoseKey {
+    Es256 { x: Vec<u8>, y: Vec<u8> },
+    EdDsa { x: Vec<u8> },
+    Rs256 { n: Vec<u8>, e: Vec<u8> }
+}
+
+pub fn generate_challenge() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    encode(&bytes)
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// Browsers send unpadded base64url, but some libraries pad it
+pub fn decode(value: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')).ok()
+}
+
+// Attestation statements aren't checked since only `none` attestation is requested
+pub fn verify_registration(
+    rp: &RelyingParty,
+    client_data_json: &[u8],
+    attestation_object: &[u8]
+) -> Result<RegisteredCredential, WebauthnError> {
+    verify_client_data(rp, client_data_json, "webauthn.create")?;
+
+    let attestation: Value = ciborium::from_reader(attestation_object)
+        .map_err(|_| WebauthnError::Malformed("attestation object"))?;
+    let auth_data = map_entry(&attestation, &Value::Text(String::from("authData")))
+        .and_then(Value::as_bytes)
+        .ok_or(WebauthnError::Malformed("attestation object"))?;
+
+    let data = parse_authenticator_data(auth_data)?;
+    verify_authenticator_data(rp, &data)?;
+    let (credential_id, public_key) = data.attested.ok_or(WebauthnError::Malformed("authenticator data"))?;
+    parse_cose_key(&public_key)?;
+
+    Ok(RegisteredCredential {
+        credential_id,
+        public_key,
+        sign_count: data.sign_count
+    })
+}
+
+// Returns the authenticator's new signature counter
+pub fn verify_assertion(
+    rp: &RelyingParty,
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+    public_key: &[u8]
+) -> Result<u32, WebauthnError> {
+    verify_client_data(rp, client_data_json, "webauthn.get")?;
+
+    let data = parse_authenticator_data(authenticator_data)?;
+    verify_authenticator_data(rp, &data)?;
+
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(&Sha256::digest(client_data_json));
+    verify_signature(&parse_cose_key(public_key)?, &signed, signature)?;
+    Ok(data.sign_count)
+}
+
+fn verify_client_data(rp: &RelyingParty, client_data_json: &[u8], ceremony: &str) -> Result<(), WebauthnError> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|_| WebauthnError::Malformed("client data"))?;
+
+    if client_data.ceremony != ceremony {
+        return Err(WebauthnError::CeremonyMismatch);
+    }
+    if client_data.challenge.trim_end_matches('=') != rp.challenge {
+        return Err(WebauthnError::ChallengeMismatch);
+    }
+    // Checking the origin is what makes passkeys phishing resistant
+    if client_data.cross_origin || !rp.origins.contains(&client_data.origin.as_str()) {
+        return Err(WebauthnError::OriginMismatch);
+    }
+    Ok(())
+}
+
+fn verify_authenticator_data(rp: &RelyingParty, data: &AuthenticatorData) -> Result<(), WebauthnError> {
+    if data.rp_id_hash != Sha256::digest(rp.rp_id.as_bytes()).as_slice() {
+        return Err(WebauthnError::RpIdMismatch);
+    }
+    // Passkeys replace the password, so the authenticator has to have verified the user itself
+    if data.flags & FLAG_USER_PRESENT == 0 || data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(WebauthnError::UserNotVerified);
+    }
+    Ok(())
+}
+
+// Layout: rpIdHash (32) | flags (1) | signCount (4) | [aaguid (16) | idLength (2) | id | COSE key] | [extensions]
+fn parse_authenticator_data(bytes: &[u8]) -> Result<AuthenticatorData, WebauthnError> {
+    let malformed = WebauthnError::Malformed("authenticator data");
+    if bytes.len() < 37 {
+        return Err(malformed);
+    }
+    let flags = bytes[32];
+    let sign_count = u32::from_be_bytes([bytes[33], bytes[34], bytes[35], bytes[36]]);
+
+    let mut attested = None;
+    if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        if bytes.len() < 55 {
+            return Err(malformed);
+        }
+        let id_length = u16::from_be_bytes([bytes[53], bytes[54]]) as usize;
+        let rest = &bytes[55..];
+        let credential_id = rest.get(..id_length).ok_or(WebauthnError::Malformed("authenticator data"))?;
+
+        // The key is followed by optional extensions, so its length is only known after decoding it
+        let mut key_bytes = &rest[id_length..];
+        let before = key_bytes.len();
+        let _: Value = ciborium::from_reader(&mut key_bytes).map_err(|_| WebauthnError::Malformed("public key"))?;
+        let key_length = before - key_bytes.len();
+        attested = Some((credential_id.to_vec(), rest[id_length..id_length + key_length].to_vec()));
+    }
+
+    Ok(AuthenticatorData {
+        rp_id_hash: bytes[..32].to_vec(),
+        flags,
+        sign_count,
+        attested
+    })
+}
+
+fn parse_cose_key(bytes: &[u8]) -> Result<CoseKey, WebauthnError> {
+    let key: Value = ciborium::from_reader(bytes).map_err(|_| WebauthnError::Malformed("public key"))?;
+    let integer = |label: i64| map_entry(&key, &Value::Integer(label.into()))
+        .and_then(Value::as_integer)
+        .and_then(|value| i64::try_from(value).ok());
+    let bytes = |label: i64| map_entry(&key, &Value::Integer(label.into()))
+        .and_then(Value::as_bytes)
+        .cloned();
+
+    // kty 2 is EC2 with crv 1 (P-256), kty 1 is OKP with crv 6 (Ed25519), kty 3 is RSA
+    match (integer(1), integer(3)) {
+        (Some(2), Some(ES256)) if integer(-1) == Some(1) => Ok(CoseKey::Es256 {
+            x: bytes(-2).ok_or(WebauthnError::UnsupportedKey)?,
+            y: bytes(-3).ok_or(WebauthnError::UnsupportedKey)?
+        }),
+        (Some(1), Some(EDDSA)) if integer(-1) == Some(6) => Ok(CoseKey::EdDsa {
+            x: bytes(-2).ok_or(WebauthnError::UnsupportedKey)?
+        }),
+        (Some(3), Some(RS256)) => Ok(CoseKey::Rs256 {
+            n: bytes(-1).ok_or(WebauthnError::UnsupportedKey)?,
+            e: bytes(-2).ok_or(WebauthnError::UnsupportedKey)?
+        }),
+        _ => Err(WebauthnError::UnsupportedKey)
+    }
+}
+
+fn verify_signature(key: &CoseKey, message: &[u8], signature: &[u8]) -> Result<(), WebauthnError> {
+    let verified = match key {
+        CoseKey::Es256 { x, y } => {
+            let mut point = vec![0x04];
+            point.extend_from_slice(x);
+            point.extend_from_slice(y);
+            UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, point).verify(message, signature)
+        }
+        CoseKey::EdDsa { x } => UnparsedPublicKey::new(&signature::ED25519, x).verify(message, signature),
+        CoseKey::Rs256 { n, e } => RsaPublicKeyComponents { n, e }
+            .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, signature)
+    };
+    verified.map_err(|_| WebauthnError::InvalidSignature)
+}
+
+fn map_entry<'a>(map: &'a Value, key: &Value) -> Option<&'a Value> {
+    map.as_map()?.iter().find(|(entry, _)| entry == key).map(|(_, value)| value)
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, RSA_PKCS1_SHA256};
+    use serde_json::json;
+
+    use super::*;
+
+    const RP_ID: &str = "iris.example";
+    const ORIGINS: [&str; 1] = ["https://iris.example"];
+    const PRESENT_AND_VERIFIED: u8 = FLAG_USER_PRESENT | FLAG_USER_VERIFIED;
+
+    // A 2048 bit PKCS#8 key, since ring can sign with RSA keys but not generate them
+    const RSA_KEY: &[u8] = include_bytes!("testdata/rsa2048.pk8");
+
+    enum SigningKey {
+        Es256(EcdsaKeyPair),
+        EdDsa(Ed25519KeyPair),
+        Rs256(RsaKeyPair)
+    }
+
+    // A software authenticator holding a single credential
+    struct Authenticator {
+        key: SigningKey,
+        credential_id: Vec<u8>,
+        rng: SystemRandom
+    }
+
+    impl Authenticator {
+        fn new(algorithm: i64) -> Self {
+            let rng = SystemRandom::new();
+            let key = match algorithm {
+                ES256 => {
+                    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+                    SigningKey::Es256(EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng).unwrap())
+                }
+                EDDSA => {
+                    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+                    SigningKey::EdDsa(Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap())
+                }
+                RS256 => SigningKey::Rs256(RsaKeyPair::from_pkcs8(RSA_KEY).unwrap()),
+                _ => panic!("Unsupported algorithm {}", algorithm)
+            };
+            Authenticator {
+                key,
+                credential_id: format!("credential-{}", algorithm).into_bytes(),
+                rng
+            }
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let int = |value: i64| Value::Integer(value.into());
+            let entries = match &self.key {
+                SigningKey::Es256(key) => {
+                    let point = key.public_key().as_ref();
+                    vec![
+                        (int(1), int(2)),
+                        (int(3), int(ES256)),
+                        (int(-1), int(1)),
+                        (int(-2), Value::Bytes(point[1..33].to_vec())),
+                        (int(-3), Value::Bytes(point[33..].to_vec()))
+                    ]
+                }
+                SigningKey::EdDsa(key) => vec![
+                    (int(1), int(1)),
+                    (int(3), int(EDDSA)),
+                    (int(-1), int(6)),
+                    (int(-2), Value::Bytes(key.public_key().as_ref().to_vec()))
+                ],
+                SigningKey::Rs256(key) => {
+                    let (n, e) = rsa_components(key.public_key().as_ref());
+                    vec![
+                        (int(1), int(3)),
+                        (int(3), int(RS256)),
+                        (int(-1), Value::Bytes(n)),
+                        (int(-2), Value::Bytes(e))
+                    ]
+                }
+            };
+            cbor(&Value::Map(entries))
+        }
+
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            match &self.key {
+                SigningKey::Es256(key) => key.sign(&self.rng, message).unwrap().as_ref().to_vec(),
+                SigningKey::EdDsa(key) => key.sign(message).as_ref().to_vec(),
+                SigningKey::Rs256(key) => {
+                    let mut signature = vec![0u8; key.public().modulus_len()];
+                    key.sign(&RSA_PKCS1_SHA256, &self.rng, message, &mut signature).unwrap();
+                    signature
+                }
+            }
+        }
+
+        fn attestation_object(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+            let mut attested = vec![0u8; 16];
+            attested.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            attested.extend_from_slice(&self.credential_id);
+            attested.extend_from_slice(&self.cose_key());
+            let auth_data = authenticator_data(rp_id, flags | FLAG_ATTESTED_CREDENTIAL, 0, &attested);
+            cbor(&Value::Map(vec![
+                (Value::Text(String::from("fmt")), Value::Text(String::from("none"))),
+                (Value::Text(String::from("attStmt")), Value::Map(Vec::new())),
+                (Value::Text(String::from("authData")), Value::Bytes(auth_data))
+            ]))
+        }
+
+        // Returns the authenticator data and its signature over it and the client data
+        fn assert(&self, rp_id: &str, flags: u8, counter: u32, client_data_json: &[u8]) -> (Vec<u8>, Vec<u8>) {
+            let auth_data = authenticator_data(rp_id, flags, counter, &[]);
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&Sha256::digest(client_data_json));
+            let signature = self.sign(&signed);
+            (auth_data, signature)
+        }
+    }
+
+    fn cbor(value: &Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn authenticator_data(rp_id: &str, flags: u8, counter: u32, attested: &[u8]) -> Vec<u8> {
+        let mut bytes = Sha256::digest(rp_id.as_bytes()).to_vec();
+        bytes.push(flags);
+        bytes.extend_from_slice(&counter.to_be_bytes());
+        bytes.extend_from_slice(attested);
+        bytes
+    }
+
+    fn client_data(ceremony: &str, challenge: &str, origin: &str, cross_origin: bool) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "type": ceremony,
+            "challenge": challenge,
+            "origin": origin,
+            "crossOrigin": cross_origin
+        })).unwrap()
+    }
+
+    // ring exposes RSA public keys as DER `SEQUENCE { INTEGER n, INTEGER e }`
+    fn rsa_components(der: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        fn read(bytes: &[u8]) -> (&[u8], &[u8]) {
+            let (length, header) = match bytes[1] {
+                short if short < 0x80 => (short as usize, 2),
+                long => {
+                    let size = (long & 0x7f) as usize;
+                    (bytes[2..2 + size].iter().fold(0, |length, byte| length << 8 | *byte as usize), 2 + size)
+                }
+            };
+            (&bytes[header..header + length], &bytes[header + length..])
+        }
+        let (sequence, _) = read(der);
+        let (n, rest) = read(sequence);
+        let (e, _) = read(rest);
+        let unsigned = |integer: &[u8]| integer.iter().skip_while(|byte| **byte == 0).copied().collect();
+        (unsigned(n), unsigned(e))
+    }
+
+    fn register(authenticator: &Authenticator, challenge: &str) -> RegisteredCredential {
+        let rp = RelyingParty { rp_id: RP_ID, origins: &ORIGINS, challenge };
+        let client_data_json = client_data("webauthn.create", challenge, ORIGINS[0], false);
+        verify_registration(&rp, &client_data_json, &authenticator.attestation_object(RP_ID, PRESENT_AND_VERIFIED))
+            .expect("Registration failed")
+    }
+
+    #[test]
+    fn registers_and_asserts_each_algorithm() {
+        for algorithm in SUPPORTED_ALGORITHMS {
+            let authenticator = Authenticator::new(algorithm);
+            let credential = register(&authenticator, &generate_challenge());
+            assert_eq!(credential.credential_id, authenticator.credential_id);
+            assert_eq!(credential.public_key, authenticator.cose_key());
+            assert_eq!(credential.sign_count, 0);
+
+            let challenge = generate_challenge();
+            let rp = RelyingParty { rp_id: RP_ID, origins: &ORIGINS, challenge: &challenge };
+            let client_data_json = client_data("webauthn.get", &challenge, ORIGINS[0], false);
+            let (auth_data, signature) = authenticator.assert(RP_ID, PRESENT_AND_VERIFIED, 7, &client_data_json);
+            let verified = verify_assertion(&rp, &client_data_json, &auth_data, &signature, &credential.public_key);
+            assert_eq!(verified, Ok(7), "algorithm {}", algorithm);
+
+            let mut tampered = signature.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            let verified = verify_assertion(&rp, &client_data_json, &auth_data, &tampered, &credential.public_key);
+            assert_eq!(verified, Err(WebauthnError::InvalidSignature), "algorithm {}", algorithm);
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_client_data() {
+        let authenticator = Authenticator::new(ES256);
+        let credential = register(&authenticator, &generate_challenge());
+        let challenge = generate_challenge();
+        let rp = RelyingParty { rp_id: RP_ID, origins: &ORIGINS, challenge: &challenge };
+
+        let cases = [
+            (client_data("webauthn.get", &generate_challenge(), ORIGINS[0], false), WebauthnError::ChallengeMismatch),
+            (client_data("webauthn.get", &challenge, "https://iris.example.evil", false), WebauthnError::OriginMismatch),
+            (client_data("webauthn.get", &challenge, ORIGINS[0], true), WebauthnError::OriginMismatch),
+            (client_data("webauthn.create", &challenge, ORIGINS[0], false), WebauthnError::CeremonyMismatch),
+            (b"{\"type\":".to_vec(), WebauthnError::Malformed("client data"))
+        ];
+        for (client_data_json, expected) in cases {
+            let (auth_data, signature) = authenticator.assert(RP_ID, PRESENT_AND_VERIFIED, 1, &client_data_json);
+            let verified = verify_assertion(&rp, &client_data_json, &auth_data, &signature, &credential.public_key);
+            assert_eq!(verified, Err(expected));
+        }
+    }
+
+    #[test]
+    fn rejects_registration_for_another_origin() {
+        let authenticator = Authenticator::new(EDDSA);
+        let challenge = generate_challenge();
+        let rp = RelyingParty { rp_id: RP_ID, origins: &ORIGINS, challenge: &challenge };
+        let attestation = authenticator.attestation_object(RP_ID, PRESENT_AND_VERIFIED);
+
+        let client_data_json = client_data("webauthn.create", &challenge, "https://evil.example", false);
+        assert!(matches!(verify_registration(&rp, &client_data_json, &attestation), Err(WebauthnError::OriginMismatch)));
+        let client_data_json = client_data("webauthn.create", &challenge, ORIGINS[0], true);
+        assert!(matches!(verify_registration(&rp, &client_data_json, &attestation), Err(WebauthnError::OriginMismatch)));
+    }
+
+    #[test]
+    fn rejects_wrong_rp_id_hash() {
+        let authenticator = Authenticator::new(ES256);
+        let challenge = generate_challenge();
+        let rp = RelyingParty { rp_id: RP_ID, origins: &ORIGINS, challenge: &challenge };
+
+        let client_data_json = client_data("webauthn.create", &challenge, ORIGINS[0], false);
+        let attestation = authenticator.attestation_object("evil.example", PRESENT_AND_VERIFIED);
+        assert!(matches!(verify_registration(&rp, &client_data_json, &attestation), Err(WebauthnError::RpIdMismatch)));
+
+        let credential = register(&authenticator, &generate_challenge());
+        let client_data_json = client_data("webauthn.get", &challenge, ORIGINS[0], false);
+        let (auth_data, signature) = authenticator.assert("evil.example", PRESENT_AND_VERIFIED, 1, &client_data_json);
+        let verified = verify_assertion(&rp, &client_data_json, &auth_data, &signature, &credential.public_key);
+        assert_eq!(verified, Err(WebauthnError::RpIdMismatch));
+    }
+
+    #[test]
+    fn requires_user_presence_and_verification() {
+        let authenticator = Authenticator::new(RS256);
+        let credential = register(&authenticator, &generate_challenge());
+        let challenge = generate_challenge();
+        let rp = RelyingParty { rp_id: RP_ID, origins: &ORIGINS, challenge: &challenge };
+
+        for flags in [0, FLAG_USER_PRESENT, FLAG_USER_VERIFIED] {
+            let client_data_json = client_data("webauthn.create", &challenge, ORIGINS[0], false);
+            let attestation = authenticator.attestation_object(RP_ID, flags);
+            assert!(matches!(verify_registration(&rp, &client_data_json, &attestation), Err(WebauthnError::UserNotVerified)));
+
+            let client_data_json = client_data("webauthn.get", &challenge, ORIGINS[0], false);
+            let (auth_data, signature) = authenticator.assert(RP_ID, flags, 1, &client_data_json);
+            let verified = verify_assertion(&rp, &client_data_json, &auth_data, &signature, &credential.public_key);
+            assert_eq!(verified, Err(WebauthnError::UserNotVerified), "flags {:#04x}", flags);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_authenticator_data() {
+        let authenticator = Authenticator::new(ES256);
+        let credential = register(&authenticator, &generate_challenge());
+        let challenge = generate_challenge();
+        let rp = RelyingParty { rp_id: RP_ID, origins: &ORIGINS, challenge: &challenge };
+
+        let client_data_json = client_data("webauthn.get", &challenge, ORIGINS[0], false);
+        let (auth_data, signature) = authenticator.assert(RP_ID, PRESENT_AND_VERIFIED, 1, &client_data_json);
+        for length in [0, 32, 36] {
+            let verified = verify_assertion(&rp, &client_data_json, &auth_data[..length], &signature, &credential.public_key);
+            assert_eq!(verified, Err(WebauthnError::Malformed("authenticator data")), "length {}", length);
+        }
+
+        // Attested credential data cut off in the header, the credential id and the public key
+        let attestation: Value = ciborium::from_reader(authenticator.attestation_object(RP_ID, PRESENT_AND_VERIFIED).as_slice()).unwrap();
+        let auth_data = map_entry(&attestation, &Value::Text(String::from("authData"))).and_then(Value::as_bytes).unwrap().clone();
+        let client_data_json = client_data("webauthn.create", &challenge, ORIGINS[0], false);
+        let id_end = 55 + authenticator.credential_id.len();
+        for length in [37, 54, id_end - 1, id_end + 1, auth_data.len() - 1] {
+            let truncated = cbor(&Value::Map(vec![
+                (Value::Text(String::from("fmt")), Value::Text(String::from("none"))),
+                (Value::Text(String::from("authData")), Value::Bytes(auth_data[..length].to_vec()))
+            ]));
+            let registered = verify_registration(&rp, &client_data_json, &truncated);
+            assert!(matches!(registered, Err(WebauthnError::Malformed(_))), "length {}", length);
+        }
+    }
+}